@@ -1,9 +1,10 @@
 use std::{collections::HashMap, str::FromStr};
 
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use chrono;
 
-use super::{error, stock::{self, Holding}, transactions::{self, Transaction, TransactionType}};
+use super::{error, lots::ClosedLot, money, stock::{self, Holding}, transactions::{self, Transaction, TransactionType}};
 
 /// The type of account
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -26,22 +27,60 @@ impl PartialEq for AccountType{
 /// An account is a simple account that can be deposited to and withdrawn from
 pub trait Account {
     fn get_id(&self) -> u32;
-    fn get_balance(&self) -> f64;
+    fn get_balance(&self) -> Decimal;
     fn get_nickname(&self) -> Option<String>;
-    fn deposit(&mut self, amount: f64) -> f64;
-    fn withdraw(&mut self, amount: f64) -> Result<f64, error::BankError>;
+    /// Deposits money, recording the transaction at the given date rather than now, such as for backtests and imports
+    fn deposit_at(&mut self, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<Decimal, error::BankError>;
+    /// Withdraws money, recording the transaction at the given date rather than now
+    fn withdraw_at(&mut self, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<Decimal, error::BankError>;
+
+    fn deposit(&mut self, amount: Decimal) -> Result<Decimal, error::BankError>{
+        self.deposit_at(amount, chrono::Utc::now())
+    }
+
+    fn withdraw(&mut self, amount: Decimal) -> Result<Decimal, error::BankError>{
+        self.withdraw_at(amount, chrono::Utc::now())
+    }
+    /// Whether the account is frozen, in which case money cannot move in or out of it
+    fn is_frozen(&self) -> bool;
+    fn set_frozen(&mut self, frozen: bool);
     fn get_account_type(&self) -> AccountType;
     fn get_created_at(&self) -> chrono::DateTime<chrono::Utc>;
     fn add_transaction(&mut self, transaction: Transaction);
+    fn get_transactions(&self) -> &Vec<Transaction>;
+    fn get_transactions_mut(&mut self) -> &mut Vec<Transaction>;
+
+    /// Adds a tag to the transaction at the given position in the log
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::TransactionNotFound` if there is no transaction at the index
+    fn tag_transaction(&mut self, index: usize, tag: &str) -> Result<(), error::BankError>{
+        self.get_transactions_mut()
+            .get_mut(index)
+            .ok_or(error::BankError::TransactionNotFound)?
+            .add_tag(tag);
+        Ok(())
+    }
+
+    /// Gets every transaction with the given tag, in the order they occurred
+    fn transactions_with_tag(&self, tag: &str) -> Vec<&Transaction>{
+        self.get_transactions()
+            .iter()
+            .filter(|transaction| transaction.has_tag(tag))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CheckingAccount{
     id: u32,
-    balance: f64,
+    balance: Decimal,
     nickname: Option<String>,
     created_at: chrono::DateTime<chrono::Utc>,
-    transactions: Vec<Transaction>
+    transactions: Vec<Transaction>,
+    #[serde(default)]
+    frozen: bool,
 }
 
 impl Account for CheckingAccount{
@@ -49,7 +88,7 @@ impl Account for CheckingAccount{
         self.id
     }
 
-    fn get_balance(&self) -> f64{
+    fn get_balance(&self) -> Decimal{
         self.balance
     }
 
@@ -57,18 +96,24 @@ impl Account for CheckingAccount{
         self.nickname.clone()
     }
 
-    fn deposit(&mut self, amount: f64) -> f64{
+    fn deposit_at(&mut self, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<Decimal, error::BankError>{
+        if self.frozen{
+            return Err(error::BankError::AccountFrozen);
+        }
         self.balance += amount;
         self.transactions.push(Transaction::new(
             transactions::TransactionType::Deposit,
             amount,
-            chrono::Utc::now(),
+            date,
             None,
         ));
-        self.balance
+        Ok(self.balance)
     }
 
-    fn withdraw(&mut self, amount: f64) -> Result<f64, error::BankError>{
+    fn withdraw_at(&mut self, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<Decimal, error::BankError>{
+        if self.frozen{
+            return Err(error::BankError::AccountFrozen);
+        }
         if self.balance < amount{
             return Err(error::BankError::InsufficientFunds);
         }
@@ -76,7 +121,7 @@ impl Account for CheckingAccount{
         self.transactions.push(Transaction::new(
             transactions::TransactionType::Withdraw,
             amount,
-            chrono::Utc::now(),
+            date,
             None,
         ));
         Ok(self.balance)
@@ -86,6 +131,14 @@ impl Account for CheckingAccount{
         self.created_at
     }
 
+    fn is_frozen(&self) -> bool{
+        self.frozen
+    }
+
+    fn set_frozen(&mut self, frozen: bool){
+        self.frozen = frozen;
+    }
+
     fn get_account_type(&self) -> AccountType{
         AccountType::Checking
     }
@@ -93,6 +146,14 @@ impl Account for CheckingAccount{
     fn add_transaction(&mut self, transaction: Transaction){
         self.transactions.push(transaction);
     }
+
+    fn get_transactions(&self) -> &Vec<Transaction>{
+        &self.transactions
+    }
+
+    fn get_transactions_mut(&mut self) -> &mut Vec<Transaction>{
+        &mut self.transactions
+    }
 }
 
 impl CheckingAccount{
@@ -104,13 +165,14 @@ impl CheckingAccount{
     /// * `id` - The id of the account
     /// * `balance` - The balance of the account
     /// * `nickname` - An optional nickname for the account
-    pub fn new(id: u32, balance: f64, nickname: Option<String>) -> Self{
+    pub fn new(id: u32, balance: Decimal, nickname: Option<String>) -> Self{
         CheckingAccount{
             id: id,
             balance: balance,
             nickname: nickname,
             created_at: chrono::Utc::now(),
             transactions: Vec::<Transaction>::new(),
+            frozen: false,
         }
     }
 
@@ -120,7 +182,8 @@ impl CheckingAccount{
             balance: account.get_balance(),
             nickname: account.get_nickname(),
             created_at: account.get_created_at(),
-            transactions: Vec::<Transaction>::new(),
+            transactions: account.get_transactions().clone(),
+            frozen: account.is_frozen(),
         }
     }
 }
@@ -142,16 +205,71 @@ impl ToString for CheckingAccount{
     }
 }
 
+/// What a sale of more shares than are held does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PartialSellPolicy{
+    /// The sale is rejected
+    #[default]
+    Strict,
+    /// The whole holding is sold instead
+    SellAvailable,
+}
+
+/// How the gains and income of an investment account are taxed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TaxTreatment{
+    #[default]
+    Taxable,
+    /// Taxed only when withdrawn, such as a traditional IRA or 401(k)
+    TaxDeferred,
+    /// Never taxed, such as a Roth IRA
+    TaxFree,
+}
+
+impl TaxTreatment{
+    /// Whether gains and income are taxed as they are realized
+    pub fn is_taxable(&self) -> bool{
+        *self == TaxTreatment::Taxable
+    }
+}
+
 /// An investment account is a checking account that can also be invested in stocks, bonds, etc.
 /// Holds stocks, bonds, etc.
+///
+/// Every field is saved with the bank. Fields added later take `#[serde(default)]` so that older saves still load,
+/// and values derived from the saved fields, such as caches, take `#[serde(skip)]` so that they are rebuilt rather than saved
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct InvestmentAccount{
     id: u32,
-    balance: f64,
+    balance: Decimal,
     nickname: Option<String>,
     created_at: chrono::DateTime<chrono::Utc>,
+    /// The open holdings, by symbol. Saved, as the cost basis of each is kept as it goes rather than replayed
     pub assets: HashMap<String, Holding>,
+    /// Every transaction, oldest first. Saved, and the record the statements and reports are worked out from
     pub transactions: Vec<Transaction>,
+    #[serde(default)]
+    pub closed_lots: Vec<ClosedLot>,
+    /// The number of decimals share quantities are kept to
+    #[serde(default = "default_quantity_decimals")]
+    quantity_decimals: u32,
+    /// Holdings left with less than this after a sale are sold off entirely. Defaults to the rounding of quantities
+    #[serde(default)]
+    dust_threshold: Option<f64>,
+    /// The number of decimals a sale leaves the remaining quantity at, if sales are rounded
+    #[serde(default)]
+    remainder_decimals: Option<u32>,
+    #[serde(default)]
+    partial_sell_policy: PartialSellPolicy,
+    #[serde(default)]
+    frozen: bool,
+    /// Chosen when the account is opened
+    #[serde(default)]
+    tax_treatment: TaxTreatment,
+}
+
+fn default_quantity_decimals() -> u32{
+    stock::DEFAULT_QUANTITY_DECIMALS
 }
 
 impl FromStr for InvestmentAccount{
@@ -176,7 +294,7 @@ impl Account for InvestmentAccount{
         self.id
     }
 
-    fn get_balance(&self) -> f64{
+    fn get_balance(&self) -> Decimal{
         self.balance
     }
 
@@ -184,25 +302,31 @@ impl Account for InvestmentAccount{
         self.nickname.clone()
     }
 
-    fn deposit(&mut self, amount: f64) -> f64{
+    fn deposit_at(&mut self, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<Decimal, error::BankError>{
+        if self.frozen{
+            return Err(error::BankError::AccountFrozen);
+        }
         self.balance += amount;
         self.transactions.push(Transaction::new(
             transactions::TransactionType::Deposit,
             amount,
-            chrono::Utc::now(),
+            date,
             None,
         ));
-        self.balance
+        Ok(self.balance)
     }
 
-    fn withdraw(&mut self, amount: f64) -> Result<f64, error::BankError>{
+    fn withdraw_at(&mut self, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<Decimal, error::BankError>{
+        if self.frozen{
+            return Err(error::BankError::AccountFrozen);
+        }
         if self.balance < amount{
             return Err(error::BankError::InsufficientFunds);
         }
         self.transactions.push(Transaction::new(
             transactions::TransactionType::Withdraw,
             amount,
-            chrono::Utc::now(),
+            date,
             None,
         ));
         self.balance -= amount;
@@ -213,6 +337,14 @@ impl Account for InvestmentAccount{
         self.created_at
     }
 
+    fn is_frozen(&self) -> bool{
+        self.frozen
+    }
+
+    fn set_frozen(&mut self, frozen: bool){
+        self.frozen = frozen;
+    }
+
     fn get_account_type(&self) -> AccountType{
         AccountType::Investment
     }
@@ -220,6 +352,14 @@ impl Account for InvestmentAccount{
     fn add_transaction(&mut self, transaction: Transaction){
         self.transactions.push(transaction);
     }
+
+    fn get_transactions(&self) -> &Vec<Transaction>{
+        &self.transactions
+    }
+
+    fn get_transactions_mut(&mut self) -> &mut Vec<Transaction>{
+        &mut self.transactions
+    }
 }
 
 impl InvestmentAccount{
@@ -231,7 +371,7 @@ impl InvestmentAccount{
     /// * `id` - The id of the account
     /// * `balance` - The balance of the account
     /// * `nickname` - An optional nickname for the account
-    pub fn new(id: u32, balance: f64, nickname: Option<String>) -> Self{
+    pub fn new(id: u32, balance: Decimal, nickname: Option<String>) -> Self{
         InvestmentAccount{
             id: id,
             balance: balance,
@@ -239,33 +379,138 @@ impl InvestmentAccount{
             created_at: chrono::Utc::now(),
             assets: HashMap::new(),
             transactions: Vec::<Transaction>::new(),
+            closed_lots: Vec::new(),
+            quantity_decimals: stock::DEFAULT_QUANTITY_DECIMALS,
+            dust_threshold: None,
+            remainder_decimals: None,
+            partial_sell_policy: PartialSellPolicy::default(),
+            frozen: false,
+            tax_treatment: TaxTreatment::default(),
         }
     }
 
+    /// Sets how the account is taxed, for an account being opened
+    pub fn with_tax_treatment(mut self, tax_treatment: TaxTreatment) -> Self{
+        self.tax_treatment = tax_treatment;
+        self
+    }
+
+    /// Gives the account another id, for an account moving between the bank's maps
+    pub(crate) fn with_id(mut self, id: u32) -> Self{
+        self.id = id;
+        self
+    }
+
+    pub fn get_tax_treatment(&self) -> TaxTreatment{
+        self.tax_treatment
+    }
+
     pub fn get_investments(&self) -> &HashMap<String, Holding>{
         &self.assets
     }
 
-    pub fn purchase_investment(&mut self, symbol: String, price: f64, quantity: f64) -> Result<(), error::BankError>{
+    pub fn get_quantity_decimals(&self) -> u32{
+        self.quantity_decimals
+    }
+
+    /// Sets the number of decimals share quantities are rounded to on every purchase and sale
+    pub fn set_quantity_decimals(&mut self, quantity_decimals: u32){
+        self.quantity_decimals = quantity_decimals;
+    }
+
+    pub fn get_dust_threshold(&self) -> f64{
+        self.dust_threshold.unwrap_or_else(|| stock::quantity_epsilon(self.quantity_decimals))
+    }
+
+    /// Sets the quantity under which what a sale leaves of a holding is sold too, or goes back to the rounding of quantities
+    pub fn set_dust_threshold(&mut self, dust_threshold: Option<f64>){
+        self.dust_threshold = dust_threshold;
+    }
+
+    pub fn get_remainder_decimals(&self) -> Option<u32>{
+        self.remainder_decimals
+    }
+
+    /// Sets the number of decimals sales leave holdings at, adjusting each sale's quantity to fit, or stops adjusting sales
+    pub fn set_remainder_decimals(&mut self, remainder_decimals: Option<u32>){
+        self.remainder_decimals = remainder_decimals;
+    }
+
+    pub fn get_partial_sell_policy(&self) -> PartialSellPolicy{
+        self.partial_sell_policy
+    }
+
+    /// Sets whether selling more shares than are held is rejected, or sells the whole holding
+    pub fn set_partial_sell_policy(&mut self, partial_sell_policy: PartialSellPolicy){
+        self.partial_sell_policy = partial_sell_policy;
+    }
+
+    /// Gets the quantity a sale of the given quantity will actually sell: rounded, adjusted so the remainder
+    /// lands on the remainder decimals, and taking the whole holding if what is left would be dust,
+    /// or if more than is held was asked for and the partial sell policy allows it
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::InsufficientQuantity` if the holding does not exist, or is too small under the strict policy
+    pub fn sale_quantity(&self, symbol: &str, quantity: f64) -> Result<f64, error::BankError>{
+        let mut quantity = stock::round_quantity(quantity, self.quantity_decimals);
+        if quantity.is_nan() || quantity <= 0.0{
+            return Err(error::BankError::InvalidAmount);
+        }
+        let holding = self.assets.get(symbol).ok_or(error::BankError::InsufficientQuantity)?;
+        let epsilon = stock::quantity_epsilon(self.quantity_decimals);
+        if holding.quantity < quantity - epsilon{
+            match self.partial_sell_policy{
+                PartialSellPolicy::Strict => return Err(error::BankError::InsufficientQuantity),
+                PartialSellPolicy::SellAvailable => return Ok(holding.quantity),
+            }
+        }
+        if let Some(decimals) = self.remainder_decimals{
+            let remainder = stock::round_quantity(holding.quantity - quantity, decimals).clamp(0.0, holding.quantity);
+            quantity = stock::round_quantity(holding.quantity - remainder, self.quantity_decimals);
+        }
+        if holding.quantity - quantity < self.get_dust_threshold(){
+            quantity = holding.quantity;
+        }
+        // selling within rounding of the whole position sells exactly the whole position
+        Ok(quantity.min(holding.quantity))
+    }
+
+    pub fn purchase_investment(&mut self, symbol: String, price: Decimal, quantity: f64) -> Result<(), error::BankError>{
+        self.purchase_investment_at(symbol, price, quantity, chrono::Utc::now())
+    }
+
+    /// Buys an investment as `purchase_investment` does, recording the purchase at the given date rather than now.
+    /// A new holding counts as acquired at the date
+    pub fn purchase_investment_at(&mut self, symbol: String, price: Decimal, quantity: f64, date: chrono::DateTime<chrono::Utc>) -> Result<(), error::BankError>{
+        if self.frozen{
+            return Err(error::BankError::AccountFrozen);
+        }
+        // quantities that round to nothing are rejected too, rather than recording an empty purchase
+        let quantity = stock::round_quantity(quantity, self.quantity_decimals);
+        if quantity.is_nan() || quantity <= 0.0{
+            return Err(error::BankError::InvalidAmount);
+        }
         // Check if the account has enough balance
-        let total_cost = price * quantity;
+        let total_cost = price * money::from_f64(quantity);
         if self.balance < total_cost{
             return Err(error::BankError::InsufficientFunds);
         }
         self.balance -= total_cost;
         if let Some(holding) = self.assets.get_mut(symbol.as_str()){
             // Update the average cost per unit
-            holding.average_cost_per_unit = (holding.average_cost_per_unit * holding.quantity + total_cost) / (holding.quantity + quantity);
-            holding.quantity += quantity;
+            holding.average_cost_per_unit = (holding.average_cost_per_unit * money::from_f64(holding.quantity) + total_cost) / money::from_f64(holding.quantity + quantity);
+            holding.quantity = stock::round_quantity(holding.quantity + quantity, self.quantity_decimals);
         }else{
-            let holding = Holding::new(total_cost/quantity, quantity, symbol.clone());
+            let mut holding = Holding::new(price, quantity, symbol.clone());
+            holding.first_acquired = date;
             self.assets.insert(symbol.clone(), holding);
         }
         // Update the transactions
         let transaction = Transaction::new(
             transactions::TransactionType::Purchase(stock::Asset::new(symbol.clone()), quantity),
             total_cost,
-            chrono::Utc::now(),
+            date,
             None,
         );
         self.transactions.push(transaction);
@@ -283,35 +528,84 @@ impl InvestmentAccount{
     /// 
     /// # Returns
     /// 
-    /// * `Ok(())` - If the investment was sold successfully
-    /// * `Err(BankError::InsufficientQuantity)` - If the quantity of the investment is insufficient
-    pub fn sell_investment(&mut self, symbol: String, price: f64, quantity: f64) -> Result<(), error::BankError>{
-        // check that you actually have the investment
-        if !self.assets.contains_key(symbol.as_str()){
-            return Err(error::BankError::InsufficientQuantity);
+    /// * `Ok(quantity)` - The quantity actually sold, adjusted as `sale_quantity` does
+    /// * `Err(BankError::InsufficientQuantity)` - If the quantity of the investment is insufficient under the strict partial sell policy
+    pub fn sell_investment(&mut self, symbol: String, price: Decimal, quantity: f64) -> Result<f64, error::BankError>{
+        self.sell_investment_at(symbol, price, quantity, chrono::Utc::now())
+    }
+
+    /// Sells an investment as `sell_investment` does, recording the sale and its closed lot at the given date rather than now
+    pub fn sell_investment_at(&mut self, symbol: String, price: Decimal, quantity: f64, date: chrono::DateTime<chrono::Utc>) -> Result<f64, error::BankError>{
+        if self.frozen{
+            return Err(error::BankError::AccountFrozen);
         }
+        let quantity = self.sale_quantity(&symbol, quantity)?;
+        let epsilon = stock::quantity_epsilon(self.quantity_decimals);
         let holding = self.assets.get_mut(symbol.as_str()).unwrap();
-        if holding.quantity < quantity{
-            return Err(error::BankError::InsufficientQuantity);
-        }
-        let total_cost = price * quantity;
+        let total_cost = price * money::from_f64(quantity);
         self.balance += total_cost;
-        holding.quantity -= quantity;
-        if holding.quantity == 0.0{
-            // Remove the holding if the quantity is 0
+        let mut lot = ClosedLot::new(
+            symbol.clone(),
+            quantity,
+            holding.average_cost_per_unit * money::from_f64(quantity),
+            total_cost,
+            date,
+        );
+        // holdings saved before acquisition dates were recorded have no known opening
+        if holding.get_first_acquired() != chrono::DateTime::UNIX_EPOCH{
+            lot = lot.with_opened_at(holding.get_first_acquired());
+        }
+        self.closed_lots.push(lot);
+        holding.quantity = stock::round_quantity(holding.quantity - quantity, self.quantity_decimals);
+        if holding.quantity.abs() < epsilon{
+            // Remove the holding if the quantity is 0, up to rounding
             self.assets.remove(symbol.as_str());
         }
         // Update the transactions
         let transaction = Transaction::new(
             transactions::TransactionType::Sale(stock::Asset::new(symbol.clone()), quantity),
             total_cost,
-            chrono::Utc::now(),
+            date,
             None,
         );
         self.transactions.push(transaction);
+        Ok(quantity)
+    }
+
+    /// Charges a commission for a trade of the given symbol
+    /// A zero commission is not recorded
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::InsufficientFunds` if the balance cannot cover the commission
+    pub fn charge_commission(&mut self, symbol: String, amount: Decimal) -> Result<(), error::BankError>{
+        self.charge_commission_at(symbol, amount, chrono::Utc::now())
+    }
+
+    /// Charges a commission as `charge_commission` does, recording it at the given date rather than now
+    pub fn charge_commission_at(&mut self, symbol: String, amount: Decimal, date: chrono::DateTime<chrono::Utc>) -> Result<(), error::BankError>{
+        if amount.is_zero(){
+            return Ok(());
+        }
+        if self.balance < amount{
+            return Err(error::BankError::InsufficientFunds);
+        }
+        self.balance -= amount;
+        self.transactions.push(Transaction::new(
+            transactions::TransactionType::Commission(stock::Asset::new(symbol)),
+            amount,
+            date,
+            None,
+        ));
         Ok(())
     }
 
+    /// Credits a dividend payment to the account, and records it
+    pub fn pay_dividend(&mut self, transaction: Transaction){
+        self.balance += transaction.amount;
+        self.transactions.push(transaction);
+    }
+
     pub fn from_checking<T: Account>(account: T) -> Self{
         InvestmentAccount{
             id: account.get_id(),
@@ -319,7 +613,14 @@ impl InvestmentAccount{
             nickname: account.get_nickname(),
             created_at: account.get_created_at(),
             assets: HashMap::new(),
-            transactions: Vec::<Transaction>::new(),
+            transactions: account.get_transactions().clone(),
+            closed_lots: Vec::new(),
+            quantity_decimals: stock::DEFAULT_QUANTITY_DECIMALS,
+            dust_threshold: None,
+            remainder_decimals: None,
+            partial_sell_policy: PartialSellPolicy::default(),
+            frozen: account.is_frozen(),
+            tax_treatment: TaxTreatment::default(),
         }
     }
 
@@ -329,95 +630,248 @@ impl InvestmentAccount{
 mod test{
 
     use super::*;
+    use rust_decimal_macros::dec;
 
     #[test]
     fn test_checking_account(){
-        let account = CheckingAccount::new(1, 0.0, None);
+        let account = CheckingAccount::new(1, dec!(0.0), None);
         assert_eq!(account.get_id(), 1);
-        assert_eq!(account.get_balance(), 0.0);
+        assert_eq!(account.get_balance(), dec!(0.0));
         assert_eq!(account.get_nickname(), None);
         assert_eq!(account.get_account_type(), AccountType::Checking);
     }
 
     #[test]
     fn test_investment_account(){
-        let account = InvestmentAccount::new(1, 0.0, None);
+        let account = InvestmentAccount::new(1, dec!(0.0), None);
         assert_eq!(account.get_id(), 1);
-        assert_eq!(account.get_balance(), 0.0);
+        assert_eq!(account.get_balance(), dec!(0.0));
         assert_eq!(account.get_nickname(), None);
         assert_eq!(account.get_account_type(), AccountType::Investment);
     }
 
     #[test]
     fn test_deposit(){
-        let mut account = CheckingAccount::new(1, 0.0, None);
-        assert_eq!(account.deposit(100.0), 100.0);
-        assert_eq!(account.deposit(100.0), 200.0);
+        let mut account = CheckingAccount::new(1, dec!(0.0), None);
+        assert_eq!(account.deposit(dec!(100.0)).unwrap(), dec!(100.0));
+        assert_eq!(account.deposit(dec!(100.0)).unwrap(), dec!(200.0));
     }
 
     #[test]
     fn test_withdraw(){
-        let mut account = CheckingAccount::new(1, 100.0, None);
-        assert_eq!(account.withdraw(50.0).unwrap(), 50.0);
+        let mut account = CheckingAccount::new(1, dec!(100.0), None);
+        assert_eq!(account.withdraw(dec!(50.0)).unwrap(), dec!(50.0));
     }
 
     #[test]
     fn test_withdraw_insufficient_funds(){
-        let mut account = CheckingAccount::new(1, 0.0, None);
-        assert!(account.withdraw(50.0).is_err());
+        let mut account = CheckingAccount::new(1, dec!(0.0), None);
+        assert!(account.withdraw(dec!(50.0)).is_err());
     }
 
     #[test]
     fn test_purchase_investment(){
-        let mut account = InvestmentAccount::new(1, 100.0, None);
-        account.purchase_investment("AAPL".to_string(), 100.0, 1.0).unwrap();
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 1.0).unwrap();
         assert_eq!(account.get_investments().len(), 1);
         assert_eq!(account.get_investments().get("AAPL").unwrap().quantity, 1.0);
-        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, 100.0);
+        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, dec!(100.0));
 
-        account.deposit(100.0);
-        account.purchase_investment("AAPL".to_string(), 100.0, 1.0).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 1.0).unwrap();
         assert_eq!(account.get_investments().len(), 1);
         assert_eq!(account.get_investments().get("AAPL").unwrap().quantity, 2.0);
-        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, 100.0);
+        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, dec!(100.0));
 
-        account.deposit(100.0);
-        account.purchase_investment("AAPL".to_string(), 10.0, 1.).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 1.).unwrap();
         assert_eq!(account.get_investments().len(), 1);
         assert_eq!(account.get_investments().get("AAPL").unwrap().quantity, 3.0);
-        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, 70.0);
+        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, dec!(70.0));
     }
 
     #[test]
     fn test_purchase_investment_insufficient_funds(){
-        let mut account = InvestmentAccount::new(1, 0.0, None);
-        assert!(account.purchase_investment("AAPL".to_string(), 100.0, 1.0).is_err());
+        let mut account = InvestmentAccount::new(1, dec!(0.0), None);
+        assert!(account.purchase_investment("AAPL".to_string(), dec!(100.0), 1.0).is_err());
     }
 
     #[test]
     fn test_sell_investment(){
-        let mut account = InvestmentAccount::new(1, 100.0, None);
-        account.purchase_investment("AAPL".to_string(), 100.0, 1.0).unwrap();
-        account.sell_investment("AAPL".to_string(), 100.0, 1.0).unwrap();
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 1.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(100.0), 1.0).unwrap();
         assert_eq!(account.get_investments().len(), 0);
     }
 
     #[test]
     fn test_sell_investment_insufficient_quantity(){
-        let mut account = InvestmentAccount::new(1, 100.0, None);
-        account.purchase_investment("AAPL".to_string(), 100.0, 1.0).unwrap();
-        assert!(account.sell_investment("AAPL".to_string(), 100.0, 2.0).is_err());
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 1.0).unwrap();
+        assert!(account.sell_investment("AAPL".to_string(), dec!(100.0), 2.0).is_err());
+
+        account.set_partial_sell_policy(PartialSellPolicy::SellAvailable);
+        assert_eq!(account.sell_investment("AAPL".to_string(), dec!(100.0), 2.0).unwrap(), 1.0);
+        assert_eq!(account.get_balance(), dec!(100.0));
+        assert!(account.get_investments().is_empty());
+        assert!(account.sell_investment("AAPL".to_string(), dec!(100.0), 1.0).is_err());
     }
 
     #[test]
     fn test_sell_investment_mean_remains(){
-        let mut account = InvestmentAccount::new(1, 100.0, None);
-        account.purchase_investment("AAPL".to_string(), 50.0, 2.0).unwrap();
-        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, 50.0);
-        account.sell_investment("AAPL".to_string(), 100.0, 1.0).unwrap();
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(50.0), 2.0).unwrap();
+        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, dec!(50.0));
+        account.sell_investment("AAPL".to_string(), dec!(100.0), 1.0).unwrap();
         assert_eq!(account.get_investments().get("AAPL").unwrap().quantity, 1.0);
-        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, 50.0);
-        assert_eq!(account.get_balance(), 100.0);
+        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, dec!(50.0));
+        assert_eq!(account.get_balance(), dec!(100.0));
+    }
+
+    #[test]
+    fn test_charge_commission(){
+        let mut account = InvestmentAccount::new(1, dec!(10.0), None);
+        account.charge_commission("AAPL".to_string(), dec!(0.0)).unwrap();
+        assert_eq!(account.transactions.len(), 0);
+        account.charge_commission("AAPL".to_string(), dec!(2.5)).unwrap();
+        assert_eq!(account.get_balance(), dec!(7.5));
+        assert_eq!(account.transactions[0].transaction_type, TransactionType::Commission(stock::Asset::new("AAPL".to_string())));
+        assert!(account.charge_commission("AAPL".to_string(), dec!(10.0)).is_err());
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_tag_transactions(){
+        let mut account = InvestmentAccount::new(1, dec!(0.0), None);
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 3.0).unwrap();
+        account.tag_transaction(1, transactions::tags::MANUAL).unwrap();
+        account.tag_transaction(2, transactions::tags::REBALANCE).unwrap();
+        account.tag_transaction(2, transactions::tags::REBALANCE).unwrap();
+        assert!(account.tag_transaction(3, "other").is_err());
+
+        let manual = account.transactions_with_tag(transactions::tags::MANUAL);
+        assert_eq!(manual.len(), 1);
+        assert_eq!(manual[0].amount, dec!(20.0));
+        let rebalance = account.transactions_with_tag(transactions::tags::REBALANCE);
+        assert_eq!(rebalance.len(), 1);
+        assert_eq!(rebalance[0].tags.len(), 1);
+        assert!(account.transactions_with_tag("other").is_empty());
+    }
+
+    #[test]
+    fn test_sell_position_in_fractional_pieces(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        for _ in 0..3{
+            account.purchase_investment("AAPL".to_string(), dec!(10.0), 0.1).unwrap();
+        }
+        for _ in 0..2{
+            account.sell_investment("AAPL".to_string(), dec!(10.0), 0.1).unwrap();
+        }
+        assert!(account.get_investments().contains_key("AAPL"));
+        account.sell_investment("AAPL".to_string(), dec!(10.0), 0.1).unwrap();
+        assert!(!account.get_investments().contains_key("AAPL"));
+        assert_eq!(account.get_balance(), dec!(100.0));
+    }
+
+    #[test]
+    fn test_quantity_decimals(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.set_quantity_decimals(2);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 1.004).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].quantity, 1.0);
+        account.sell_investment("AAPL".to_string(), dec!(10.0), 0.999).unwrap();
+        assert!(account.get_investments().is_empty());
+        assert!(account.sell_investment("AAPL".to_string(), dec!(10.0), 0.1).is_err());
+    }
+
+    #[test]
+    fn test_zero_quantity_rejected(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 1.0).unwrap();
+        let transactions = account.transactions.len();
+        for quantity in [0.0, -1.0, 0.0000001]{
+            assert!(matches!(account.purchase_investment("AAPL".to_string(), dec!(10.0), quantity), Err(error::BankError::InvalidAmount)));
+            assert!(matches!(account.sell_investment("AAPL".to_string(), dec!(10.0), quantity), Err(error::BankError::InvalidAmount)));
+        }
+        assert!(matches!(account.purchase_investment("MSFT".to_string(), dec!(10.0), 0.0), Err(error::BankError::InvalidAmount)));
+        assert_eq!(account.transactions.len(), transactions);
+        assert!(!account.get_investments().contains_key("MSFT"));
+        assert_eq!(account.get_balance(), dec!(90.0));
+    }
+
+    #[test]
+    fn test_dust_threshold(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 1.0).unwrap();
+        account.set_dust_threshold(Some(0.01));
+        account.sell_investment("AAPL".to_string(), dec!(10.0), 0.995).unwrap();
+        assert!(account.get_investments().is_empty());
+        assert_eq!(account.get_balance(), dec!(100.0));
+    }
+
+    #[test]
+    fn test_remainder_decimals(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 1.0).unwrap();
+        account.set_remainder_decimals(Some(2));
+        assert_eq!(account.sale_quantity("AAPL", 1.0 / 3.0).unwrap(), 0.33);
+        account.sell_investment("AAPL".to_string(), dec!(10.0), 1.0 / 3.0).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].quantity, 0.67);
+    }
+
+    #[test]
+    fn test_first_acquired_kept_across_purchases(){
+        use chrono::TimeZone;
+
+        let date = |day| chrono::Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap();
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment_at("AAPL".to_string(), dec!(10.0), 1.0, date(2)).unwrap();
+        account.purchase_investment_at("AAPL".to_string(), dec!(10.0), 1.0, date(3)).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].get_first_acquired(), date(2));
+
+        // selling out and buying back starts a new holding
+        account.sell_investment_at("AAPL".to_string(), dec!(10.0), 2.0, date(4)).unwrap();
+        account.purchase_investment_at("AAPL".to_string(), dec!(10.0), 1.0, date(5)).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].get_first_acquired(), date(5));
+    }
+
+    #[test]
+    fn test_investment_account_round_trip(){
+        let mut account = InvestmentAccount::new(1, dec!(0.0), Some("Savings".to_string())).with_tax_treatment(TaxTreatment::TaxDeferred);
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 3.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(12.0), 1.0).unwrap();
+        account.set_quantity_decimals(4);
+        account.set_dust_threshold(Some(0.01));
+        account.set_remainder_decimals(Some(2));
+        account.set_partial_sell_policy(PartialSellPolicy::SellAvailable);
+        account.set_frozen(true);
+
+        let loaded = InvestmentAccount::from_str(&account.to_string()).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&account).unwrap());
+        assert_eq!(loaded.get_partial_sell_policy(), PartialSellPolicy::SellAvailable);
+        assert_eq!(loaded.get_tax_treatment(), TaxTreatment::TaxDeferred);
+        assert_eq!(loaded.closed_lots.len(), 1);
+    }
+
+    #[test]
+    fn test_dated_transactions(){
+        use chrono::TimeZone;
+
+        let date = |day| chrono::Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap();
+        let mut account = InvestmentAccount::new(1, dec!(0.0), None);
+        account.deposit_at(dec!(100.0), date(2)).unwrap();
+        account.purchase_investment_at("AAPL".to_string(), dec!(10.0), 2.0, date(3)).unwrap();
+        account.sell_investment_at("AAPL".to_string(), dec!(12.0), 1.0, date(4)).unwrap();
+        account.withdraw_at(dec!(50.0), date(5)).unwrap();
+
+        let dates: Vec<_> = account.transactions.iter().map(|transaction| transaction.date).collect();
+        assert_eq!(dates, vec![date(2), date(3), date(4), date(5)]);
+        assert_eq!(account.get_investments()["AAPL"].get_first_acquired(), date(3));
+        assert_eq!(account.closed_lots[0].closed_at, date(4));
+        assert_eq!(account.closed_lots[0].opened_at, Some(date(3)));
+        assert_eq!(account.get_balance(), dec!(42.0));
+    }
+
+}
@@ -0,0 +1,111 @@
+use rust_decimal::Decimal;
+
+use super::{accounts::{Account, AccountType}, Bank};
+
+/// Something wrong with the saved state of an account
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditProblem{
+    NegativeBalance(Decimal),
+    /// A holding of less than nothing
+    NegativeQuantity{ symbol: String, quantity: f64 },
+    /// A holding whose quantity is NaN or infinite
+    NonFiniteQuantity{ symbol: String, quantity: f64 },
+    NegativeCost{ symbol: String, average_cost_per_unit: Decimal },
+    /// The transactions do not add up to the stored balance
+    Unreconciled{ balance: Decimal, transactions_total: Decimal },
+}
+
+/// A problem found in one account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditFinding{
+    pub account_id: u32,
+    pub account_type: AccountType,
+    pub problem: AuditProblem,
+}
+
+/// Checks what every account has, checking or investment: the balance, and the transactions that explain it
+fn audit_cash<T: Account>(account: &T, findings: &mut Vec<AuditFinding>){
+    let mut flag = |problem| findings.push(AuditFinding{ account_id: account.get_id(), account_type: account.get_account_type(), problem });
+    let balance = account.get_balance();
+    if balance < Decimal::ZERO{
+        flag(AuditProblem::NegativeBalance(balance));
+    }
+    let transactions_total: Decimal = account.get_transactions().iter().map(|transaction| transaction.cash_effect()).sum();
+    if transactions_total != balance{
+        flag(AuditProblem::Unreconciled{ balance, transactions_total });
+    }
+}
+
+impl Bank{
+    /// Scans every account for state that should not be possible, such as after a bad save or a path that skipped validation:
+    /// negative balances, holdings with negative or non-finite quantities or negative costs,
+    /// and transactions that do not add up to the balance. Accounts are opened empty, so every cent should have a transaction
+    ///
+    /// # Returns
+    ///
+    /// Returns each problem found, checking accounts first, then investment accounts, each in order of id
+    pub fn audit(&self) -> Vec<AuditFinding>{
+        let mut findings = Vec::new();
+        let mut checking_ids: Vec<&u32> = self.checking_accounts.keys().collect();
+        checking_ids.sort();
+        for id in checking_ids{
+            audit_cash(&self.checking_accounts[id], &mut findings);
+        }
+
+        let mut investment_ids: Vec<&u32> = self.investment_accounts.keys().collect();
+        investment_ids.sort();
+        for id in investment_ids{
+            let account = &self.investment_accounts[id];
+            audit_cash(account, &mut findings);
+            let mut holdings: Vec<_> = account.get_investments().values().collect();
+            holdings.sort_by(|a, b| a.asset.symbol.cmp(&b.asset.symbol));
+            for holding in holdings{
+                let mut flag = |problem| findings.push(AuditFinding{ account_id: *id, account_type: AccountType::Investment, problem });
+                let symbol = &holding.asset.symbol;
+                if !holding.quantity.is_finite(){
+                    flag(AuditProblem::NonFiniteQuantity{ symbol: symbol.clone(), quantity: holding.quantity });
+                }else if holding.quantity < 0.0{
+                    flag(AuditProblem::NegativeQuantity{ symbol: symbol.clone(), quantity: holding.quantity });
+                }
+                if holding.average_cost_per_unit < Decimal::ZERO{
+                    flag(AuditProblem::NegativeCost{ symbol: symbol.clone(), average_cost_per_unit: holding.average_cost_per_unit });
+                }
+            }
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_audit(){
+        let mut bank = Bank::empty();
+        let checking = bank.open_account(None, AccountType::Checking).unwrap();
+        let investment = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_checking_account_mut(checking).unwrap().deposit(dec!(50.0)).unwrap();
+        let account = bank.get_investment_account_mut(investment).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 2.0).unwrap();
+        assert!(bank.audit().is_empty());
+
+        // a negative deposit is recorded, so it reconciles, but leaves the balance below zero
+        bank.get_checking_account_mut(checking).unwrap().deposit(dec!(-80.0)).unwrap();
+        let account = bank.get_investment_account_mut(investment).unwrap();
+        account.assets.get_mut("AAPL").unwrap().quantity = -1.0;
+        account.assets.get_mut("MSFT").unwrap().quantity = f64::NAN;
+        account.transactions.pop();
+
+        let findings = bank.audit();
+        assert_eq!(findings.len(), 4);
+        assert_eq!(findings[0], AuditFinding{ account_id: checking, account_type: AccountType::Checking, problem: AuditProblem::NegativeBalance(dec!(-30.0)) });
+        assert_eq!(findings[1].problem, AuditProblem::Unreconciled{ balance: dec!(60.0), transactions_total: dec!(80.0) });
+        assert_eq!(findings[2].problem, AuditProblem::NegativeQuantity{ symbol: "AAPL".to_string(), quantity: -1.0 });
+        assert!(matches!(&findings[3].problem, AuditProblem::NonFiniteQuantity{ symbol, .. } if symbol == "MSFT"));
+    }
+}
@@ -0,0 +1,185 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{accounts::{Account, InvestmentAccount}, error, money, stock::{self, Asset, Holding}, transactions::{Transaction, TransactionType}};
+
+/// A change to a holding made by the company rather than by a trade, recorded by hand
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CorporateAction{
+    /// The ticker changed, such as FB to META. The holding keeps its quantity and cost basis
+    SymbolChange{ from: String, to: String },
+    /// Shares of a new company were given to holders of the parent
+    Spinoff{
+        parent: String,
+        child: String,
+        /// Shares of the child per share of the parent
+        ratio: f64,
+        /// The fraction of the parent's cost basis that moves to the child, such as 0.2 for 20%
+        basis_fraction: Decimal,
+    },
+    /// The company was acquired for shares of the acquirer. The cost basis carries over to the new shares
+    Merger{
+        target: String,
+        acquirer: String,
+        /// Shares of the acquirer per share of the target
+        ratio: f64,
+    },
+}
+
+impl CorporateAction{
+    fn describe(&self) -> String{
+        match self{
+            CorporateAction::SymbolChange{ from, to } => format!("Symbol change from {} to {}", from, to),
+            CorporateAction::Spinoff{ parent, child, ratio, .. } => format!("Spin-off of {} {} per share of {}", ratio, child, parent),
+            CorporateAction::Merger{ target, acquirer, ratio } => format!("Merger of {} into {} at {} per share", target, acquirer, ratio),
+        }
+    }
+}
+
+impl InvestmentAccount{
+    /// Adds shares to a holding at a total cost basis, averaging with the shares already held
+    fn add_to_holding(&mut self, symbol: &str, quantity: f64, cost_basis: Decimal, first_acquired: chrono::DateTime<chrono::Utc>){
+        let decimals = self.get_quantity_decimals();
+        match self.assets.get_mut(symbol){
+            Some(holding) => {
+                let total_quantity = holding.quantity + quantity;
+                holding.average_cost_per_unit = (holding.average_cost_per_unit * money::from_f64(holding.quantity) + cost_basis) / money::from_f64(total_quantity);
+                holding.quantity = stock::round_quantity(total_quantity, decimals);
+                holding.first_acquired = holding.first_acquired.min(first_acquired);
+            }
+            None => {
+                let mut holding = Holding::new(cost_basis / money::from_f64(quantity), stock::round_quantity(quantity, decimals), symbol.to_string());
+                holding.first_acquired = first_acquired;
+                self.assets.insert(symbol.to_string(), holding);
+            }
+        }
+    }
+
+    /// Applies a corporate action to the account's holdings, and records it in the transaction log.
+    /// The transaction moves no cash, and lists how the quantity of each symbol changed
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::HoldingNotFound` if the account does not hold the symbol the action applies to,
+    /// `BankError::InvalidAmount` if a ratio is not positive or a basis fraction is not between 0 and 1,
+    /// or `BankError::AccountFrozen` if the account is frozen
+    pub fn apply_corporate_action(&mut self, action: CorporateAction) -> Result<(), error::BankError>{
+        self.apply_corporate_action_at(action, chrono::Utc::now())
+    }
+
+    /// Applies a corporate action as `apply_corporate_action` does, recording it at the given date rather than now
+    pub fn apply_corporate_action_at(&mut self, action: CorporateAction, date: chrono::DateTime<chrono::Utc>) -> Result<(), error::BankError>{
+        if self.is_frozen(){
+            return Err(error::BankError::AccountFrozen);
+        }
+        let changes = match &action{
+            CorporateAction::SymbolChange{ from, to } => {
+                let holding = self.assets.remove(from).ok_or(error::BankError::HoldingNotFound)?;
+                let cost_basis = holding.average_cost_per_unit * money::from_f64(holding.quantity);
+                self.add_to_holding(to, holding.quantity, cost_basis, holding.first_acquired);
+                vec![(Asset::new(from.clone()), -holding.quantity), (Asset::new(to.clone()), holding.quantity)]
+            }
+            CorporateAction::Spinoff{ parent, child, ratio, basis_fraction } => {
+                if ratio.is_nan() || *ratio <= 0.0 || *basis_fraction < Decimal::ZERO || *basis_fraction > Decimal::ONE{
+                    return Err(error::BankError::InvalidAmount);
+                }
+                let holding = self.assets.get_mut(parent).ok_or(error::BankError::HoldingNotFound)?;
+                let moved_basis = holding.average_cost_per_unit * money::from_f64(holding.quantity) * basis_fraction;
+                holding.average_cost_per_unit *= Decimal::ONE - basis_fraction;
+                let (quantity, first_acquired) = (holding.quantity * ratio, holding.first_acquired);
+                self.add_to_holding(child, quantity, moved_basis, first_acquired);
+                vec![(Asset::new(child.clone()), quantity)]
+            }
+            CorporateAction::Merger{ target, acquirer, ratio } => {
+                if ratio.is_nan() || *ratio <= 0.0{
+                    return Err(error::BankError::InvalidAmount);
+                }
+                let holding = self.assets.remove(target).ok_or(error::BankError::HoldingNotFound)?;
+                let cost_basis = holding.average_cost_per_unit * money::from_f64(holding.quantity);
+                let quantity = holding.quantity * ratio;
+                self.add_to_holding(acquirer, quantity, cost_basis, holding.first_acquired);
+                vec![(Asset::new(target.clone()), -holding.quantity), (Asset::new(acquirer.clone()), quantity)]
+            }
+        };
+        let transaction = Transaction::new(
+            TransactionType::CorporateAction(changes),
+            Decimal::ZERO,
+            date,
+            Some(action.describe()),
+        );
+        self.transactions.push(transaction);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::collections::HashMap;
+
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_symbol_change(){
+        let mut account = InvestmentAccount::new(1, dec!(1000.0), None);
+        account.purchase_investment("FB".to_string(), dec!(100.0), 2.0).unwrap();
+        account.purchase_investment("META".to_string(), dec!(400.0), 1.0).unwrap();
+        account.apply_corporate_action(CorporateAction::SymbolChange{ from: "FB".to_string(), to: "META".to_string() }).unwrap();
+
+        assert!(!account.assets.contains_key("FB"));
+        let holding = &account.assets["META"];
+        assert_eq!(holding.quantity, 3.0);
+        assert_eq!(holding.average_cost_per_unit, dec!(200.0));
+        assert_eq!(account.get_balance(), dec!(400.0));
+        assert_eq!(account.transactions.last().unwrap().description.as_deref(), Some("Symbol change from FB to META"));
+        let (_, quantities) = account.positions_at(chrono::Utc::now());
+        assert_eq!(quantities["META"], 3.0);
+        assert!(matches!(
+            account.apply_corporate_action(CorporateAction::SymbolChange{ from: "FB".to_string(), to: "META".to_string() }),
+            Err(error::BankError::HoldingNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_backdated_symbol_change(){
+        let day = |day| Utc.with_ymd_and_hms(2022, 6, day, 15, 0, 0).unwrap();
+        let mut account = InvestmentAccount::new(1, dec!(1000.0), None);
+        account.purchase_investment_at("FB".to_string(), dec!(100.0), 2.0, day(1)).unwrap();
+        account.purchase_investment_at("META".to_string(), dec!(400.0), 1.0, day(2)).unwrap();
+        account.apply_corporate_action_at(CorporateAction::SymbolChange{ from: "FB".to_string(), to: "META".to_string() }, day(9)).unwrap();
+        assert_eq!(account.transactions.last().unwrap().date, day(9));
+
+        let (_, before) = account.positions_at(day(5));
+        assert_eq!(before, HashMap::from([("FB".to_string(), 2.0), ("META".to_string(), 1.0)]));
+        let (_, after) = account.positions_at(day(10));
+        assert_eq!(after, HashMap::from([("META".to_string(), 3.0)]));
+    }
+
+    #[test]
+    fn test_spinoff_and_merger(){
+        let mut account = InvestmentAccount::new(1, dec!(1000.0), None);
+        account.purchase_investment("GE".to_string(), dec!(100.0), 4.0).unwrap();
+        account.apply_corporate_action(CorporateAction::Spinoff{
+            parent: "GE".to_string(),
+            child: "GEHC".to_string(),
+            ratio: 0.5,
+            basis_fraction: dec!(0.25),
+        }).unwrap();
+        assert_eq!(account.assets["GE"].quantity, 4.0);
+        assert_eq!(account.assets["GE"].average_cost_per_unit, dec!(75.0));
+        assert_eq!(account.assets["GEHC"].quantity, 2.0);
+        assert_eq!(account.assets["GEHC"].average_cost_per_unit, dec!(50.0));
+
+        account.apply_corporate_action(CorporateAction::Merger{ target: "GEHC".to_string(), acquirer: "XYZ".to_string(), ratio: 2.0 }).unwrap();
+        assert!(!account.assets.contains_key("GEHC"));
+        assert_eq!(account.assets["XYZ"].quantity, 4.0);
+        assert_eq!(account.assets["XYZ"].average_cost_per_unit, dec!(25.0));
+        assert_eq!(account.get_balance(), dec!(600.0));
+        assert!(matches!(
+            account.apply_corporate_action(CorporateAction::Merger{ target: "GE".to_string(), acquirer: "XYZ".to_string(), ratio: 0.0 }),
+            Err(error::BankError::InvalidAmount)
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+use chrono::{DateTime, Duration, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{accounts::InvestmentAccount, money};
+
+/// The number of days after a losing sale in which a repurchase is a wash sale
+pub const WASH_SALE_WINDOW_DAYS: i64 = 30;
+/// The number of days a lot must be held past to be taxed as a long term gain
+pub const LONG_TERM_HOLDING_DAYS: i64 = 365;
+
+/// A sale of part or all of a holding, recorded for realized gains and tax reporting
+/// The cost basis is the holding's average cost at the time of sale.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClosedLot{
+    pub symbol: String,
+    pub quantity: f64,
+    pub cost_basis: Decimal,
+    pub proceeds: Decimal,
+    pub closed_at: DateTime<Utc>,
+    /// The part of the loss disallowed because the symbol was bought back within the wash sale window
+    #[serde(default)]
+    pub wash_sale_disallowed: Option<Decimal>,
+    /// When the holding the lot was sold from was first bought, if known
+    #[serde(default)]
+    pub opened_at: Option<DateTime<Utc>>,
+}
+
+impl ClosedLot{
+    pub fn new(symbol: String, quantity: f64, cost_basis: Decimal, proceeds: Decimal, closed_at: DateTime<Utc>) -> Self{
+        ClosedLot{
+            symbol,
+            quantity,
+            cost_basis,
+            proceeds,
+            closed_at,
+            wash_sale_disallowed: None,
+            opened_at: None,
+        }
+    }
+
+    pub fn with_opened_at(mut self, opened_at: DateTime<Utc>) -> Self{
+        self.opened_at = Some(opened_at);
+        self
+    }
+
+    /// The realized gain with the loss disallowed by wash sales added back
+    pub fn taxable_gain(&self) -> Decimal{
+        self.realized_gain() + self.wash_sale_disallowed.unwrap_or(Decimal::ZERO)
+    }
+
+    /// Whether the lot was held for more than a year. Lots with an unknown opening are short term
+    pub fn is_long_term(&self) -> bool{
+        self.opened_at.is_some_and(|opened_at| self.closed_at - opened_at > Duration::days(LONG_TERM_HOLDING_DAYS))
+    }
+
+    /// How many more days the lot would have to be held for to be long term,
+    /// or `None` if it already is, or its opening is unknown
+    pub fn days_until_long_term(&self) -> Option<i64>{
+        if self.is_long_term(){
+            return None;
+        }
+        self.opened_at.map(|opened_at| LONG_TERM_HOLDING_DAYS + 1 - (self.closed_at - opened_at).num_days())
+    }
+
+    /// Proceeds minus cost basis
+    pub fn realized_gain(&self) -> Decimal{
+        self.proceeds - self.cost_basis
+    }
+
+    /// The loss that has not been disallowed by a wash sale, as a positive amount
+    fn allowed_loss(&self) -> Decimal{
+        let loss = -self.realized_gain().min(Decimal::ZERO);
+        loss - self.wash_sale_disallowed.unwrap_or(Decimal::ZERO)
+    }
+
+    /// Whether a purchase at the given date falls in this lot's wash sale window
+    fn in_wash_sale_window(&self, date: DateTime<Utc>) -> bool{
+        date >= self.closed_at && date <= self.closed_at + Duration::days(WASH_SALE_WINDOW_DAYS)
+    }
+}
+
+/// How the broker treats a purchase that would be a wash sale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum WashSaleRule{
+    /// Wash sales are not tracked
+    Off,
+    /// The purchase goes through, and the disallowed loss is recorded on the closed lot
+    #[default]
+    Advisory,
+    /// The purchase is rejected
+    Strict,
+}
+
+impl InvestmentAccount{
+    /// Whether buying the symbol at the given date would be a wash sale,
+    /// because it was sold at a loss within the wash sale window
+    pub fn would_wash_sale(&self, symbol: &str, date: DateTime<Utc>) -> bool{
+        self.closed_lots
+            .iter()
+            .any(|lot| lot.symbol == symbol && lot.in_wash_sale_window(date) && lot.allowed_loss() > Decimal::ZERO)
+    }
+
+    /// Records the loss disallowed by buying back the symbol at the given date
+    /// The repurchased quantity disallows a proportional part of each losing lot in the window, oldest first.
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the total loss disallowed by this purchase
+    pub fn record_wash_sale(&mut self, symbol: &str, quantity: f64, date: DateTime<Utc>) -> Decimal{
+        let mut remaining = quantity;
+        let mut disallowed = Decimal::ZERO;
+        for lot in self.closed_lots.iter_mut(){
+            if remaining <= 0.0{
+                break;
+            }
+            if lot.symbol != symbol || !lot.in_wash_sale_window(date) || lot.allowed_loss() <= Decimal::ZERO || lot.quantity <= 0.0{
+                continue;
+            }
+            let matched = remaining.min(lot.quantity);
+            let loss = -lot.realized_gain();
+            let portion = (loss * money::from_f64(matched / lot.quantity)).min(lot.allowed_loss());
+            lot.wash_sale_disallowed = Some(lot.wash_sale_disallowed.unwrap_or(Decimal::ZERO) + portion);
+            disallowed += portion;
+            remaining -= matched;
+        }
+        disallowed
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn test_closed_lot_recorded(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 4.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(12.0), 3.0).unwrap();
+        assert_eq!(account.closed_lots.len(), 1);
+        let lot = &account.closed_lots[0];
+        assert_eq!(lot.cost_basis, dec!(30.0));
+        assert_eq!(lot.proceeds, dec!(36.0));
+        assert_eq!(lot.realized_gain(), dec!(6.0));
+    }
+
+    #[test]
+    fn test_wash_sale(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 4.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(8.0), 4.0).unwrap();
+        let now = Utc::now();
+        assert!(account.would_wash_sale("AAPL", now));
+        assert!(!account.would_wash_sale("MSFT", now));
+        assert!(!account.would_wash_sale("AAPL", now + Duration::days(WASH_SALE_WINDOW_DAYS + 1)));
+
+        // buying back half the shares disallows half the loss
+        assert_eq!(account.record_wash_sale("AAPL", 2.0, now), dec!(4.0));
+        assert_eq!(account.closed_lots[0].wash_sale_disallowed, Some(dec!(4.0)));
+        assert_eq!(account.record_wash_sale("AAPL", 10.0, now), dec!(4.0));
+        assert!(!account.would_wash_sale("AAPL", now));
+    }
+
+    #[test]
+    fn test_gain_is_not_wash_sale(){
+        let mut account = InvestmentAccount::new(1, dec!(100.0), None);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 4.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(12.0), 4.0).unwrap();
+        assert!(!account.would_wash_sale("AAPL", Utc::now()));
+    }
+}
@@ -1,9 +1,16 @@
 use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::{Arc, Mutex}};
-use accounts::{CheckingAccount, AccountType, Account, InvestmentAccount};
+use accounts::{CheckingAccount, AccountType, Account, InvestmentAccount, TaxTreatment};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+
+use crate::{config::EngineConfig, state::{JsonFileStore, StateStore}};
 pub mod stock;
 pub mod transactions;
 pub mod accounts;
+pub mod audit;
+pub mod corporate_actions;
+pub mod lots;
+pub mod statements;
 
 /// A bank that holds accounts
 /// It does nothing as of now, but hold accounts
@@ -38,34 +45,99 @@ impl Bank{
         match account_type{
             AccountType::Checking => {
                 let id = self.checking_accounts.keys().max().unwrap_or(&0) + 1;
-                let account = CheckingAccount::new(id, 0.0, nickname);
+                let account = CheckingAccount::new(id, Decimal::ZERO, nickname);
                 self.checking_accounts.insert(id, account);
                 Ok(id)
             },
-            AccountType::Investment => {
-                let id = self.investment_accounts.keys().max().unwrap_or(&0) + 1;
-                let account = InvestmentAccount::new(id, 0.0, nickname);
-                self.investment_accounts.insert(id, account);
-                Ok(id)
-            },
+            AccountType::Investment => self.open_investment_account(nickname, TaxTreatment::default()),
         }
     }
 
+    /// Opens a new investment account, with an optional nickname, taxed as given.
+    /// `open_account` opens taxable investment accounts
+    pub fn open_investment_account(&mut self, nickname: Option<String>, tax_treatment: TaxTreatment) -> Result<u32, error::BankError>{
+        let id = self.investment_accounts.keys().max().unwrap_or(&0) + 1;
+        let account = InvestmentAccount::new(id, Decimal::ZERO, nickname).with_tax_treatment(tax_treatment);
+        self.investment_accounts.insert(id, account);
+        Ok(id)
+    }
+
     /// Closes an account
     pub fn close_account(&mut self, id: u32) -> Result<(), error::BankError>{
         if !self.checking_accounts.contains_key(&id){
             return Err(error::BankError::AccountNotFound);
         }
-        if self.checking_accounts.get(&id).unwrap().get_balance() != 0.0{
+        if !self.checking_accounts.get(&id).unwrap().get_balance().is_zero(){
             return Err(error::BankError::CloseAccountWithBalance);
         }
         self.checking_accounts.remove(&id);
         Ok(())
     }
 
+    /// Upgrades a checking account to an investment account, keeping its balance, nickname, creation date and transaction history.
+    /// The investment account keeps the id of the checking account, unless an investment account already has it,
+    /// as ids are numbered separately for each type, in which case it is given the next free investment id
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::AccountNotFound` if there is no checking account with the id
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the id of the investment account
+    pub fn upgrade_to_investment(&mut self, id: u32) -> Result<u32, error::BankError>{
+        let account = self.checking_accounts.remove(&id).ok_or(error::BankError::AccountNotFound)?;
+        let investment_id = match self.investment_accounts.contains_key(&id){
+            true => self.investment_accounts.keys().max().unwrap_or(&0) + 1,
+            false => id,
+        };
+        self.investment_accounts.insert(investment_id, InvestmentAccount::from_checking(account).with_id(investment_id));
+        Ok(investment_id)
+    }
+
+    /// Freezes an account, so that nothing can be deposited, withdrawn, bought or sold until it is unfrozen
+    pub fn freeze_account(&mut self, id: u32, account_type: AccountType) -> Result<(), error::BankError>{
+        self.set_frozen(id, account_type, true)
+    }
+
+    pub fn unfreeze_account(&mut self, id: u32, account_type: AccountType) -> Result<(), error::BankError>{
+        self.set_frozen(id, account_type, false)
+    }
+
+    fn set_frozen(&mut self, id: u32, account_type: AccountType, frozen: bool) -> Result<(), error::BankError>{
+        match account_type{
+            AccountType::Checking => self.get_checking_account_mut(id)?.set_frozen(frozen),
+            AccountType::Investment => self.get_investment_account_mut(id)?.set_frozen(frozen),
+        }
+        Ok(())
+    }
+
+    /// Saves the bank as a JSON file, as `JsonFileStore` does
     pub async fn save(&self, path: &str) -> Result<(), std::io::Error>{
-        let json = serde_json::to_string(self)?;
-        tokio::fs::write(path, json).await
+        JsonFileStore::new(path).save(self).await
+    }
+
+    /// Loads a bank saved as a JSON file, as `JsonFileStore` does
+    pub async fn load(path: &str) -> Result<Bank, std::io::Error>{
+        JsonFileStore::new(path).load().await
+    }
+
+    /// Loads the bank saved in the store, or creates a new one if nothing is saved there.
+    /// A new bank gets a default investment account funded with the config's starting cash, if any
+    pub async fn load_or_create(store: &dyn StateStore, config: &EngineConfig) -> Result<Bank, std::io::Error>{
+        match store.load().await{
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            loaded => return loaded,
+        }
+        let mut bank = Bank::empty();
+        if let Some(starting_cash) = config.starting_cash{
+            let id = bank.open_account(Some(String::from("Paper")), AccountType::Investment)
+                .map_err(std::io::Error::other)?;
+            bank.get_investment_account_mut(id)
+                .and_then(|account| account.deposit(starting_cash))
+                .map_err(std::io::Error::other)?;
+        }
+        Ok(bank)
     }
 
     pub fn get_investment_account(&self, id: u32) -> Result<&InvestmentAccount, error::BankError>{
@@ -108,6 +180,43 @@ impl Bank{
         &self.checking_accounts
     }
 
+    /// Sums the cash of every account, checking and investment. Holdings are not included
+    pub fn total_cash(&self) -> Decimal{
+        let (checking, investment) = self.total_cash_by_type();
+        checking + investment
+    }
+
+    /// Sums the cash of every account, split by type
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the cash in checking accounts, and the cash in investment accounts
+    pub fn total_cash_by_type(&self) -> (Decimal, Decimal){
+        let checking = self.checking_accounts.values().map(|account| account.get_balance()).sum();
+        let investment = self.investment_accounts.values().map(|account| account.get_balance()).sum();
+        (checking, investment)
+    }
+
+    /// Whether an account of either type has the given id
+    pub fn account_exists(&self, id: u32) -> bool{
+        self.checking_accounts.contains_key(&id) || self.investment_accounts.contains_key(&id)
+    }
+
+    /// Gets the type of the account with the given id
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::AccountNotFound` if no account has the id, or `BankError::AmbiguousAccount` if
+    /// one account of each type has it, as ids are numbered separately for each type, and the caller has to say which type it means
+    pub fn account_type(&self, id: u32) -> Result<AccountType, error::BankError>{
+        match (self.checking_accounts.contains_key(&id), self.investment_accounts.contains_key(&id)){
+            (true, false) => Ok(AccountType::Checking),
+            (false, true) => Ok(AccountType::Investment),
+            (true, true) => Err(error::BankError::AmbiguousAccount),
+            (false, false) => Err(error::BankError::AccountNotFound),
+        }
+    }
+
 }
 
 impl From<HashMap<u32, CheckingAccount>> for Bank{
@@ -141,6 +250,106 @@ impl std::string::ToString for Bank{
 }
 
 
+/// Conversions between the `f64` used for share quantities and API prices,
+/// and the `Decimal` used for every amount of money.
+pub mod money{
+    use rust_decimal::{prelude::{FromPrimitive, ToPrimitive}, Decimal};
+
+    /// Converts a float into a Decimal
+    /// Non-finite values do not represent an amount, and are converted to zero
+    pub fn from_f64(value: f64) -> Decimal{
+        Decimal::from_f64(value).unwrap_or(Decimal::ZERO)
+    }
+
+    /// Converts a Decimal into a float, for quantity arithmetic and statistics
+    pub fn to_f64(value: Decimal) -> f64{
+        value.to_f64().unwrap_or(0.0)
+    }
+
+    /// How amounts of money are written for people, such as in statements and reports
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct MoneyFormat{
+        /// Written before the amount, after any minus sign
+        pub currency_symbol: String,
+        /// The number of decimals amounts are rounded to
+        pub decimals: u32,
+        /// Separates every three digits of the whole part
+        pub thousands_separator: char,
+        pub decimal_separator: char,
+    }
+
+    impl Default for MoneyFormat{
+        /// US dollars, such as `$1,234.56`
+        fn default() -> Self{
+            MoneyFormat{
+                currency_symbol: String::from("$"),
+                decimals: 2,
+                thousands_separator: ',',
+                decimal_separator: '.',
+            }
+        }
+    }
+
+    impl MoneyFormat{
+        pub fn with_currency_symbol(mut self, currency_symbol: &str) -> Self{
+            self.currency_symbol = currency_symbol.to_string();
+            self
+        }
+
+        /// Uses `.` between thousands and `,` before the decimals, such as `1.234,56`
+        pub fn with_comma_decimals(mut self) -> Self{
+            self.thousands_separator = '.';
+            self.decimal_separator = ',';
+            self
+        }
+    }
+
+    /// Groups the digits of a whole number in threes
+    fn group_thousands(digits: &str, separator: char) -> String{
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, digit) in digits.chars().enumerate(){
+            if i > 0 && (digits.len() - i).is_multiple_of(3){
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+
+    /// Writes a number with its whole part grouped in thousands, rounded half away from zero
+    fn format_number(value: Decimal, decimals: u32, thousands_separator: char, decimal_separator: char) -> String{
+        let rounded = value.round_dp_with_strategy(decimals, rust_decimal::RoundingStrategy::MidpointAwayFromZero);
+        let sign = if rounded.is_sign_negative() && !rounded.is_zero(){ "-" } else { "" };
+        let digits = format!("{:.*}", decimals as usize, rounded.abs());
+        let (whole, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+        let mut formatted = format!("{}{}", sign, group_thousands(whole, thousands_separator));
+        if !fraction.is_empty(){
+            formatted.push(decimal_separator);
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+
+    /// Writes an amount of money for people, such as `$1,234.56` or `-$0.50`
+    pub fn format_money(amount: Decimal, format: &MoneyFormat) -> String{
+        let number = format_number(amount, format.decimals, format.thousands_separator, format.decimal_separator);
+        match number.strip_prefix('-'){
+            Some(number) => format!("-{}{}", format.currency_symbol, number),
+            None => format!("{}{}", format.currency_symbol, number),
+        }
+    }
+
+    /// Writes a share quantity for people, with thousands grouped and trailing zeros dropped, such as `1,234.5`
+    pub fn format_quantity(quantity: f64, decimals: u32) -> String{
+        let number = format_number(from_f64(quantity), decimals, ',', '.');
+        if number.contains('.'){
+            number.trim_end_matches('0').trim_end_matches('.').to_string()
+        }else{
+            number
+        }
+    }
+}
+
 pub mod error{
     use thiserror::Error;
     use tokio::time::error::Error;
@@ -151,12 +360,38 @@ pub mod error{
         AccountNotFound,
         #[error("Account already exists")]
         AccountAlreadyExists,
+        #[error("Both a checking and an investment account have the id")]
+        AmbiguousAccount,
         #[error("Insufficient funds")]
         InsufficientFunds,
         #[error("Cannot close account with balance")]
         CloseAccountWithBalance,
+        #[error("Amount must be positive")]
+        InvalidAmount,
         #[error("Insufficient quantity of investment")]
         InsufficientQuantity,
+        #[error("Holding not found")]
+        HoldingNotFound,
+        #[error("Transaction not found")]
+        TransactionNotFound,
+        #[error("Every API key has reached its rate limit")]
+        RateLimited,
+        #[error("Purchase would be a wash sale")]
+        WashSale,
+        #[error("Account is frozen")]
+        AccountFrozen,
+        #[error("Purchase would exceed the maximum position size")]
+        PositionLimitExceeded,
+        #[error("Sale would be a day trade over the pattern day trader limit")]
+        PatternDayTrader,
+        #[error("Market is closed")]
+        MarketClosed,
+        #[error("No price available for {0}")]
+        PriceUnavailable(String),
+        #[error("Order not found")]
+        OrderNotFound,
+        #[error("Invalid order: {0}")]
+        InvalidOrder(String),
         #[error("Tokio error: {0}")]
         OtherTokio(tokio::io::Error),
         #[error("AlphaVantage error: {0}")]
@@ -171,6 +406,7 @@ mod tests{
     use stock::Holding;
 
     use super::*;
+    use rust_decimal_macros::dec;
     use std::{rc::Rc, str::FromStr};
 
     #[test]
@@ -179,10 +415,27 @@ mod tests{
         let id = bank.open_account(None, AccountType::Checking).unwrap();
         let account = bank.checking_accounts.get(&id).unwrap();
         assert_eq!(account.get_id(), id);
-        assert_eq!(account.get_balance(), 0.0);
+        assert_eq!(account.get_balance(), dec!(0.0));
         assert_eq!(account.get_nickname(), None);
     }
 
+    #[test]
+    fn test_account_type(){
+        let mut bank = Bank::empty();
+        let checking = bank.open_account(None, AccountType::Checking).unwrap();
+        bank.open_account(None, AccountType::Checking).unwrap();
+        let investment = bank.open_account(None, AccountType::Investment).unwrap();
+        assert!(bank.account_exists(checking));
+        assert!(!bank.account_exists(3));
+        assert_eq!(bank.account_type(2).unwrap(), AccountType::Checking);
+        assert!(matches!(bank.account_type(3), Err(error::BankError::AccountNotFound)));
+        // the first account of each type shares an id
+        assert_eq!(checking, investment);
+        assert!(matches!(bank.account_type(checking), Err(error::BankError::AmbiguousAccount)));
+        bank.close_account(checking).unwrap();
+        assert_eq!(bank.account_type(investment).unwrap(), AccountType::Investment);
+    }
+
     #[test]
     fn test_bank_from_str(){
         let json = r#"{"checking_accounts":{}, "investment_accounts":{}}"#;
@@ -197,7 +450,7 @@ mod tests{
         let id = bank.open_account(Some("Nickname".to_string()), AccountType::Checking).unwrap();
         let account = bank.checking_accounts.get(&id).unwrap();
         assert_eq!(account.get_id(), id);
-        assert_eq!(account.get_balance(), 0.0);
+        assert_eq!(account.get_balance(), dec!(0.0));
         assert_eq!(account.get_nickname(), Some("Nickname".to_string()));
 
         let path = "tests/test_bank_save.json";
@@ -211,34 +464,58 @@ mod tests{
         assert_eq!(bank2.checking_accounts.len(), 1);
         let account2 = bank2.checking_accounts.get(&id).unwrap();
         assert_eq!(account2.get_id(), id);
-        assert_eq!(account2.get_balance(), 0.0);
+        assert_eq!(account2.get_balance(), dec!(0.0));
         assert_eq!(account2.get_nickname(), Some("Nickname".to_string()));
     }
 
+    #[test]
+    fn test_load_or_create(){
+        let config = EngineConfig{ starting_cash: Some(dec!(10000.0)), ..Default::default() };
+        let path = "tests/test_load_or_create.json";
+        let store = JsonFileStore::new(path);
+        let _ = std::fs::remove_file(path);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // a new bank is funded
+        let bank = runtime.block_on(Bank::load_or_create(&store, &config)).unwrap();
+        assert_eq!(bank.investment_accounts.len(), 1);
+        let account = bank.investment_accounts.values().next().unwrap();
+        assert_eq!(account.get_balance(), dec!(10000.0));
+
+        // a saved bank is loaded as it was
+        let mut bank = Bank::empty();
+        bank.open_account(None, AccountType::Checking).unwrap();
+        runtime.block_on(bank.save(path)).unwrap();
+        let bank2 = runtime.block_on(Bank::load_or_create(&store, &config)).unwrap();
+        assert_eq!(bank2.to_string(), bank.to_string());
+        assert!(bank2.investment_accounts.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_complex_bank_save(){
         let mut bank = Bank::empty();
         let id = bank.open_account(Some("Nickname".to_string()), AccountType::Checking).unwrap();
         let account = bank.checking_accounts.get_mut(&id).unwrap();
-        account.deposit(10.0);
-        assert_eq!(account.get_balance(), 10.0);
+        account.deposit(dec!(10.0)).unwrap();
+        assert_eq!(account.get_balance(), dec!(10.0));
         // 2nd checking
         let id2 = bank.open_account(Some("Nickname2".to_string()), AccountType::Checking).unwrap();
         let account = bank.checking_accounts.get_mut(&id2).unwrap();
-        account.deposit(20.0);
-        assert_eq!(account.get_balance(), 20.0);
+        account.deposit(dec!(20.0)).unwrap();
+        assert_eq!(account.get_balance(), dec!(20.0));
         // open investment
         let id3 = bank.open_account(Some("Investment".to_string()), AccountType::Investment).unwrap();
         let account = bank.investment_accounts.get_mut(&id3).unwrap();
-        account.deposit(40.0);
-        assert_eq!(account.get_balance(), 40.0);
+        account.deposit(dec!(40.0)).unwrap();
+        assert_eq!(account.get_balance(), dec!(40.0));
         // purchase stock
-        account.purchase_investment("AAPL".to_string(), 10., 2.).unwrap();
-        assert_eq!(account.get_balance(), 20.0);
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.).unwrap();
+        assert_eq!(account.get_balance(), dec!(20.0));
         assert_eq!(account.get_investments().len(), 1);
         // purchase second stock
-        account.purchase_investment("GOOGL".to_string(), 20., 1.).unwrap();
-        assert_eq!(account.get_balance(), 0.0);
+        account.purchase_investment("GOOGL".to_string(), dec!(20.0), 1.).unwrap();
+        assert_eq!(account.get_balance(), dec!(0.0));
         assert_eq!(account.get_investments().len(), 2);
 
 
@@ -254,28 +531,44 @@ mod tests{
         assert_eq!(bank2.checking_accounts.len(), 2);
         let account2 = bank2.checking_accounts.get(&id).unwrap();
         assert_eq!(account2.get_id(), id);
-        assert_eq!(account2.get_balance(), 10.0);
+        assert_eq!(account2.get_balance(), dec!(10.0));
         assert_eq!(account2.get_nickname(), Some("Nickname".to_string()));
         let account2 = bank2.checking_accounts.get(&id2).unwrap();
         assert_eq!(account2.get_id(), id2);
-        assert_eq!(account2.get_balance(), 20.0);
+        assert_eq!(account2.get_balance(), dec!(20.0));
         assert_eq!(account2.get_nickname(), Some("Nickname2".to_string()));
         // check investment accounts
         assert_eq!(bank2.investment_accounts.len(), 1);
         let account2 = bank2.investment_accounts.get(&id3).unwrap();
         assert_eq!(account2.get_id(), id3);
-        assert_eq!(account2.get_balance(), 0.);
+        assert_eq!(account2.get_balance(), dec!(0.0));
         assert_eq!(account2.get_nickname(), Some("Investment".to_string()));
         assert_eq!(account2.get_investments().len(), 2);
         let holding = account2.get_investments().get("AAPL").unwrap();
         assert_eq!(holding.quantity, 2.);
-        assert_eq!(holding.average_cost_per_unit, 10.);
+        assert_eq!(holding.average_cost_per_unit, dec!(10.0));
         let holding = account2.get_investments().get("GOOGL").unwrap();
         assert_eq!(holding.quantity, 1.);
-        assert_eq!(holding.average_cost_per_unit, 20.);
+        assert_eq!(holding.average_cost_per_unit, dec!(20.0));
 
     }
 
+    #[test]
+    fn test_legacy_float_bank(){
+        // banks saved before the move to Decimal stored money as JSON floats
+        let json = r#"{"checking_accounts":{"1":{"id":1,"balance":10.5,"nickname":null,"created_at":"2025-01-14T04:57:23.041775813Z","transactions":[{"transaction_type":"Deposit","amount":10.5,"date":"2025-01-14T04:57:23.041778691Z","description":null}]}},"investment_accounts":{"1":{"id":1,"balance":0.1,"nickname":null,"created_at":"2025-01-14T04:57:23.041788324Z","assets":{"AAPL":{"asset":{"symbol":"AAPL"},"average_cost_per_unit":19.95,"quantity":2.0}},"transactions":[]}}}"#;
+        let bank = Bank::from_str(json).unwrap();
+        assert_eq!(bank.get_checking_account(1).unwrap().get_balance(), dec!(10.5));
+        let account = bank.get_investment_account(1).unwrap();
+        assert_eq!(account.get_balance(), dec!(0.1));
+        assert_eq!(account.get_investments().get("AAPL").unwrap().average_cost_per_unit, dec!(19.95));
+        assert_eq!(account.get_investments().get("AAPL").unwrap().get_first_acquired(), chrono::DateTime::UNIX_EPOCH);
+
+        // once saved again, money is written as exact decimal strings
+        let bank2 = Bank::from_str(&bank.to_string()).unwrap();
+        assert_eq!(bank2.get_investment_account(1).unwrap().get_balance(), dec!(0.1));
+    }
+
     #[test]
     fn test_close_account(){
         let mut bank = Bank::empty();
@@ -285,8 +578,90 @@ mod tests{
 
         let id = bank.open_account(None, AccountType::Checking).unwrap();
         let account = bank.checking_accounts.get_mut(&id).unwrap();
-        account.deposit(10.0);
+        account.deposit(dec!(10.0)).unwrap();
         assert!(bank.close_account(id).is_err());
     }
 
+    #[test]
+    fn test_freeze_account(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        bank.freeze_account(id, AccountType::Investment).unwrap();
+
+        let account = bank.get_investment_account_mut(id).unwrap();
+        assert!(matches!(account.deposit(dec!(10.0)), Err(error::BankError::AccountFrozen)));
+        assert!(matches!(account.withdraw(dec!(10.0)), Err(error::BankError::AccountFrozen)));
+        assert!(matches!(account.purchase_investment("AAPL".to_string(), dec!(10.0), 1.0), Err(error::BankError::AccountFrozen)));
+        assert!(matches!(account.sell_investment("AAPL".to_string(), dec!(10.0), 1.0), Err(error::BankError::AccountFrozen)));
+        assert_eq!(account.get_balance(), dec!(80.0));
+
+        // the frozen flag is saved with the bank
+        let mut bank = Bank::from_str(&bank.to_string()).unwrap();
+        assert!(bank.get_investment_account(id).unwrap().is_frozen());
+        assert!(bank.freeze_account(id, AccountType::Checking).is_err());
+        bank.unfreeze_account(id, AccountType::Investment).unwrap();
+        assert!(bank.get_investment_account_mut(id).unwrap().withdraw(dec!(10.0)).is_ok());
+    }
+
+    #[test]
+    fn test_upgrade_to_investment(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(Some("savings".to_string()), AccountType::Checking).unwrap();
+        bank.get_checking_account_mut(id).unwrap().deposit(dec!(100.0)).unwrap();
+        let created_at = bank.get_checking_account(id).unwrap().get_created_at();
+
+        assert_eq!(bank.upgrade_to_investment(id).unwrap(), id);
+        assert!(bank.get_checking_account(id).is_err());
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_balance(), dec!(100.0));
+        assert_eq!(account.get_nickname(), Some("savings".to_string()));
+        assert_eq!(account.get_created_at(), created_at);
+        assert_eq!(account.transactions.len(), 1);
+
+        // both types already hold the id, so the upgraded account is given the next investment id
+        let id = bank.open_account(Some("spending".to_string()), AccountType::Checking).unwrap();
+        bank.open_account(None, AccountType::Investment).unwrap();
+        assert!(bank.get_investment_account(id).is_ok());
+        let upgraded = bank.upgrade_to_investment(id).unwrap();
+        assert_eq!(upgraded, 3);
+        assert!(bank.get_checking_account(id).is_err());
+        let account = bank.get_investment_account(upgraded).unwrap();
+        assert_eq!(account.get_id(), upgraded);
+        assert_eq!(account.get_nickname(), Some("spending".to_string()));
+        assert!(matches!(bank.upgrade_to_investment(99), Err(error::BankError::AccountNotFound)));
+    }
+
+    #[test]
+    fn test_total_cash(){
+        let mut bank = Bank::empty();
+        assert_eq!(bank.total_cash(), dec!(0.0));
+        let checking = bank.open_account(None, AccountType::Checking).unwrap();
+        let investment = bank.open_account(None, AccountType::Investment).unwrap();
+        let other = bank.open_account(None, AccountType::Checking).unwrap();
+        bank.get_checking_account_mut(checking).unwrap().deposit(dec!(10.5)).unwrap();
+        bank.get_checking_account_mut(other).unwrap().deposit(dec!(4.5)).unwrap();
+        let account = bank.get_investment_account_mut(investment).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+
+        assert_eq!(bank.total_cash_by_type(), (dec!(15.0), dec!(80.0)));
+        assert_eq!(bank.total_cash(), dec!(95.0));
+    }
+
+    #[test]
+    fn test_format_money(){
+        let usd = money::MoneyFormat::default();
+        assert_eq!(money::format_money(dec!(1234.555), &usd), "$1,234.56");
+        assert_eq!(money::format_money(dec!(-0.5), &usd), "-$0.50");
+        assert_eq!(money::format_money(dec!(1234567), &usd), "$1,234,567.00");
+        assert_eq!(money::format_money(dec!(-0.001), &usd), "$0.00");
+        let euro = money::MoneyFormat::default().with_currency_symbol("€").with_comma_decimals();
+        assert_eq!(money::format_money(dec!(1234.5), &euro), "€1.234,50");
+        assert_eq!(money::format_quantity(1234.5, 6), "1,234.5");
+        assert_eq!(money::format_quantity(3.0, 6), "3");
+    }
+
 }
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use super::{accounts::{Account, InvestmentAccount}, stock, transactions::TransactionType};
+
+/// Where an account's cash moved over a window of time
+/// 
+/// The flows reconcile: `opening_balance + net_change() == closing_balance`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CashFlowStatement{
+    /// The cash balance before the window
+    pub opening_balance: Decimal,
+    /// Deposits minus withdrawals
+    pub net_deposits: Decimal,
+    /// Cash spent on purchases minus proceeds from sales
+    pub net_investment: Decimal,
+    pub dividends: Decimal,
+    /// Commissions and other fees paid
+    pub fees: Decimal,
+    pub interest: Decimal,
+    /// The cash balance at the end of the window
+    pub closing_balance: Decimal,
+}
+
+impl CashFlowStatement{
+    /// The total change in cash over the window
+    pub fn net_change(&self) -> Decimal{
+        self.net_deposits - self.net_investment + self.dividends - self.fees + self.interest
+    }
+}
+
+impl InvestmentAccount{
+    /// Breaks down how the account's cash moved between start and end, inclusive
+    /// 
+    /// # Arguments
+    /// 
+    /// * `start` - The start of the window
+    /// * `end` - The end of the window
+    pub fn cash_flows(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> CashFlowStatement{
+        // the stored balance is the current one, so unwind anything after the window to find the closing balance
+        let after_window: Decimal = self.transactions
+            .iter()
+            .filter(|transaction| transaction.date > end)
+            .map(|transaction| transaction.cash_effect())
+            .sum();
+        let mut statement = CashFlowStatement{
+            opening_balance: Decimal::ZERO,
+            net_deposits: Decimal::ZERO,
+            net_investment: Decimal::ZERO,
+            dividends: Decimal::ZERO,
+            fees: Decimal::ZERO,
+            interest: Decimal::ZERO,
+            closing_balance: self.get_balance() - after_window,
+        };
+        for transaction in self.transactions.iter().filter(|transaction| transaction.date >= start && transaction.date <= end){
+            match transaction.transaction_type{
+                TransactionType::Deposit => statement.net_deposits += transaction.amount,
+                TransactionType::Withdraw => statement.net_deposits -= transaction.amount,
+                TransactionType::Purchase(_, _) => statement.net_investment += transaction.amount,
+                TransactionType::Sale(_, _) => statement.net_investment -= transaction.amount,
+                TransactionType::Dividend(_, _) => statement.dividends += transaction.amount,
+                TransactionType::Commission(_) => statement.fees += transaction.amount,
+                TransactionType::Interest => statement.interest += transaction.amount,
+                TransactionType::CorporateAction(_) => {}
+            }
+        }
+        statement.opening_balance = statement.closing_balance - statement.net_change();
+        statement
+    }
+
+    /// Gets the cash balance and the quantity of each holding as they were at the given date,
+    /// by unwinding every transaction after it
+    pub fn positions_at(&self, date: DateTime<Utc>) -> (Decimal, HashMap<String, f64>){
+        let mut cash = self.get_balance();
+        let mut quantities: HashMap<String, f64> = self.get_investments()
+            .iter()
+            .map(|(symbol, holding)| (symbol.clone(), holding.quantity))
+            .collect();
+        for transaction in self.transactions.iter().filter(|transaction| transaction.date > date){
+            cash -= transaction.cash_effect();
+            match &transaction.transaction_type{
+                TransactionType::Purchase(asset, quantity) => *quantities.entry(asset.symbol.clone()).or_default() -= quantity,
+                TransactionType::Sale(asset, quantity) => *quantities.entry(asset.symbol.clone()).or_default() += quantity,
+                TransactionType::CorporateAction(changes) => {
+                    for (asset, change) in changes{
+                        *quantities.entry(asset.symbol.clone()).or_default() -= change;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let epsilon = stock::quantity_epsilon(self.get_quantity_decimals());
+        quantities.retain(|_, quantity| quantity.abs() >= epsilon);
+        (cash, quantities)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use crate::bank::{stock::Asset, transactions::Transaction};
+
+    use super::*;
+
+    #[test]
+    fn test_cash_flows(){
+        let mut account = InvestmentAccount::new(1, dec!(0.0), None);
+        let start = Utc::now();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.withdraw(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 5.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(120.0), 2.0).unwrap();
+        account.charge_commission("AAPL".to_string(), dec!(1.5)).unwrap();
+        account.pay_dividend(Transaction::new(TransactionType::Dividend(Asset::new("AAPL".to_string()), 3.0), dec!(2.25), Utc::now(), None));
+        let end = Utc::now();
+
+        let statement = account.cash_flows(start, end);
+        assert_eq!(statement.opening_balance, dec!(0.0));
+        assert_eq!(statement.net_deposits, dec!(900.0));
+        assert_eq!(statement.net_investment, dec!(260.0));
+        assert_eq!(statement.dividends, dec!(2.25));
+        assert_eq!(statement.fees, dec!(1.5));
+        assert_eq!(statement.interest, dec!(0.0));
+        assert_eq!(statement.closing_balance, account.get_balance());
+        assert_eq!(statement.opening_balance + statement.net_change(), statement.closing_balance);
+    }
+
+    #[test]
+    fn test_cash_flows_window(){
+        let mut account = InvestmentAccount::new(1, dec!(0.0), None);
+        account.deposit(dec!(50.0)).unwrap();
+        let start = Utc::now();
+        account.deposit(dec!(25.0)).unwrap();
+        let end = Utc::now();
+        account.deposit(dec!(10.0)).unwrap();
+
+        let statement = account.cash_flows(start, end);
+        assert_eq!(statement.opening_balance, dec!(50.0));
+        assert_eq!(statement.net_deposits, dec!(25.0));
+        assert_eq!(statement.closing_balance, dec!(75.0));
+    }
+
+    #[test]
+    fn test_positions_at(){
+        let mut account = InvestmentAccount::new(1, dec!(0.0), None);
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 5.0).unwrap();
+        let middle = Utc::now();
+        account.sell_investment("AAPL".to_string(), dec!(120.0), 5.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 1.0).unwrap();
+        // the later trades happen after the middle, even if the clock has not moved
+        for transaction in account.transactions[2..].iter_mut(){
+            transaction.date = middle + chrono::Duration::seconds(1);
+        }
+
+        let (cash, quantities) = account.positions_at(middle);
+        assert_eq!(cash, dec!(500.0));
+        assert_eq!(quantities.len(), 1);
+        assert_eq!(quantities["AAPL"], 5.0);
+    }
+}
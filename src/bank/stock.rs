@@ -1,26 +1,54 @@
-use chrono::FixedOffset;
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
+/// The number of decimals share quantities are rounded to, unless an account says otherwise
+pub const DEFAULT_QUANTITY_DECIMALS: u32 = 6;
+
+/// Rounds a share quantity to the given number of decimals
+pub fn round_quantity(quantity: f64, decimals: u32) -> f64{
+    let scale = 10f64.powi(decimals as i32);
+    (quantity * scale).round() / scale
+}
+
+/// The largest difference between two quantities that are considered equal at the given number of decimals
+pub fn quantity_epsilon(decimals: u32) -> f64{
+    0.5 / 10f64.powi(decimals as i32)
+}
 
 /// An asset is a holding that represents a stock or a cryptocurrency.
 /// It has a total cost, a quantity, and a symbol.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Holding{
     pub asset: Asset,
-    pub average_cost_per_unit: f64,
+    pub average_cost_per_unit: Decimal,
     pub quantity: f64,
+    /// When the holding was opened. Later purchases do not change it.
+    /// Holdings saved before this was recorded load as the Unix epoch
+    #[serde(default = "unknown_acquisition")]
+    pub first_acquired: DateTime<Utc>,
+}
+
+fn unknown_acquisition() -> DateTime<Utc>{
+    DateTime::UNIX_EPOCH
 }
 
 impl Holding{
-    pub fn new(total_cost: f64, quantity: f64, symbol: String) -> Self{
+    /// Opens a holding, acquired now
+    pub fn new(total_cost: Decimal, quantity: f64, symbol: String) -> Self{
         Holding{
             average_cost_per_unit: total_cost,
             quantity: quantity,
             asset: Asset::new(symbol),
+            first_acquired: Utc::now(),
         }
     }
 
-    fn get_price(&self) -> f64{
+    pub fn get_first_acquired(&self) -> DateTime<Utc>{
+        self.first_acquired
+    }
+
+    fn get_price(&self) -> Decimal{
         self.average_cost_per_unit
     }
 
@@ -58,13 +86,13 @@ impl PartialEq for Asset{
 }
 
 pub struct Dividend{
-    pub amount: f64,
+    pub amount: Decimal,
     pub asset: Asset,
     pub date: chrono::DateTime<FixedOffset>,
 }
 
 impl Dividend{
-    pub fn new(amount: f64, asset: Asset, date: chrono::DateTime<FixedOffset>) -> Self{
+    pub fn new(amount: Decimal, asset: Asset, date: chrono::DateTime<FixedOffset>) -> Self{
         Dividend{
             amount: amount,
             asset: asset,
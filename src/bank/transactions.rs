@@ -1,7 +1,26 @@
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
 use super::stock::Asset;
 
+/// Tags the engine attaches to the transactions it creates
+pub mod tags{
+    /// A trade placed directly by the user
+    pub const MANUAL: &str = "manual";
+    /// A trade placed to rebalance the account
+    pub const REBALANCE: &str = "rebalance";
+    /// A dividend payment
+    pub const DIVIDEND: &str = "dividend";
+    /// A purchase made by reinvesting a dividend
+    pub const DRIP: &str = "DRIP";
+    /// A trade made when a pending order was triggered
+    pub const ORDER: &str = "order";
+    /// A purchase made by investing cash above the idle cash threshold
+    pub const IDLE_CASH: &str = "idle cash";
+    /// A sale made when every position of the account was closed at once
+    pub const LIQUIDATION: &str = "liquidation";
+}
+
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionType {
@@ -20,7 +39,15 @@ pub enum TransactionType {
     /// A dividend payment.
     /// The first parameter is the asset that paid the dividend.
     /// The second parameter is the quantity of stock that paid the dividend.
-    Dividend(Asset, f64)
+    Dividend(Asset, f64),
+    /// A commission paid on a trade.
+    /// The parameter is the asset that was traded.
+    Commission(Asset),
+    /// Interest credited to the account.
+    Interest,
+    /// A corporate action recorded by hand, such as a symbol change, spin-off or merger. Moves no cash.
+    /// The parameter is the change in quantity of each asset the action touched.
+    CorporateAction(Vec<(Asset, f64)>),
 }
 
 // equal transaction type
@@ -32,6 +59,9 @@ impl PartialEq for TransactionType {
             (TransactionType::Sale(a1, q1), TransactionType::Sale(a2, q2)) => a1 == a2 && q1 == q2,
             (TransactionType::Purchase(a1, q1), TransactionType::Purchase(a2, q2)) => a1 == a2 && q1 == q2,
             (TransactionType::Dividend(a1, q1), TransactionType::Dividend(a2, q2)) => a1 == a2 && q1 == q2,
+            (TransactionType::Commission(a1), TransactionType::Commission(a2)) => a1 == a2,
+            (TransactionType::Interest, TransactionType::Interest) => true,
+            (TransactionType::CorporateAction(c1), TransactionType::CorporateAction(c2)) => c1 == c2,
             _ => false,
         }
     }
@@ -42,20 +72,58 @@ pub struct Transaction {
     /// The type of transaction.
     pub transaction_type: TransactionType,
     /// The dollar amount of the transaction.
-    pub amount: f64,
+    pub amount: Decimal,
     /// The date and time of the transaction.
     pub date: chrono::DateTime<chrono::Utc>,
     /// A description of the transaction.
     pub description: Option<String>,
+    /// User and engine tags used to group transactions, such as "manual" or "rebalance".
+    /// Logs saved before tags existed load with no tags.
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 impl Transaction {
-    pub fn new(transaction_type: TransactionType, amount: f64, date: chrono::DateTime<chrono::Utc>, description: Option<String>) -> Self {
+    pub fn new(transaction_type: TransactionType, amount: Decimal, date: chrono::DateTime<chrono::Utc>, description: Option<String>) -> Self {
         Transaction {
             transaction_type: transaction_type,
             amount: amount,
             date: date,
             description: description,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Adds the given tags to the transaction
+    pub fn with_tags(mut self, tags: &[&str]) -> Self {
+        for tag in tags {
+            self.add_tag(tag);
+        }
+        self
+    }
+
+    /// Adds a tag to the transaction, if it does not already have it
+    pub fn add_tag(&mut self, tag: &str) {
+        if !self.has_tag(tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// The change in the account's cash caused by this transaction
+    pub fn cash_effect(&self) -> Decimal {
+        match self.transaction_type {
+            TransactionType::Deposit
+            | TransactionType::Sale(_, _)
+            | TransactionType::Dividend(_, _)
+            | TransactionType::Interest => self.amount,
+            TransactionType::Withdraw
+            | TransactionType::Purchase(_, _)
+            | TransactionType::Commission(_) => -self.amount,
+            TransactionType::CorporateAction(_) => Decimal::ZERO,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use tokio::sync::broadcast;
+
+use crate::bank::error::BankError;
+
+use super::{orders::{OrderId, OrderKind}, Broker};
+
+/// When a holding needs attention. Each check is skipped when its threshold is `None`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AttentionThresholds{
+    /// Flags holdings down more than this percent from their cost basis, such as `dec!(10)` for 10%
+    pub loss_percent: Option<Decimal>,
+    /// Flags holdings up more than this percent from their cost basis
+    pub gain_percent: Option<Decimal>,
+    /// Flags holdings priced within this percent above a pending stop
+    pub stop_distance_percent: Option<Decimal>,
+    /// Flags holdings with an ex-dividend date within this many days.
+    /// This looks up the dividends of every holding, which costs a request each
+    pub ex_dividend_within_days: Option<i64>,
+}
+
+/// Why a holding needs attention
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttentionReason{
+    /// The price is down this percent from the average cost
+    DownFromCost{ percent: Decimal },
+    /// The price is up this percent from the average cost
+    UpFromCost{ percent: Decimal },
+    /// The price is close to the stop of a pending order
+    NearStop{ order_id: OrderId, stop: Decimal },
+    /// The holding goes ex-dividend soon
+    ExDividendSoon{ date: NaiveDate },
+}
+
+/// A holding that meets one of the alert conditions. A holding meeting several appears once per reason
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttentionItem{
+    pub symbol: String,
+    pub price: Decimal,
+    pub reason: AttentionReason,
+}
+
+pub type AlertId = u64;
+
+/// How many fired alerts a subscriber can fall behind by before it starts missing them
+const ALERT_CHANNEL_CAPACITY: usize = 64;
+
+/// The price a symbol has to reach for an alert to fire
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertCondition{
+    /// Fires when the price is at or above this
+    Above(Decimal),
+    /// Fires when the price is at or below this
+    Below(Decimal),
+}
+
+impl AlertCondition{
+    pub fn is_met(&self, price: Decimal) -> bool{
+        match self{
+            AlertCondition::Above(threshold) => price >= *threshold,
+            AlertCondition::Below(threshold) => price <= *threshold,
+        }
+    }
+}
+
+/// A registered price alert
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceAlert{
+    pub id: AlertId,
+    pub symbol: String,
+    pub condition: AlertCondition,
+    /// Whether the alert stays registered after firing. A repeating alert fires again
+    /// only once the price has moved back out of the condition and into it again
+    pub repeat: bool,
+    armed: bool,
+}
+
+/// Sent to subscribers when a price alert fires
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent{
+    pub alert_id: AlertId,
+    pub symbol: String,
+    pub condition: AlertCondition,
+    /// The price that met the condition
+    pub price: Decimal,
+}
+
+/// The price alerts registered with a broker, and the channel they fire into
+#[derive(Debug)]
+pub struct PriceAlerts{
+    alerts: Vec<PriceAlert>,
+    next_id: AlertId,
+    events: broadcast::Sender<AlertEvent>,
+}
+
+impl Default for PriceAlerts{
+    fn default() -> Self{
+        PriceAlerts{
+            alerts: Vec::new(),
+            next_id: 0,
+            events: broadcast::channel(ALERT_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl PriceAlerts{
+    fn add(&mut self, symbol: &str, condition: AlertCondition, repeat: bool) -> AlertId{
+        let id = self.next_id;
+        self.next_id += 1;
+        self.alerts.push(PriceAlert{ id, symbol: symbol.to_string(), condition, repeat, armed: true });
+        id
+    }
+
+    /// Fires every armed alert whose condition the current prices meet, dropping the one-shot ones
+    fn check(&mut self, current_prices: &HashMap<String, Decimal>) -> Vec<AlertEvent>{
+        let mut fired = Vec::new();
+        for alert in self.alerts.iter_mut(){
+            let Some(price) = current_prices.get(&alert.symbol) else{
+                continue;
+            };
+            if !alert.condition.is_met(*price){
+                alert.armed = true;
+                continue;
+            }
+            if alert.armed{
+                alert.armed = false;
+                fired.push(AlertEvent{ alert_id: alert.id, symbol: alert.symbol.clone(), condition: alert.condition, price: *price });
+            }
+        }
+        self.alerts.retain(|alert| alert.repeat || !fired.iter().any(|event| event.alert_id == alert.id));
+        for event in &fired{
+            // nobody listening is not an error, the events are also returned
+            let _ = self.events.send(event.clone());
+        }
+        fired
+    }
+}
+
+impl Broker{
+    /// Registers an alert that fires once, the first time the symbol's price meets the condition, then is removed
+    pub fn add_price_alert(&mut self, symbol: &str, condition: AlertCondition) -> AlertId{
+        self.price_alerts.add(symbol, condition, false)
+    }
+
+    /// Registers an alert that stays registered after it fires. It fires each time the price moves into the condition,
+    /// rather than on every check while the price stays there
+    pub fn add_repeating_price_alert(&mut self, symbol: &str, condition: AlertCondition) -> AlertId{
+        self.price_alerts.add(symbol, condition, true)
+    }
+
+    /// Removes an alert, returning it if it was still registered
+    pub fn remove_price_alert(&mut self, id: AlertId) -> Option<PriceAlert>{
+        let index = self.price_alerts.alerts.iter().position(|alert| alert.id == id)?;
+        Some(self.price_alerts.alerts.remove(index))
+    }
+
+    pub fn get_price_alerts(&self) -> &[PriceAlert]{
+        &self.price_alerts.alerts
+    }
+
+    /// Gets a receiver for every alert that fires from now on
+    pub fn subscribe_alerts(&self) -> broadcast::Receiver<AlertEvent>{
+        self.price_alerts.events.subscribe()
+    }
+
+    /// Fires the alerts whose condition the given prices meet, sending each to the subscribers.
+    /// Alerts on symbols missing from the prices are left alone
+    ///
+    /// # Arguments
+    ///
+    /// * `current_prices` - The latest price of each symbol, such as from `price_stream`
+    ///
+    /// # Returns
+    ///
+    /// The alerts that fired
+    pub fn check_alerts(&mut self, current_prices: &HashMap<String, Decimal>) -> Vec<AlertEvent>{
+        self.price_alerts.check(current_prices)
+    }
+
+    /// Lists the holdings of an investment account that meet any of the alert conditions, such as for a notifications panel
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `thresholds` - Which conditions to check, and how far a holding must go to meet them
+    /// * `date_limit` - The date to price the holdings at, and to count upcoming ex-dividend dates from
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, a holding cannot be priced, or dividends are checked and cannot be loaded
+    pub async fn positions_needing_attention(&self, account_id: u32, thresholds: &AttentionThresholds, date_limit: Option<DateTime<FixedOffset>>) -> Result<Vec<AttentionItem>, BankError>{
+        let (_, holdings) = self.snapshot_holdings(account_id).await?;
+        let prices = self.price_holdings(&holdings, date_limit).await?;
+        let today = date_limit.map(|date| date.with_timezone(&Utc)).unwrap_or_else(Utc::now).date_naive();
+
+        let mut items = Vec::new();
+        for holding in &holdings{
+            let symbol = &holding.asset.symbol;
+            let price = prices[symbol];
+            let mut flag = |reason| items.push(AttentionItem{ symbol: symbol.clone(), price, reason });
+
+            if !holding.average_cost_per_unit.is_zero(){
+                let change = (price - holding.average_cost_per_unit) / holding.average_cost_per_unit * Decimal::ONE_HUNDRED;
+                if thresholds.loss_percent.is_some_and(|loss| -change > loss){
+                    flag(AttentionReason::DownFromCost{ percent: -change });
+                }
+                if thresholds.gain_percent.is_some_and(|gain| change > gain){
+                    flag(AttentionReason::UpFromCost{ percent: change });
+                }
+            }
+
+            if let Some(distance) = thresholds.stop_distance_percent{
+                let pending_stops = self.orders.get_orders()
+                    .iter()
+                    .filter(|order| order.account_id == account_id && order.symbol == *symbol && !order.status.is_final());
+                for order in pending_stops{
+                    if let OrderKind::StopSell{ stop } = order.kind{
+                        if price <= stop * (Decimal::ONE + distance / Decimal::ONE_HUNDRED){
+                            flag(AttentionReason::NearStop{ order_id: order.id, stop });
+                        }
+                    }
+                }
+            }
+
+            if let Some(days) = thresholds.ex_dividend_within_days{
+                let horizon = today + Duration::days(days);
+                let upcoming = self.provider.dividends(symbol).await?
+                    .data
+                    .into_iter()
+                    .filter_map(|dividend| dividend.ex_dividend_date)
+                    .filter(|date| *date >= today && *date <= horizon)
+                    .min();
+                if let Some(date) = upcoming{
+                    flag(AttentionReason::ExDividendSoon{ date });
+                }
+            }
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_positions_needing_attention(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 2.0).unwrap();
+        account.purchase_investment("GOOG".to_string(), dec!(10.0), 2.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(8.0))
+            .with_price("MSFT", date, dec!(13.0))
+            .with_price("GOOG", date, dec!(10.0)));
+        let entry = broker.place_bracket_order("AAPL", 2.0, dec!(9.0), dec!(12.0), dec!(7.9), id).unwrap();
+
+        let thresholds = AttentionThresholds{
+            loss_percent: Some(dec!(10)),
+            gain_percent: Some(dec!(25)),
+            stop_distance_percent: Some(dec!(5)),
+            ex_dividend_within_days: None,
+        };
+        let items = broker.positions_needing_attention(id, &thresholds, Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(items.contains(&AttentionItem{ symbol: "AAPL".to_string(), price: dec!(8.0), reason: AttentionReason::DownFromCost{ percent: dec!(20) } }));
+        assert!(items.contains(&AttentionItem{ symbol: "AAPL".to_string(), price: dec!(8.0), reason: AttentionReason::NearStop{ order_id: entry + 2, stop: dec!(7.9) } }));
+        assert!(items.contains(&AttentionItem{ symbol: "MSFT".to_string(), price: dec!(13.0), reason: AttentionReason::UpFromCost{ percent: dec!(30) } }));
+
+        let nothing = broker.positions_needing_attention(id, &AttentionThresholds::default(), Some(date.fixed_offset())).await.unwrap();
+        assert!(nothing.is_empty());
+    }
+
+    #[test]
+    fn test_price_alerts(){
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        let mut events = broker.subscribe_alerts();
+        let once = broker.add_price_alert("AAPL", AlertCondition::Above(dec!(150.0)));
+        let repeating = broker.add_repeating_price_alert("AAPL", AlertCondition::Below(dec!(100.0)));
+        let prices = |price| HashMap::from([("AAPL".to_string(), price)]);
+
+        assert!(broker.check_alerts(&prices(dec!(120.0))).is_empty());
+        let fired = broker.check_alerts(&prices(dec!(151.0)));
+        assert_eq!(fired, vec![AlertEvent{ alert_id: once, symbol: "AAPL".to_string(), condition: AlertCondition::Above(dec!(150.0)), price: dec!(151.0) }]);
+        assert_eq!(events.try_recv().unwrap(), fired[0]);
+        // the one-shot alert is gone
+        assert!(broker.check_alerts(&prices(dec!(160.0))).is_empty());
+        assert_eq!(broker.get_price_alerts().len(), 1);
+
+        // the repeating alert fires on entering the condition, not while staying in it
+        assert_eq!(broker.check_alerts(&prices(dec!(99.0))).len(), 1);
+        assert!(broker.check_alerts(&prices(dec!(98.0))).is_empty());
+        broker.check_alerts(&prices(dec!(105.0)));
+        assert_eq!(broker.check_alerts(&prices(dec!(97.0)))[0].alert_id, repeating);
+        assert!(broker.check_alerts(&HashMap::new()).is_empty());
+
+        assert!(broker.remove_price_alert(repeating).is_some());
+        assert!(broker.get_price_alerts().is_empty());
+    }
+}
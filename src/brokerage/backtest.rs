@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use chrono::{Days, Months, NaiveDate};
+use rust_decimal::Decimal;
+
+use crate::bank::{error::BankError, money, stock};
+
+use super::Broker;
+
+/// How often a dollar cost averaging backtest buys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DcaInterval{
+    Weekly,
+    Monthly,
+}
+
+impl DcaInterval{
+    /// The date of the nth contribution, counting the start as the zeroth.
+    /// Months are counted from the start, so a contribution on the 31st falls back to the end of short months
+    /// without drifting earlier for the rest of the backtest
+    fn nth_date(&self, start: NaiveDate, n: u32) -> Option<NaiveDate>{
+        match self{
+            DcaInterval::Weekly => start.checked_add_days(Days::new(7 * n as u64)),
+            DcaInterval::Monthly => start.checked_add_months(Months::new(n)),
+        }
+    }
+}
+
+/// One purchase of a dollar cost averaging backtest
+#[derive(Debug, Clone, PartialEq)]
+pub struct DcaPurchase{
+    /// The trading day the contribution was invested on
+    pub date: NaiveDate,
+    pub price: Decimal,
+    pub quantity: f64,
+    pub commission: Decimal,
+}
+
+/// The outcome of investing a fixed amount on a schedule
+#[derive(Debug, Clone, PartialEq)]
+pub struct DcaResult{
+    /// Every contribution added up
+    pub total_contributed: Decimal,
+    /// What was spent on shares and commissions. Less than contributed when only whole shares are bought
+    pub total_invested: Decimal,
+    /// The contributions not spent, which carry over to the next purchase
+    pub cash: Decimal,
+    pub shares: f64,
+    /// The last close in the window
+    pub ending_price: Decimal,
+    /// The shares at the ending price, plus the cash
+    pub ending_value: Decimal,
+    /// The ending value less the contributions
+    pub gain: Decimal,
+    /// Oldest first
+    pub purchases: Vec<DcaPurchase>,
+}
+
+impl Broker{
+    /// Runs a dollar cost averaging backtest over a series of daily closes.
+    /// Each contribution is invested at the first close on or after its scheduled date, and before the next one
+    pub(crate) fn dca_over_closes(&self, closes: &BTreeMap<NaiveDate, f64>, amount: Decimal, interval: DcaInterval, start: NaiveDate, end: NaiveDate) -> Result<DcaResult, BankError>{
+        if amount <= Decimal::ZERO{
+            return Err(BankError::InvalidAmount);
+        }
+        let (_, last_close) = closes.range(start..=end).next_back()
+            .ok_or_else(|| BankError::Other(format!("No closes between {} and {}", start, end)))?;
+        let ending_price = money::from_f64(*last_close);
+
+        let mut result = DcaResult{
+            total_contributed: Decimal::ZERO,
+            total_invested: Decimal::ZERO,
+            cash: Decimal::ZERO,
+            shares: 0.0,
+            ending_price,
+            ending_value: Decimal::ZERO,
+            gain: Decimal::ZERO,
+            purchases: Vec::new(),
+        };
+        let scheduled: Vec<NaiveDate> = (0..)
+            .map_while(|n| interval.nth_date(start, n))
+            .take_while(|date| *date <= end)
+            .collect();
+        for (i, date) in scheduled.iter().enumerate(){
+            // a contribution with no close before the next one, such as during a gap in the series, is skipped
+            let next = scheduled.get(i + 1).copied().unwrap_or(end + Days::new(1));
+            let Some((trading_day, close)) = closes.range(*date..next).next() else{
+                continue;
+            };
+            result.total_contributed += amount;
+            result.cash += amount;
+            let price = money::from_f64(*close);
+            let quantity = self.affordable_quantity(price, result.cash, stock::DEFAULT_QUANTITY_DECIMALS);
+            if quantity <= 0.0{
+                continue;
+            }
+            let cost = price * money::from_f64(quantity);
+            let commission = self.commission_schedule.commission(cost, quantity);
+            result.cash -= cost + commission;
+            result.total_invested += cost + commission;
+            result.shares += quantity;
+            result.purchases.push(DcaPurchase{ date: *trading_day, price, quantity, commission });
+        }
+        result.ending_value = ending_price * money::from_f64(result.shares) + result.cash;
+        result.gain = result.ending_value - result.total_contributed;
+        Ok(result)
+    }
+
+    /// Answers "what if I had invested this much every month?" by buying a symbol on a schedule against its daily closes.
+    /// Nothing is traded: the bank is not touched. The commission schedule and the fractional share setting apply,
+    /// slippage does not. The daily series is loaded once, through the cache
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol to buy
+    /// * `amount` - The amount contributed each interval
+    /// * `interval` - How often to contribute
+    /// * `start` - The date of the first contribution
+    /// * `end` - The last date to contribute on, and the date the result is valued at
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::InvalidAmount` if the amount is not positive,
+    /// or an error if the series cannot be loaded or has no closes in the window
+    pub async fn dca_backtest(&self, symbol: &str, amount: Decimal, interval: DcaInterval, start: NaiveDate, end: NaiveDate) -> Result<DcaResult, BankError>{
+        let series = self.get_time_series_daily_full(symbol).await?;
+        let closes = series.entries
+            .iter()
+            .map(|entry| Ok((entry.date.date_naive(), self.price_field.price(symbol, entry)?)))
+            .collect::<Result<BTreeMap<NaiveDate, f64>, BankError>>()?;
+        self.dca_over_closes(&closes, amount, interval, start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::bank::Bank;
+
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate{
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_dca_over_closes(){
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        // the 1st of March is a Friday, and the 1st of June a Saturday, so the June buy waits for Monday
+        let closes = BTreeMap::from([
+            (date(1, 2), 10.0),
+            (date(2, 1), 20.0),
+            (date(3, 1), 25.0),
+            (date(3, 15), 40.0),
+            (date(6, 3), 50.0),
+        ]);
+
+        let result = broker.dca_over_closes(&closes, dec!(100), DcaInterval::Monthly, date(1, 1), date(3, 31)).unwrap();
+        assert_eq!(result.purchases.iter().map(|purchase| purchase.date).collect::<Vec<_>>(), vec![date(1, 2), date(2, 1), date(3, 1)]);
+        assert_eq!(result.total_contributed, dec!(300));
+        assert_eq!(result.shares, 19.0);
+        assert_eq!(result.ending_price, dec!(40));
+        assert_eq!(result.ending_value, dec!(760));
+        assert_eq!(result.gain, dec!(460));
+
+        // whole shares only, so what is left of each contribution carries to the next
+        broker.set_fractional_shares(false);
+        let result = broker.dca_over_closes(&closes, dec!(30), DcaInterval::Monthly, date(1, 1), date(3, 31)).unwrap();
+        assert_eq!(result.shares, 5.0);
+        assert_eq!(result.total_invested, dec!(75));
+        assert_eq!(result.cash, dec!(15));
+
+        // nothing closes in May, so only the June contribution is made
+        let result = broker.dca_over_closes(&closes, dec!(100), DcaInterval::Monthly, date(5, 1), date(6, 30)).unwrap();
+        assert_eq!(result.total_contributed, dec!(100));
+        assert_eq!(result.purchases.len(), 1);
+        assert_eq!(result.purchases[0].date, date(6, 3));
+        assert!(matches!(broker.dca_over_closes(&closes, dec!(0), DcaInterval::Weekly, date(1, 1), date(3, 31)), Err(BankError::InvalidAmount)));
+        assert!(broker.dca_over_closes(&closes, dec!(100), DcaInterval::Weekly, date(7, 1), date(7, 31)).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use alphavantage::time_series::IntradayInterval;
+use futures::{stream, StreamExt};
+use tracing::{info, instrument};
+
+use crate::bank::error::BankError;
+
+use super::Broker;
+
+/// The kinds of series the broker loads from AlphaVantage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SeriesType{
+    /// The five minute intraday series used by `get_price`
+    Intraday,
+    Daily,
+    Weekly,
+    Monthly,
+    Dividends,
+    /// The symbol's listing, which `get_price` reads the market hours from when extended hours are off
+    Ticker,
+}
+
+/// The series a report on an account reads: the intraday series `get_price` values holdings with,
+/// and the daily closes and dividends of the analytics read alongside it.
+/// Without extended hours, `get_price` also reads the ticker, which `prefetch_for_account` warms as well
+pub const REPORT_SERIES: [SeriesType; 3] = [SeriesType::Intraday, SeriesType::Daily, SeriesType::Dividends];
+
+/// The outcome of warming the cache, per symbol and series type
+#[derive(Debug, Default)]
+pub struct WarmReport{
+    pub succeeded: Vec<(String, SeriesType)>,
+    pub failed: Vec<(String, SeriesType, BankError)>,
+}
+
+impl Broker{
+    /// Loads one series for a symbol and discards it.
+    /// The client caches every response, so this leaves the series in the cache.
+    async fn load_series(&self, symbol: &str, series_type: SeriesType) -> Result<(), BankError>{
+        match series_type{
+            SeriesType::Intraday => self.get_time_series_intraday(symbol, IntradayInterval::FiveMinutes).await.map(|_| ()),
+            SeriesType::Daily => self.get_time_series_daily_full(symbol).await.map(|_| ()),
+            SeriesType::Weekly => self.get_time_series_weekly_full(symbol).await.map(|_| ()),
+            SeriesType::Monthly => self.get_time_series_monthly_full(symbol).await.map(|_| ()),
+            SeriesType::Dividends => self.load_dividend_data(symbol).await.map(|_| ()),
+            SeriesType::Ticker => self.get_ticker(symbol.to_string()).await.map_err(BankError::OtherTokio)?.map(|_| ()),
+        }
+    }
+
+    /// Preloads every combination of the given symbols and series types, so that later reads hit the cache.
+    /// At most `get_max_concurrency` requests are made at a time, to avoid bursting past the API rate limit.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `symbols` - The symbols to load
+    /// * `series_types` - The series to load for each symbol
+    /// 
+    /// # Returns
+    /// 
+    /// Returns which combinations were loaded, and which failed along with the error
+    #[instrument(skip(self))]
+    pub async fn warm(&self, symbols: &[String], series_types: &[SeriesType]) -> WarmReport{
+        let jobs = symbols
+            .iter()
+            .flat_map(|symbol| series_types.iter().map(move |series_type| (symbol.clone(), *series_type)));
+        let results: Vec<(String, SeriesType, Result<(), BankError>)> = stream::iter(jobs)
+            .map(|(symbol, series_type)| async move {
+                let _permit = self.batch_permit().await;
+                let result = self.load_series(&symbol, series_type).await;
+                (symbol, series_type, result)
+            })
+            .buffer_unordered(self.max_concurrency)
+            .collect()
+            .await;
+
+        let mut report = WarmReport::default();
+        for (symbol, series_type, result) in results{
+            match result{
+                Ok(()) => report.succeeded.push((symbol, series_type)),
+                Err(e) => report.failed.push((symbol, series_type, e)),
+            }
+        }
+        info!(succeeded = report.succeeded.len(), failed = report.failed.len(), "warmed the cache");
+        report
+    }
+
+    /// Warms the cache with the series a report reads for every symbol an investment account holds,
+    /// so that a report generated afterwards is fast and does not need the network
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist. Series that fail to load are listed in the report
+    pub async fn prefetch_for_account(&self, account_id: u32) -> Result<WarmReport, BankError>{
+        let (_, holdings) = self.snapshot_holdings(account_id).await?;
+        let mut symbols: Vec<String> = holdings.into_iter().map(|holding| holding.asset.symbol).collect();
+        symbols.sort();
+        let mut series_types = REPORT_SERIES.to_vec();
+        if !self.extended_hours{
+            series_types.push(SeriesType::Ticker);
+        }
+        Ok(self.warm(&symbols, &series_types).await)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use tokio::sync::Mutex;
+
+    use crate::bank::{accounts::AccountType, Bank};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_prefetch_for_account(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+
+        // an account with no holdings needs nothing loaded
+        let report = broker.prefetch_for_account(id).await.unwrap();
+        assert!(report.succeeded.is_empty() && report.failed.is_empty());
+        assert!(matches!(broker.prefetch_for_account(id + 1).await, Err(BankError::AccountNotFound)));
+    }
+
+    #[tokio::test]
+    async fn test_report_after_prefetch_needs_no_network(){
+        use chrono::{TimeZone, Utc};
+        use rust_decimal_macros::dec;
+
+        use crate::{bank::accounts::Account, brokerage::{provider::mock::{self, MockProvider}, report::ReportFormat}};
+
+        let day = |day| Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit_at(dec!(1000.0), day(2)).unwrap();
+        account.purchase_investment_at("AAPL".to_string(), dec!(100.0), 5.0, day(2)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let provider = MockProvider::new()
+            .with_intraday("AAPL", vec![mock::bar(day(1), 100.0), mock::bar(day(3), 110.0)])
+            .with_daily("AAPL", vec![mock::bar(day(1), 100.0), mock::bar(day(3), 110.0)]);
+        broker.set_market_data_provider(provider.clone());
+
+        let report = broker.prefetch_for_account(id).await.unwrap();
+        assert!(report.failed.is_empty());
+        provider.go_offline();
+        let date_limit = Some(day(4).fixed_offset());
+        assert_eq!(broker.get_account_value(id, date_limit).await.unwrap(), dec!(1050.0));
+        assert!(broker.generate_report(id, date_limit, ReportFormat::Markdown).await.is_ok());
+    }
+}
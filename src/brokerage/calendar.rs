@@ -0,0 +1,236 @@
+use chrono::{Datelike, Days, NaiveDate, Weekday};
+use rust_decimal::Decimal;
+
+use super::Broker;
+
+/// Knows which days an exchange trades on
+pub trait TradingCalendar: Send + Sync {
+    fn is_trading_day(&self, date: NaiveDate) -> bool;
+
+    /// Gets the first trading day strictly after the given date
+    fn next_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut next = date + Days::new(1);
+        while !self.is_trading_day(next) {
+            next = next + Days::new(1);
+        }
+        next
+    }
+}
+
+/// The calendar of the US equity exchanges: weekdays, less the NYSE holidays.
+/// Early closes are treated as full trading days
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UsEquityCalendar;
+
+impl UsEquityCalendar {
+    /// Whether the exchange is closed for a holiday on the given date
+    pub fn is_holiday(&self, date: NaiveDate) -> bool {
+        let year = date.year();
+        let mut holidays = vec![
+            nth_weekday(year, 1, Weekday::Mon, 3),  // Martin Luther King Jr. Day
+            nth_weekday(year, 2, Weekday::Mon, 3),  // Washington's Birthday
+            easter(year) - Days::new(2),            // Good Friday
+            last_weekday(year, 5, Weekday::Mon),    // Memorial Day
+            observed(ymd(year, 7, 4)),              // Independence Day
+            nth_weekday(year, 9, Weekday::Mon, 1),  // Labor Day
+            nth_weekday(year, 11, Weekday::Thu, 4), // Thanksgiving
+            observed(ymd(year, 12, 25)),            // Christmas
+        ];
+        // New Year's Day is not moved back into the previous year when it falls on a Saturday
+        let new_year = ymd(year, 1, 1);
+        if new_year.weekday() != Weekday::Sat {
+            holidays.push(observed(new_year));
+        }
+        if year >= 2022 {
+            holidays.push(observed(ymd(year, 6, 19))); // Juneteenth
+        }
+        holidays.contains(&date)
+    }
+}
+
+impl TradingCalendar for UsEquityCalendar {
+    fn is_trading_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.is_holiday(date)
+    }
+}
+
+/// The number of trading days in a year, for the business day accrual basis
+pub const BUSINESS_DAYS_PER_YEAR: u32 = 252;
+
+/// How interest and fees are prorated over a period
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccrualBasis {
+    /// Every calendar day, over a 365 day year
+    #[default]
+    Actual365,
+    /// Every calendar day, over a 360 day year
+    Actual360,
+    /// Only trading days, over a year of `BUSINESS_DAYS_PER_YEAR`
+    BusinessDays,
+}
+
+impl AccrualBasis {
+    /// Gets the fraction of a year that accrues from the start up to, but not including, the end.
+    /// An end on or before the start accrues nothing
+    pub fn year_fraction(&self, start: NaiveDate, end: NaiveDate, calendar: &dyn TradingCalendar) -> Decimal {
+        let days = (end - start).num_days().max(0);
+        match self {
+            AccrualBasis::Actual365 => Decimal::from(days) / Decimal::from(365),
+            AccrualBasis::Actual360 => Decimal::from(days) / Decimal::from(360),
+            AccrualBasis::BusinessDays => {
+                let business_days = start.iter_days()
+                    .take(days as usize)
+                    .filter(|date| calendar.is_trading_day(*date))
+                    .count();
+                Decimal::from(business_days) / Decimal::from(BUSINESS_DAYS_PER_YEAR)
+            }
+        }
+    }
+}
+
+fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+/// A holiday on a Saturday is observed on the Friday before, and one on a Sunday on the Monday after
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Days::new(1),
+        Weekday::Sun => date + Days::new(1),
+        _ => date,
+    }
+}
+
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap()
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5)
+        .unwrap_or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+/// Easter Sunday in the Gregorian calendar, by the anonymous Gregorian algorithm
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    ymd(year, month as u32, day as u32)
+}
+
+impl Broker {
+    pub fn get_trading_calendar(&self) -> &dyn TradingCalendar {
+        self.calendar.as_ref()
+    }
+
+    /// Sets the calendar used to decide which days the market trades on
+    pub fn set_trading_calendar<C>(&mut self, calendar: C)
+    where
+        C: TradingCalendar + 'static,
+    {
+        self.calendar = Box::new(calendar);
+    }
+
+    /// Gets the simple interest, or fee, accrued on a principal at an annual rate from the start up to the end,
+    /// prorated by the accrual basis against the broker's trading calendar
+    ///
+    /// # Arguments
+    ///
+    /// * `principal` - The amount accruing
+    /// * `annual_rate` - The rate per year, as a fraction, so 0.05 is 5%
+    /// * `start` - The first day that accrues
+    /// * `end` - The day accrual stops, which does not accrue itself
+    /// * `basis` - How the period is counted as a fraction of a year
+    pub fn accrued_interest(&self, principal: Decimal, annual_rate: Decimal, start: NaiveDate, end: NaiveDate, basis: AccrualBasis) -> Decimal {
+        principal * annual_rate * basis.year_fraction(start, end, self.calendar.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_easter() {
+        assert_eq!(easter(2024), ymd(2024, 3, 31));
+        assert_eq!(easter(2025), ymd(2025, 4, 20));
+    }
+
+    #[test]
+    fn test_us_equity_holidays() {
+        let calendar = UsEquityCalendar;
+        let holidays_2024 = [
+            ymd(2024, 1, 1),
+            ymd(2024, 1, 15),
+            ymd(2024, 2, 19),
+            ymd(2024, 3, 29),
+            ymd(2024, 5, 27),
+            ymd(2024, 6, 19),
+            ymd(2024, 7, 4),
+            ymd(2024, 9, 2),
+            ymd(2024, 11, 28),
+            ymd(2024, 12, 25),
+        ];
+        for holiday in holidays_2024 {
+            assert!(!calendar.is_trading_day(holiday), "{} should be a holiday", holiday);
+        }
+        // Independence Day 2026 is a Saturday, observed on the Friday
+        assert!(!calendar.is_trading_day(ymd(2026, 7, 3)));
+        // New Year's Day 2022 was a Saturday, and was not observed
+        assert!(calendar.is_trading_day(ymd(2021, 12, 31)));
+        assert!(calendar.is_trading_day(ymd(2024, 1, 2)));
+        assert!(!calendar.is_trading_day(ymd(2024, 1, 6)));
+    }
+
+    #[test]
+    fn test_accrual_basis_over_weekend() {
+        let calendar = UsEquityCalendar;
+        // Friday to Tuesday accrues Friday through Monday: four calendar days, two of them trading days
+        let (start, end) = (ymd(2024, 1, 5), ymd(2024, 1, 9));
+        assert_eq!(AccrualBasis::Actual365.year_fraction(start, end, &calendar), Decimal::from(4) / Decimal::from(365));
+        assert_eq!(AccrualBasis::Actual360.year_fraction(start, end, &calendar), Decimal::from(4) / Decimal::from(360));
+        assert_eq!(AccrualBasis::BusinessDays.year_fraction(start, end, &calendar), Decimal::from(2) / Decimal::from(252));
+        assert_eq!(AccrualBasis::default().year_fraction(end, start, &calendar), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_accrued_interest() {
+        use std::sync::Arc;
+
+        use alphavantage::cache_enabled::client::Client;
+        use tokio::sync::Mutex;
+
+        use crate::bank::Bank;
+
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        let principal = Decimal::from(3600);
+        let rate = Decimal::new(5, 2);
+        let (start, end) = (ymd(2024, 1, 5), ymd(2024, 1, 9));
+        assert_eq!(broker.accrued_interest(principal, rate, start, end, AccrualBasis::Actual360).round_dp(2), Decimal::from(2));
+        // the weekend does not accrue on business days
+        assert!(broker.accrued_interest(principal, rate, start, end, AccrualBasis::BusinessDays)
+            < broker.accrued_interest(principal, rate, start, end, AccrualBasis::Actual365));
+    }
+
+    #[test]
+    fn test_next_trading_day() {
+        let calendar = UsEquityCalendar;
+        // Friday to Monday
+        assert_eq!(calendar.next_trading_day(ymd(2024, 1, 5)), ymd(2024, 1, 8));
+        // over the MLK weekend
+        assert_eq!(calendar.next_trading_day(ymd(2024, 1, 12)), ymd(2024, 1, 16));
+        // over Thanksgiving
+        assert_eq!(calendar.next_trading_day(ymd(2024, 11, 27)), ymd(2024, 11, 29));
+    }
+}
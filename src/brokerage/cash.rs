@@ -0,0 +1,297 @@
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
+use tracing::info;
+
+use crate::bank::{accounts::Account, error::BankError, lots::WashSaleRule, money, stock, transactions::tags};
+
+use super::Broker;
+
+/// What an investment account can spend, split by where the money stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CashBreakdown{
+    /// Cash that can be spent or withdrawn now
+    pub settled: Decimal,
+    /// Proceeds of sales that have not settled yet
+    pub unsettled: Decimal,
+    /// The most the account can buy, borrowing included
+    pub margin_buying_power: Decimal,
+}
+
+/// Whether a buy would go through, and if not, why
+#[derive(Debug)]
+pub enum Affordability{
+    Affordable,
+    /// The error the buy would fail with
+    Blocked(BankError),
+}
+
+impl Affordability{
+    pub fn is_affordable(&self) -> bool{
+        matches!(self, Affordability::Affordable)
+    }
+}
+
+impl Broker{
+    /// Gets what an investment account can spend
+    ///
+    /// Trades settle immediately and there is no margin, so all of the balance is settled
+    /// and the buying power is the balance.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist
+    pub async fn available_cash(&self, account_id: u32) -> Result<CashBreakdown, BankError>{
+        let balance = self.bank.lock().await.get_investment_account(account_id)?.get_balance();
+        Ok(CashBreakdown{
+            settled: balance,
+            unsettled: Decimal::ZERO,
+            margin_buying_power: balance.max(Decimal::ZERO),
+        })
+    }
+
+    /// Checks whether a buy would go through without making it, against everything `buy` checks:
+    /// the market being open, the account being frozen, the position limit, the wash sale rule,
+    /// and the settled cash covering the cost and commission at the expected fill price
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol of the stock to buy
+    /// * `quantity` - The quantity of the stock to buy
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date limit to get the price of the stock
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the check itself fails, such as when the account does not exist or the symbol cannot be priced
+    pub async fn can_afford(&self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Affordability, BankError>{
+        let price = self.slippage_model.fill_price(self.get_price(symbol, date_limit).await?, true);
+        if !self.is_market_open(symbol, date_limit.unwrap_or(chrono::Utc::now().into())).await?{
+            return Ok(Affordability::Blocked(BankError::MarketClosed));
+        }
+        let quantity = match self.apply_position_limit(account_id, symbol, price, quantity, date_limit).await{
+            Ok(quantity) => quantity,
+            Err(BankError::PositionLimitExceeded) => return Ok(Affordability::Blocked(BankError::PositionLimitExceeded)),
+            Err(e) => return Err(e),
+        };
+        let cost = price * money::from_f64(quantity);
+        let commission = self.commission_schedule.commission(cost, quantity);
+        let settled = self.available_cash(account_id).await?.settled;
+
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        if account.is_frozen(){
+            return Ok(Affordability::Blocked(BankError::AccountFrozen));
+        }
+        if settled < cost + commission{
+            return Ok(Affordability::Blocked(BankError::InsufficientFunds));
+        }
+        let trade_date = date_limit.map(|date| date.to_utc()).unwrap_or(chrono::Utc::now());
+        if self.wash_sale_rule == WashSaleRule::Strict && account.would_wash_sale(symbol, trade_date){
+            return Ok(Affordability::Blocked(BankError::WashSale));
+        }
+        Ok(Affordability::Affordable)
+    }
+
+    /// Whether buys sized by amount may buy fractions of a share
+    pub fn get_fractional_shares(&self) -> bool{
+        self.fractional_shares
+    }
+
+    /// Sets whether buys sized by amount may buy fractions of a share, or only whole shares
+    pub fn set_fractional_shares(&mut self, fractional_shares: bool){
+        self.fractional_shares = fractional_shares;
+    }
+
+    pub fn get_idle_cash_threshold(&self) -> Option<Decimal>{
+        self.idle_cash_threshold
+    }
+
+    /// Sets the cash an investment account may hold before `deploy_idle_cash` invests the rest, or removes the rule
+    pub fn set_idle_cash_threshold(&mut self, idle_cash_threshold: Option<Decimal>){
+        self.idle_cash_threshold = idle_cash_threshold;
+    }
+
+    /// The smallest quantity a buy sized by amount trades in: a whole share, or the smallest fraction the account keeps when fractions are allowed
+    pub(crate) fn quantity_step(&self, quantity_decimals: u32) -> f64{
+        if self.fractional_shares{
+            1.0 / 10f64.powi(quantity_decimals as i32)
+        }else{
+            1.0
+        }
+    }
+
+    /// The largest quantity whose cost plus commission fits in the budget, in steps of `quantity_step`
+    pub(crate) fn affordable_quantity(&self, price: Decimal, budget: Decimal, quantity_decimals: u32) -> f64{
+        if price <= Decimal::ZERO || budget <= Decimal::ZERO{
+            return 0.0;
+        }
+        let step = self.quantity_step(quantity_decimals);
+        let round_down = |quantity: f64| stock::round_quantity((quantity / step).floor() * step, quantity_decimals);
+        let total_cost = |quantity: f64| {
+            let cost = price * money::from_f64(quantity);
+            cost + self.commission_schedule.commission(cost, quantity)
+        };
+        // the commission depends on the quantity, so take it out of the budget until the quantity settles
+        let mut quantity = round_down(money::to_f64(budget / price));
+        for _ in 0..8{
+            if total_cost(quantity) <= budget{
+                break;
+            }
+            let commission = total_cost(quantity) - price * money::from_f64(quantity);
+            quantity = round_down(money::to_f64((budget - commission).max(Decimal::ZERO) / price));
+        }
+        while quantity > 0.0 && total_cost(quantity) > budget{
+            quantity = round_down(quantity - step);
+        }
+        quantity.max(0.0)
+    }
+
+    /// Buys as much of a stock as an amount of money pays for, commission and slippage included
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol of the stock to buy
+    /// * `notional` - The most to spend
+    /// * `account_id` - The id of the account to buy the stock for
+    /// * `date_limit` - The date limit to get the price of the stock
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as `buy` does, or `BankError::InsufficientFunds` if the amount does not pay for a single share
+    /// when only whole shares are allowed
+    ///
+    /// # Returns
+    ///
+    /// Returns the quantity bought
+    pub async fn buy_notional(&mut self, symbol: &str, notional: Decimal, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, BankError>{
+        self.buy_notional_tagged(symbol, notional, account_id, date_limit, tags::MANUAL).await
+    }
+
+    pub(crate) async fn buy_notional_tagged(&mut self, symbol: &str, notional: Decimal, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, tag: &str) -> Result<f64, BankError>{
+        let price = self.slippage_model.fill_price(self.get_price(symbol, date_limit).await?, true);
+        let quantity_decimals = self.bank.lock().await.get_investment_account(account_id)?.get_quantity_decimals();
+        let quantity = self.affordable_quantity(price, notional, quantity_decimals);
+        if quantity <= 0.0{
+            return Err(BankError::InsufficientFunds);
+        }
+        self.buy_tagged(symbol, quantity, account_id, date_limit, tag).await?;
+        Ok(quantity)
+    }
+
+    /// Invests an investment account's cash above the idle cash threshold in the target symbol
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `target_symbol` - The symbol to buy with the excess cash
+    /// * `date_limit` - The date limit to get the price of the stock
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as `buy` does, such as when the market is closed
+    ///
+    /// # Returns
+    ///
+    /// Returns the quantity bought, which is zero when there is no threshold, the cash is under it,
+    /// or the excess does not pay for a share
+    pub async fn deploy_idle_cash(&mut self, account_id: u32, target_symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, BankError>{
+        let Some(threshold) = self.idle_cash_threshold else {
+            return Ok(0.0);
+        };
+        let balance = self.bank.lock().await.get_investment_account(account_id)?.get_balance();
+        let excess = balance - threshold;
+        if excess <= Decimal::ZERO{
+            return Ok(0.0);
+        }
+        match self.buy_notional_tagged(target_symbol, excess, account_id, date_limit, tags::IDLE_CASH).await{
+            Ok(quantity) => {
+                info!(account_id, target_symbol, quantity, %excess, "deployed idle cash");
+                Ok(quantity)
+            },
+            Err(BankError::InsufficientFunds) => Ok(0.0),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::AccountType, Bank}, brokerage::{commission::CommissionSchedule, limits::{PositionLimit, PositionLimitAction}, prices::MockPriceSource}};
+
+    use super::*;
+
+    fn broker() -> (Broker, u32, DateTime<FixedOffset>){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("VTI", date, dec!(30.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+        (broker, id, date.fixed_offset())
+    }
+
+    #[tokio::test]
+    async fn test_buy_notional(){
+        let (mut broker, id, date) = broker();
+        broker.set_fractional_shares(false);
+        // 100 pays for 3 shares at 30 and the 1 commission
+        assert_eq!(broker.buy_notional("VTI", dec!(100.0), id, Some(date)).await.unwrap(), 3.0);
+        assert!(matches!(broker.buy_notional("VTI", dec!(20.0), id, Some(date)).await, Err(BankError::InsufficientFunds)));
+
+        broker.set_fractional_shares(true);
+        let quantity = broker.buy_notional("VTI", dec!(31.0), id, Some(date)).await.unwrap();
+        assert_eq!(quantity, 1.0);
+        let balance = broker.get_bank().lock().await.get_investment_account(id).unwrap().get_balance();
+        assert_eq!(balance, dec!(1000.0) - dec!(91.0) - dec!(31.0));
+
+        // fractions go no finer than the account keeps
+        broker.get_bank().lock().await.get_investment_account_mut(id).unwrap().set_quantity_decimals(2);
+        assert_eq!(broker.buy_notional("VTI", dec!(50.0), id, Some(date)).await.unwrap(), 1.63);
+    }
+
+    #[tokio::test]
+    async fn test_available_cash(){
+        let (mut broker, id, date) = broker();
+        broker.buy("VTI", 10.0, id, Some(date)).await.unwrap();
+        let cash = broker.available_cash(id).await.unwrap();
+        assert_eq!(cash, CashBreakdown{ settled: dec!(699.0), unsettled: Decimal::ZERO, margin_buying_power: dec!(699.0) });
+        assert!(broker.available_cash(id + 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_can_afford(){
+        let (mut broker, id, date) = broker();
+        assert!(broker.can_afford("VTI", 33.0, id, Some(date)).await.unwrap().is_affordable());
+        // 34 shares cost 1020 with the commission
+        assert!(matches!(broker.can_afford("VTI", 34.0, id, Some(date)).await.unwrap(), Affordability::Blocked(BankError::InsufficientFunds)));
+
+        broker.set_position_limit(Some(PositionLimit::new(dec!(0.5), PositionLimitAction::Reject)));
+        assert!(matches!(broker.can_afford("VTI", 20.0, id, Some(date)).await.unwrap(), Affordability::Blocked(BankError::PositionLimitExceeded)));
+        broker.get_bank().lock().await.freeze_account(id, AccountType::Investment).unwrap();
+        assert!(matches!(broker.can_afford("VTI", 1.0, id, Some(date)).await.unwrap(), Affordability::Blocked(BankError::AccountFrozen)));
+        // nothing was bought
+        assert_eq!(broker.available_cash(id).await.unwrap().settled, dec!(1000.0));
+    }
+
+    #[tokio::test]
+    async fn test_deploy_idle_cash(){
+        let (mut broker, id, date) = broker();
+        assert_eq!(broker.deploy_idle_cash(id, "VTI", Some(date)).await.unwrap(), 0.0);
+
+        broker.set_fractional_shares(false);
+        broker.set_idle_cash_threshold(Some(dec!(500.0)));
+        // 500 above the threshold buys 16 shares for 481
+        assert_eq!(broker.deploy_idle_cash(id, "VTI", Some(date)).await.unwrap(), 16.0);
+        let balance = broker.get_bank().lock().await.get_investment_account(id).unwrap().get_balance();
+        assert_eq!(balance, dec!(519.0));
+        assert_eq!(broker.deploy_idle_cash(id, "VTI", Some(date)).await.unwrap(), 0.0);
+    }
+}
@@ -0,0 +1,126 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::bank::money;
+
+/// One band of a tiered commission schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommissionTier{
+    /// The order notional at which this tier starts to apply, inclusive
+    pub from_notional: Decimal,
+    /// The commission as a fraction of the order notional, so 0.001 is 0.1%
+    pub rate: Decimal,
+}
+
+/// The commission charged on each order
+/// 
+/// The commission of an order is the flat fee, plus the per share fee, plus the rate of the tier
+/// the order's notional falls in, applied to the whole notional.
+/// If a per share cap is set, the total is limited to the cap times the number of shares.
+/// 
+/// The default schedule is commission free.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommissionSchedule{
+    /// A fee charged on every order
+    pub flat: Decimal,
+    /// A fee charged for every share traded
+    pub per_share: Decimal,
+    /// Percentage tiers by order notional. Notionals below the first tier pay no percentage.
+    pub tiers: Vec<CommissionTier>,
+    /// The most that can be charged per share traded
+    pub max_per_share: Option<Decimal>,
+}
+
+impl CommissionSchedule{
+    /// Creates a commission free schedule
+    pub fn free() -> Self{
+        CommissionSchedule::default()
+    }
+
+    /// Adds a percentage tier starting at the given notional
+    pub fn with_tier(mut self, from_notional: Decimal, rate: Decimal) -> Self{
+        self.tiers.push(CommissionTier{ from_notional, rate });
+        self
+    }
+
+    /// Finds the percentage rate for an order of the given notional
+    fn tier_rate(&self, notional: Decimal) -> Decimal{
+        self.tiers
+            .iter()
+            .filter(|tier| notional >= tier.from_notional)
+            .max_by_key(|tier| tier.from_notional)
+            .map(|tier| tier.rate)
+            .unwrap_or(Decimal::ZERO)
+    }
+
+    /// Computes the commission of an order
+    /// 
+    /// # Arguments
+    /// 
+    /// * `notional` - The value of the order, price times quantity
+    /// * `quantity` - The number of shares in the order
+    pub fn commission(&self, notional: Decimal, quantity: f64) -> Decimal{
+        let shares = money::from_f64(quantity.abs());
+        let commission = self.flat + self.per_share * shares + self.tier_rate(notional.abs()) * notional.abs();
+        match self.max_per_share{
+            Some(cap) => commission.min(cap * shares),
+            None => commission,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn tiered() -> CommissionSchedule{
+        CommissionSchedule::free()
+            .with_tier(dec!(10000), dec!(0.001))
+            .with_tier(dec!(100000), dec!(0.0005))
+    }
+
+    #[test]
+    fn test_free(){
+        assert_eq!(CommissionSchedule::free().commission(dec!(50000), 100.0), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_below_first_tier(){
+        assert_eq!(tiered().commission(dec!(9999.99), 10.0), Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_first_tier_boundary(){
+        assert_eq!(tiered().commission(dec!(10000), 10.0), dec!(10));
+        assert_eq!(tiered().commission(dec!(99999.99), 10.0), dec!(99.99999));
+    }
+
+    #[test]
+    fn test_second_tier_boundary(){
+        assert_eq!(tiered().commission(dec!(100000), 10.0), dec!(50));
+    }
+
+    #[test]
+    fn test_flat_and_per_share(){
+        let schedule = CommissionSchedule{
+            flat: dec!(1),
+            per_share: dec!(0.01),
+            ..CommissionSchedule::free()
+        };
+        assert_eq!(schedule.commission(dec!(500), 100.0), dec!(2));
+    }
+
+    #[test]
+    fn test_per_share_cap(){
+        let schedule = CommissionSchedule{
+            max_per_share: Some(dec!(0.005)),
+            ..tiered()
+        };
+        // 0.1% of 20,000 is 20, but 100 shares are capped at 0.50
+        assert_eq!(schedule.commission(dec!(20000), 100.0), dec!(0.5));
+        // 0.1% of 10,000 is 10, below the cap of 10,000 shares
+        assert_eq!(schedule.commission(dec!(10000), 10000.0), dec!(10));
+    }
+}
@@ -0,0 +1,53 @@
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use super::Broker;
+
+/// The number of requests batch operations have in flight at once, unless set otherwise
+pub const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+impl Broker{
+    pub fn get_max_concurrency(&self) -> usize{
+        self.max_concurrency
+    }
+
+    /// Sets how many requests batch operations, such as warming the cache and screening, have in flight at once.
+    /// The limit is shared, so two batches running together still stay under it. A limit of zero is treated as one
+    pub fn set_max_concurrency(&mut self, max_concurrency: usize){
+        self.max_concurrency = max_concurrency.max(1);
+        self.batch_permits = Semaphore::new(self.max_concurrency);
+    }
+
+    /// Waits for a slot under the concurrency limit. The slot is freed when the permit is dropped
+    pub(crate) async fn batch_permit(&self) -> SemaphorePermit<'_>{
+        self.batch_permits.acquire().await.expect("the batch semaphore is never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use tokio::sync::Mutex;
+
+    use crate::bank::Bank;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_max_concurrency(){
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        assert_eq!(broker.get_max_concurrency(), DEFAULT_MAX_CONCURRENCY);
+
+        broker.set_max_concurrency(2);
+        let first = broker.batch_permit().await;
+        let second = broker.batch_permit().await;
+        assert!(broker.batch_permits.try_acquire().is_err());
+        drop(first);
+        assert!(broker.batch_permits.try_acquire().is_ok());
+        drop(second);
+
+        broker.set_max_concurrency(0);
+        assert_eq!(broker.get_max_concurrency(), 1);
+    }
+}
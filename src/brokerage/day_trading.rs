@@ -0,0 +1,187 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Days, FixedOffset, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use tracing::info;
+
+use crate::bank::{error::BankError, transactions::{Transaction, TransactionType}};
+
+use super::Broker;
+
+/// How many calendar days back the window of trading days is searched for, so a calendar with too few trading days still ends the search
+const MAX_WINDOW_LOOKBACK_DAYS: u64 = 366;
+
+/// The FINRA pattern day trader rule: an account under an equity threshold may make only so many day trades,
+/// a purchase and a sale of the same symbol on the same day, over a rolling window of trading days
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PatternDayTraderRule{
+    /// Accounts worth at least this much, cash included, may day trade freely
+    pub equity_threshold: Decimal,
+    /// The most day trades an account under the threshold may make in the window
+    pub max_day_trades: usize,
+    /// The number of trading days in the window, counting the day of the trade
+    pub window_trading_days: usize,
+}
+
+impl Default for PatternDayTraderRule{
+    /// Three day trades in five trading days, under $25,000
+    fn default() -> Self{
+        PatternDayTraderRule{
+            equity_threshold: Decimal::from(25_000),
+            max_day_trades: 3,
+            window_trading_days: 5,
+        }
+    }
+}
+
+/// Finds the day trades among the transactions on the given days: each symbol that was bought and then sold on one day.
+/// A symbol counts once per day, however many times it went round
+fn day_trades(transactions: &[Transaction], days: &[NaiveDate]) -> HashSet<(NaiveDate, String)>{
+    let mut bought = HashSet::new();
+    let mut trades = HashSet::new();
+    for transaction in transactions.iter().filter(|transaction| days.contains(&transaction.date.date_naive())){
+        let day = transaction.date.date_naive();
+        match &transaction.transaction_type{
+            TransactionType::Purchase(asset, _) => {
+                bought.insert((day, asset.symbol.clone()));
+            },
+            TransactionType::Sale(asset, _) if bought.contains(&(day, asset.symbol.clone())) => {
+                trades.insert((day, asset.symbol.clone()));
+            },
+            _ => {},
+        }
+    }
+    trades
+}
+
+impl Broker{
+    pub fn get_pattern_day_trader_rule(&self) -> Option<PatternDayTraderRule>{
+        self.pattern_day_trader_rule
+    }
+
+    /// Sets the pattern day trader rule sales are checked against, or removes it. There is none by default
+    pub fn set_pattern_day_trader_rule(&mut self, pattern_day_trader_rule: Option<PatternDayTraderRule>){
+        self.pattern_day_trader_rule = pattern_day_trader_rule;
+    }
+
+    /// Gets the trading days of the rule's window that ends on the given day, latest first.
+    /// The window is cut short if the calendar has too few trading days in the year before
+    fn day_trade_window(&self, rule: &PatternDayTraderRule, day: NaiveDate) -> Vec<NaiveDate>{
+        let mut days = vec![day];
+        for back in 1..=MAX_WINDOW_LOOKBACK_DAYS{
+            if days.len() >= rule.window_trading_days{
+                break;
+            }
+            let previous = day - Days::new(back);
+            if self.calendar.is_trading_day(previous){
+                days.push(previous);
+            }
+        }
+        days
+    }
+
+    /// Checks a sale against the pattern day trader rule. Trades are placed on days by the UTC date they were recorded at,
+    /// and the sale by its date limit
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::PatternDayTrader` if the sale would be a day trade past the limit, in an account under the equity threshold,
+    /// or an error if the account does not exist or cannot be valued
+    pub(crate) async fn check_day_trade(&self, account_id: u32, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<(), BankError>{
+        let Some(rule) = self.pattern_day_trader_rule else {
+            return Ok(());
+        };
+        let today = date_limit.map(|date| date.to_utc()).unwrap_or_else(Utc::now).date_naive();
+        let days = self.day_trade_window(&rule, today);
+        let trades = {
+            let bank = self.bank.lock().await;
+            let transactions = &bank.get_investment_account(account_id)?.transactions;
+            let bought_today = transactions
+                .iter()
+                .any(|transaction| transaction.date.date_naive() == today && matches!(&transaction.transaction_type, TransactionType::Purchase(asset, _) if asset.symbol == symbol));
+            let trades = day_trades(transactions, &days);
+            if !bought_today || trades.contains(&(today, symbol.to_string())){
+                return Ok(());
+            }
+            trades
+        };
+        if trades.len() < rule.max_day_trades{
+            return Ok(());
+        }
+        let equity = self.get_account_value(account_id, date_limit).await?;
+        if equity >= rule.equity_threshold{
+            return Ok(());
+        }
+        info!(account_id, symbol, day_trades = trades.len(), %equity, "refused a day trade under the pattern day trader rule");
+        Err(BankError::PatternDayTrader)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::{calendar::TradingCalendar, prices::MockPriceSource}};
+
+    use super::*;
+
+    /// A calendar on which the market never opens
+    struct ClosedCalendar;
+
+    impl TradingCalendar for ClosedCalendar{
+        fn is_trading_day(&self, _date: NaiveDate) -> bool{
+            false
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fourth_day_trade_is_refused(){
+        // Monday the 8th through Wednesday the 17th of January 2024, with the MLK holiday on the 15th
+        let day = |day, hour| Some(Utc.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap().fixed_offset());
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let start = Utc.with_ymd_and_hms(2024, 1, 8, 14, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", start, dec!(10.0))
+            .with_price("MSFT", start, dec!(10.0)));
+        broker.set_pattern_day_trader_rule(Some(PatternDayTraderRule::default()));
+
+        // day trades on Monday, Tuesday and Wednesday, and an overnight hold from Thursday
+        for trade_day in [8, 9, 10]{
+            broker.buy("AAPL", 1.0, id, day(trade_day, 15)).await.unwrap();
+            broker.sell("AAPL", 1.0, id, day(trade_day, 16)).await.unwrap();
+        }
+        broker.buy("MSFT", 1.0, id, day(11, 15)).await.unwrap();
+        broker.buy("AAPL", 2.0, id, day(12, 15)).await.unwrap();
+        assert!(matches!(broker.sell("AAPL", 1.0, id, day(12, 16)).await, Err(BankError::PatternDayTrader)));
+        // selling what was held overnight is not a day trade
+        broker.sell("MSFT", 1.0, id, day(12, 16)).await.unwrap();
+        // an account over the threshold may day trade
+        broker.set_pattern_day_trader_rule(Some(PatternDayTraderRule{ equity_threshold: dec!(500.0), ..Default::default() }));
+        broker.sell("AAPL", 1.0, id, day(12, 16)).await.unwrap();
+
+        // by Wednesday the window has moved past Monday's and Tuesday's day trades
+        broker.set_pattern_day_trader_rule(Some(PatternDayTraderRule::default()));
+        broker.buy("AAPL", 1.0, id, day(17, 15)).await.unwrap();
+        broker.sell("AAPL", 1.0, id, day(17, 16)).await.unwrap();
+        // without the rule, nothing is refused
+        broker.buy("AAPL", 1.0, id, day(17, 15)).await.unwrap();
+        broker.set_pattern_day_trader_rule(None);
+        broker.sell("AAPL", 2.0, id, day(17, 16)).await.unwrap();
+    }
+
+    #[test]
+    fn test_day_trade_window_ends_without_trading_days(){
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        broker.set_trading_calendar(ClosedCalendar);
+        let day = NaiveDate::from_ymd_opt(2024, 1, 12).unwrap();
+        assert_eq!(broker.day_trade_window(&PatternDayTraderRule::default(), day), vec![day]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use alphavantage::corprate_actions::DividendEntry;
+use chrono::{DateTime, Duration, FixedOffset, Months, NaiveDate, Utc};
+use rust_decimal::Decimal;
+
+use crate::bank::{error::BankError, money, stock::Holding, transactions::{tags, TransactionType}};
+
+use super::Broker;
+
+/// Which date decides whether a holding is owed a dividend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DividendEligibility{
+    /// Shares held before the ex-dividend date are paid, as on a real exchange.
+    /// Falls back to the payment date when the ex-dividend date is not known
+    #[default]
+    ExDividendDate,
+    /// Shares held before the payment date are paid
+    PaymentDate,
+}
+
+impl DividendEligibility{
+    /// The date a holding must be owned before to be paid the dividend
+    pub fn record_cutoff(&self, dividend: &DividendEntry) -> Option<NaiveDate>{
+        match self{
+            DividendEligibility::ExDividendDate => dividend.ex_dividend_date.or(dividend.payment_date),
+            DividendEligibility::PaymentDate => dividend.payment_date,
+        }
+    }
+
+    /// The last instant at which holding the shares earns the dividend: the end of the day before the cutoff
+    pub(crate) fn held_at(&self, dividend: &DividendEntry) -> Option<DateTime<Utc>>{
+        self.record_cutoff(dividend)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|date| date.and_utc() - Duration::seconds(1))
+    }
+}
+
+/// One dividend reinvested into its stock
+#[derive(Debug, Clone, PartialEq)]
+pub struct DripEntry{
+    pub date: DateTime<Utc>,
+    /// The dividend cash that was reinvested
+    pub amount: Decimal,
+    pub price: Decimal,
+    pub shares_acquired: f64,
+    /// The shares held just after the reinvestment
+    pub shares_held: f64,
+}
+
+/// How reinvesting dividends grew a position
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DripReport{
+    pub symbol: String,
+    /// Each reinvestment, oldest first
+    pub entries: Vec<DripEntry>,
+    pub total_reinvested: Decimal,
+    pub total_shares_acquired: f64,
+}
+
+/// The projected annual dividend income of the holdings over what they cost, as a fraction.
+/// A holding missing from the projections earns nothing, but its cost still counts
+pub(crate) fn blended_yield_on_cost(holdings: &[Holding], annual_dividends: &HashMap<String, Decimal>) -> f64{
+    let mut income = Decimal::ZERO;
+    let mut cost_basis = Decimal::ZERO;
+    for holding in holdings{
+        let quantity = money::from_f64(holding.quantity);
+        income += annual_dividends.get(&holding.asset.symbol).copied().unwrap_or(Decimal::ZERO) * quantity;
+        cost_basis += holding.average_cost_per_unit * quantity;
+    }
+    if cost_basis <= Decimal::ZERO{
+        return 0.0;
+    }
+    money::to_f64(income / cost_basis)
+}
+
+impl Broker{
+    /// Lists every reinvested dividend of a symbol in an investment account: the purchases tagged `tags::DRIP`,
+    /// with the shares each bought and the shares held after it, counting every purchase, sale and corporate action
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist
+    pub async fn drip_report(&self, account_id: u32, symbol: &str) -> Result<DripReport, BankError>{
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        let mut report = DripReport{ symbol: symbol.to_string(), ..Default::default() };
+        let mut shares_held = 0.0;
+        for transaction in &account.transactions{
+            match &transaction.transaction_type{
+                TransactionType::Purchase(asset, quantity) if asset.symbol == symbol => {
+                    shares_held += quantity;
+                    if transaction.has_tag(tags::DRIP) && *quantity > 0.0{
+                        report.total_reinvested += transaction.amount;
+                        report.total_shares_acquired += quantity;
+                        report.entries.push(DripEntry{
+                            date: transaction.date,
+                            amount: transaction.amount,
+                            price: transaction.amount / money::from_f64(*quantity),
+                            shares_acquired: *quantity,
+                            shares_held,
+                        });
+                    }
+                },
+                TransactionType::Sale(asset, quantity) if asset.symbol == symbol => shares_held -= quantity,
+                TransactionType::CorporateAction(changes) => {
+                    shares_held += changes.iter().filter(|(asset, _)| asset.symbol == symbol).map(|(_, change)| change).sum::<f64>();
+                },
+                _ => {},
+            }
+        }
+        Ok(report)
+    }
+
+    /// Projects the dividends per share a symbol will pay over the next year as those it paid over the year up to the date limit,
+    /// by the dividend eligibility's cutoff date
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the dividend data cannot be loaded
+    pub async fn projected_annual_dividend(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError>{
+        let end = date_limit.map(|date| date.date_naive()).unwrap_or_else(|| Utc::now().date_naive());
+        let start = end - Months::new(12);
+        let total = self.load_dividend_data(symbol).await?
+            .data
+            .iter()
+            .filter(|dividend| self.dividend_eligibility.record_cutoff(dividend).is_some_and(|date| date > start && date <= end))
+            .map(|dividend| money::from_f64(dividend.amount))
+            .sum();
+        Ok(total)
+    }
+
+    /// Gets the yield on cost of an investment account: the projected annual dividends of its holdings over their total cost basis,
+    /// as a fraction. Holdings that pay no dividends still count toward the cost, and an account with no holdings yields nothing
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or the dividend data of a holding cannot be loaded
+    pub async fn yield_on_cost(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, BankError>{
+        let (_, holdings) = self.snapshot_holdings(account_id).await?;
+        let mut annual_dividends = HashMap::new();
+        for holding in holdings.iter(){
+            let symbol = &holding.asset.symbol;
+            annual_dividends.insert(symbol.clone(), self.projected_annual_dividend(symbol, date_limit).await?);
+        }
+        Ok(blended_yield_on_cost(&holdings, &annual_dividends))
+    }
+
+    pub fn get_dividend_eligibility(&self) -> DividendEligibility{
+        self.dividend_eligibility
+    }
+
+    /// Sets which date decides whether a holding is owed a dividend
+    pub fn set_dividend_eligibility(&mut self, dividend_eligibility: DividendEligibility){
+        self.dividend_eligibility = dividend_eligibility;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::bank::{accounts::{Account, AccountType}, Bank};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drip_report(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 10.0).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(12.5), 2.0).unwrap();
+        account.transactions.last_mut().unwrap().add_tag(tags::DRIP);
+        account.sell_investment("AAPL".to_string(), dec!(12.0), 4.0).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(8.0), 1.0).unwrap();
+        account.transactions.last_mut().unwrap().add_tag(tags::DRIP);
+        account.purchase_investment("MSFT".to_string(), dec!(5.0), 1.0).unwrap();
+        account.transactions.last_mut().unwrap().add_tag(tags::DRIP);
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+
+        let report = broker.drip_report(id, "AAPL").await.unwrap();
+        assert_eq!(report.entries.len(), 2);
+        assert_eq!(report.entries[0].price, dec!(12.5));
+        assert_eq!(report.entries[0].shares_held, 12.0);
+        assert_eq!(report.entries[1].amount, dec!(8.0));
+        assert_eq!(report.entries[1].shares_held, 9.0);
+        assert_eq!(report.total_reinvested, dec!(33.0));
+        assert_eq!(report.total_shares_acquired, 3.0);
+    }
+
+    #[test]
+    fn test_blended_yield_on_cost(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(50.0), 10.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(25.0), 20.0).unwrap();
+        let holdings: Vec<Holding> = account.get_investments().values().cloned().collect();
+
+        // AAPL pays 2 a share on a cost of 50, and MSFT pays nothing, but doubles the cost
+        let annual_dividends = HashMap::from([("AAPL".to_string(), dec!(2.0))]);
+        assert_eq!(blended_yield_on_cost(&holdings, &annual_dividends), 0.02);
+        assert_eq!(blended_yield_on_cost(&[], &annual_dividends), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_yield_on_cost_from_dividend_history(){
+        use crate::brokerage::provider::mock::{self, MockProvider};
+
+        let ymd = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(50.0), 10.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(25.0), 20.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_market_data_provider(MockProvider::new().with_dividends("AAPL", vec![
+            // more than a year before the date limit
+            mock::dividend(ymd(2022, 11, 4), ymd(2022, 11, 17), 0.4),
+            mock::dividend(ymd(2023, 2, 10), ymd(2023, 2, 16), 0.5),
+            mock::dividend(ymd(2023, 5, 12), ymd(2023, 5, 18), 0.5),
+            mock::dividend(ymd(2023, 8, 11), ymd(2023, 8, 17), 0.5),
+            mock::dividend(ymd(2023, 11, 10), ymd(2023, 11, 16), 0.5),
+            // after the date limit
+            mock::dividend(ymd(2024, 2, 9), ymd(2024, 2, 15), 0.6),
+        ]));
+        let date_limit = Some(Utc.with_ymd_and_hms(2024, 1, 15, 15, 0, 0).unwrap().fixed_offset());
+
+        assert_eq!(broker.projected_annual_dividend("AAPL", date_limit).await.unwrap(), dec!(2.0));
+        assert_eq!(broker.projected_annual_dividend("MSFT", date_limit).await.unwrap(), Decimal::ZERO);
+        // AAPL pays 20 a year on a cost of 500, and MSFT pays nothing on another 500
+        assert_eq!(broker.yield_on_cost(id, date_limit).await.unwrap(), 0.02);
+    }
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::bank::{error::BankError, transactions::TransactionType};
+
+use super::Broker;
+
+/// Everything an investment account paid in costs over a window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeSummary{
+    pub commissions: Decimal,
+    /// The cost of fills landing away from the quoted price, estimated with the current slippage model
+    pub slippage: Decimal,
+    /// Tax withheld from dividends. Dividends are paid in full, so this is zero
+    pub dividend_withholding: Decimal,
+    /// Fees charged to the account itself. No account fees are charged, so this is zero
+    pub account_fees: Decimal,
+    pub total: Decimal,
+}
+
+impl Broker{
+    /// Totals the costs an investment account paid between start and end
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `start` - Costs after this date are counted
+    /// * `end` - Costs up to and including this date are counted
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist
+    pub async fn fee_summary(&self, account_id: u32, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<FeeSummary, BankError>{
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        let mut summary = FeeSummary::default();
+        for transaction in account.transactions.iter().filter(|transaction| transaction.date > start && transaction.date <= end){
+            match transaction.transaction_type{
+                TransactionType::Commission(_) => summary.commissions += transaction.amount,
+                TransactionType::Purchase(..) => summary.slippage += self.slippage_model.slippage_in(transaction.amount, true),
+                TransactionType::Sale(..) => summary.slippage += self.slippage_model.slippage_in(transaction.amount, false),
+                _ => {},
+            }
+        }
+        summary.total = summary.commissions + summary.slippage + summary.dividend_withholding + summary.account_fees;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::{commission::CommissionSchedule, prices::MockPriceSource, slippage::SlippageModel}};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fee_summary(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(100.0)));
+        broker.set_slippage_model(SlippageModel::BasisPoints(dec!(10.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+        broker.buy("AAPL", 5.0, id, Some(date.fixed_offset())).await.unwrap();
+        broker.sell("AAPL", 5.0, id, Some(date.fixed_offset())).await.unwrap();
+
+        let summary = broker.fee_summary(id, date - chrono::Duration::days(1), date).await.unwrap();
+        assert_eq!(summary.commissions, dec!(2.0));
+        assert_eq!(summary.slippage.round_dp(6), dec!(1.0));
+        assert_eq!(summary.total.round_dp(6), dec!(3.0));
+        // the trades are dated at the date limit, not when the test ran
+        let recent = broker.fee_summary(id, date, Utc::now()).await.unwrap();
+        assert_eq!(recent, FeeSummary::default());
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::{NaiveDate, Utc};
+use disk_cache::cache_async;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use crate::bank::error::BankError;
+
+use super::{keys, Broker};
+
+const ALPHAVANTAGE_URL: &str = "https://www.alphavantage.co/query";
+
+/// A quarterly earnings report, with the estimated EPS next to the reported EPS
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EarningsReport{
+    pub fiscal_date_ending: NaiveDate,
+    pub reported_date: Option<NaiveDate>,
+    pub reported_eps: Option<f64>,
+    pub estimated_eps: Option<f64>,
+    pub surprise: Option<f64>,
+    pub surprise_percentage: Option<f64>,
+}
+
+/// The earnings history of a symbol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EarningsHistory{
+    pub symbol: String,
+    /// Quarterly reports, most recent first
+    pub quarterly: Vec<EarningsReport>,
+}
+
+/// An earnings report scheduled in the coming months
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpcomingEarnings{
+    pub symbol: String,
+    pub report_date: NaiveDate,
+    pub fiscal_date_ending: Option<NaiveDate>,
+    pub estimate: Option<f64>,
+}
+
+/// A company's profile and valuation ratios, from the OVERVIEW endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Overview{
+    pub symbol: String,
+    pub name: String,
+    pub exchange: Option<String>,
+    pub currency: Option<String>,
+    pub sector: Option<String>,
+    pub industry: Option<String>,
+    pub market_capitalization: Option<f64>,
+    pub pe_ratio: Option<f64>,
+    pub peg_ratio: Option<f64>,
+    pub eps: Option<f64>,
+    pub book_value: Option<f64>,
+    pub dividend_per_share: Option<f64>,
+    /// The trailing dividend yield, as a fraction of the price
+    pub dividend_yield: Option<f64>,
+    pub beta: Option<f64>,
+    pub fifty_two_week_high: Option<f64>,
+    pub fifty_two_week_low: Option<f64>,
+    pub shares_outstanding: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawOverview{
+    symbol: Option<String>,
+    name: Option<String>,
+    exchange: Option<String>,
+    currency: Option<String>,
+    sector: Option<String>,
+    industry: Option<String>,
+    market_capitalization: Option<String>,
+    #[serde(rename = "PERatio")]
+    pe_ratio: Option<String>,
+    #[serde(rename = "PEGRatio")]
+    peg_ratio: Option<String>,
+    #[serde(rename = "EPS")]
+    eps: Option<String>,
+    book_value: Option<String>,
+    dividend_per_share: Option<String>,
+    dividend_yield: Option<String>,
+    beta: Option<String>,
+    #[serde(rename = "52WeekHigh")]
+    fifty_two_week_high: Option<String>,
+    #[serde(rename = "52WeekLow")]
+    fifty_two_week_low: Option<String>,
+    shares_outstanding: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawEarnings{
+    #[serde(rename = "quarterlyEarnings", default)]
+    quarterly_earnings: Vec<RawQuarterlyEarnings>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawQuarterlyEarnings{
+    fiscal_date_ending: String,
+    #[serde(default)]
+    reported_date: Option<String>,
+    #[serde(rename = "reportedEPS", default)]
+    reported_eps: Option<String>,
+    #[serde(rename = "estimatedEPS", default)]
+    estimated_eps: Option<String>,
+    #[serde(default)]
+    surprise: Option<String>,
+    #[serde(default)]
+    surprise_percentage: Option<String>,
+}
+
+/// AlphaVantage writes missing values as "None"
+pub(crate) fn parse_number(value: Option<&str>) -> Option<f64>{
+    value.and_then(|value| value.trim().parse::<f64>().ok())
+}
+
+pub(crate) fn parse_date(value: Option<&str>) -> Option<NaiveDate>{
+    value.and_then(|value| NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok())
+}
+
+fn parse_earnings(symbol: &str, body: &str) -> Result<EarningsHistory, BankError>{
+    let raw: RawEarnings = serde_json::from_str(body).map_err(|e| BankError::Other(e.to_string()))?;
+    let quarterly = raw.quarterly_earnings
+        .into_iter()
+        .filter_map(|report| Some(EarningsReport{
+            fiscal_date_ending: parse_date(Some(&report.fiscal_date_ending))?,
+            reported_date: parse_date(report.reported_date.as_deref()),
+            reported_eps: parse_number(report.reported_eps.as_deref()),
+            estimated_eps: parse_number(report.estimated_eps.as_deref()),
+            surprise: parse_number(report.surprise.as_deref()),
+            surprise_percentage: parse_number(report.surprise_percentage.as_deref()),
+        }))
+        .collect();
+    Ok(EarningsHistory{ symbol: symbol.to_string(), quarterly })
+}
+
+/// Unknown symbols come back as an empty object
+fn parse_overview(body: &str) -> Result<Overview, BankError>{
+    let raw: RawOverview = serde_json::from_str(body).map_err(|e| BankError::Other(e.to_string()))?;
+    let symbol = raw.symbol.ok_or_else(|| BankError::Other(String::from("No overview found")))?;
+    let text = |value: Option<String>| value.filter(|value| !value.is_empty() && value != "None");
+    Ok(Overview{
+        name: raw.name.unwrap_or_else(|| symbol.clone()),
+        symbol,
+        exchange: text(raw.exchange),
+        currency: text(raw.currency),
+        sector: text(raw.sector),
+        industry: text(raw.industry),
+        market_capitalization: parse_number(raw.market_capitalization.as_deref()),
+        pe_ratio: parse_number(raw.pe_ratio.as_deref()),
+        peg_ratio: parse_number(raw.peg_ratio.as_deref()),
+        eps: parse_number(raw.eps.as_deref()),
+        book_value: parse_number(raw.book_value.as_deref()),
+        dividend_per_share: parse_number(raw.dividend_per_share.as_deref()),
+        dividend_yield: parse_number(raw.dividend_yield.as_deref()),
+        beta: parse_number(raw.beta.as_deref()),
+        fifty_two_week_high: parse_number(raw.fifty_two_week_high.as_deref()),
+        fifty_two_week_low: parse_number(raw.fifty_two_week_low.as_deref()),
+        shares_outstanding: parse_number(raw.shares_outstanding.as_deref()),
+    })
+}
+
+/// The calendar is CSV: symbol,name,reportDate,fiscalDateEnding,estimate,currency
+/// Fields are read from the ends of the line, as company names may contain commas
+fn parse_earnings_calendar(body: &str) -> Vec<UpcomingEarnings>{
+    body.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().split(',').collect();
+            if fields.len() < 6{
+                return None;
+            }
+            let n = fields.len();
+            Some(UpcomingEarnings{
+                symbol: fields[0].to_string(),
+                report_date: parse_date(Some(fields[n - 4]))?,
+                fiscal_date_ending: parse_date(Some(fields[n - 3])),
+                estimate: parse_number(Some(fields[n - 2])),
+            })
+        })
+        .collect()
+}
+
+impl Broker{
+    /// Makes a request to an AlphaVantage endpoint that the client does not cover
+    /// 
+    /// # Arguments
+    /// 
+    /// * `params` - The query parameters, without the API key
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the body of the response
+    #[instrument(level = "debug", skip(self), err(level = "warn"))]
+    pub(crate) async fn query_alphavantage(&self, params: &[(&str, &str)]) -> Result<String, BankError>{
+        let (index, key) = self.clients.next_key()?;
+        debug!(key = index, "querying AlphaVantage directly");
+        let body = reqwest::Client::new()
+            .get(ALPHAVANTAGE_URL)
+            .query(params)
+            .query(&[("apikey", key)])
+            .send()
+            .await
+            .map_err(|e| BankError::Other(e.to_string()))?
+            .text()
+            .await
+            .map_err(|e| BankError::Other(e.to_string()))?;
+        // errors and rate limits come back as a JSON note, rather than a status code
+        let is_note = ["\"Information\"", "\"Note\"", "\"Error Message\""]
+            .iter()
+            .any(|note| body.contains(note));
+        if body.trim_start().starts_with('{') && is_note{
+            if let Some(limit) = keys::rate_limit(&body){
+                self.clients.bench(index, limit);
+            }
+            return Err(BankError::Other(body));
+        }
+        Ok(body)
+    }
+
+    /// Gets the reported and estimated EPS history of a symbol
+    /// Cached for a day
+    #[cache_async(cache_root = "~/.cache/trading_engine/get_earnings/{symbol}", invalidate_rate = 86400)]
+    pub async fn get_earnings(&self, symbol: String) -> Result<EarningsHistory, BankError> {
+        let body = self.query_alphavantage(&[("function", "EARNINGS"), ("symbol", &symbol)]).await?;
+        parse_earnings(&symbol, &body)
+    }
+
+    /// Gets the earnings reports scheduled for a symbol over the next three months
+    /// Cached for a day
+    #[cache_async(cache_root = "~/.cache/trading_engine/get_earnings_calendar/{symbol}", invalidate_rate = 86400)]
+    pub async fn get_earnings_calendar(&self, symbol: String) -> Result<Vec<UpcomingEarnings>, BankError> {
+        let body = self.query_alphavantage(&[("function", "EARNINGS_CALENDAR"), ("symbol", &symbol), ("horizon", "3month")]).await?;
+        Ok(parse_earnings_calendar(&body))
+    }
+
+    /// Gets the profile and valuation ratios of a company, such as its sector, market cap, P/E and dividend yield
+    /// Cached for a day
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the symbol has no overview
+    #[cache_async(cache_root = "~/.cache/trading_engine/get_company_overview/{symbol}", invalidate_rate = 86400)]
+    pub async fn get_company_overview(&self, symbol: String) -> Result<Overview, BankError> {
+        let body = self.query_alphavantage(&[("function", "OVERVIEW"), ("symbol", &symbol)]).await?;
+        parse_overview(&body)
+    }
+
+    /// Gets the date of the next earnings report of a symbol, so strategies can avoid trading across it
+    /// 
+    /// # Returns
+    /// 
+    /// Returns `None` if no report is scheduled in the next three months
+    pub async fn next_earnings(&self, symbol: &str) -> Result<Option<NaiveDate>, BankError> {
+        let today = Utc::now().date_naive();
+        let calendar = self.get_earnings_calendar(symbol.to_string()).await.map_err(BankError::OtherTokio)??;
+        Ok(calendar
+            .iter()
+            .filter(|earnings| earnings.symbol == symbol && earnings.report_date >= today)
+            .map(|earnings| earnings.report_date)
+            .min())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_parse_earnings(){
+        let body = r#"{"symbol": "IBM", "annualEarnings": [], "quarterlyEarnings": [
+            {"fiscalDateEnding": "2024-12-31", "reportedDate": "2025-01-29", "reportedEPS": "3.92", "estimatedEPS": "3.78", "surprise": "0.14", "surprisePercentage": "3.7037", "reportTime": "post-market"},
+            {"fiscalDateEnding": "1996-03-31", "reportedDate": "1996-04-16", "reportedEPS": "1.51", "estimatedEPS": "None", "surprise": "0", "surprisePercentage": "None"}
+        ]}"#;
+        let history = parse_earnings("IBM", body).unwrap();
+        assert_eq!(history.quarterly.len(), 2);
+        assert_eq!(history.quarterly[0].reported_eps, Some(3.92));
+        assert_eq!(history.quarterly[0].reported_date, NaiveDate::from_ymd_opt(2025, 1, 29));
+        assert_eq!(history.quarterly[1].estimated_eps, None);
+    }
+
+    #[test]
+    fn test_parse_overview(){
+        let body = r#"{"Symbol": "IBM", "AssetType": "Common Stock", "Name": "International Business Machines", "Exchange": "NYSE", "Currency": "USD", "Sector": "TECHNOLOGY", "Industry": "COMPUTER & OFFICE EQUIPMENT", "MarketCapitalization": "225622130000", "PERatio": "36.5", "PEGRatio": "2.152", "BookValue": "29.04", "DividendPerShare": "6.67", "DividendYield": "0.0276", "EPS": "6.64", "Beta": "0.704", "52WeekHigh": "265.72", "52WeekLow": "161.53", "SharesOutstanding": "927267000"}"#;
+        let overview = parse_overview(body).unwrap();
+        assert_eq!(overview.symbol, "IBM");
+        assert_eq!(overview.sector.as_deref(), Some("TECHNOLOGY"));
+        assert_eq!(overview.market_capitalization, Some(225622130000.0));
+        assert_eq!(overview.pe_ratio, Some(36.5));
+        assert_eq!(overview.dividend_yield, Some(0.0276));
+        assert_eq!(overview.fifty_two_week_low, Some(161.53));
+
+        let unprofitable = r#"{"Symbol": "XYZ", "Name": "XYZ", "PERatio": "None", "DividendYield": "None"}"#;
+        let overview = parse_overview(unprofitable).unwrap();
+        assert_eq!(overview.pe_ratio, None);
+        assert_eq!(overview.dividend_yield, None);
+        assert!(parse_overview("{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_earnings_calendar(){
+        let body = "symbol,name,reportDate,fiscalDateEnding,estimate,currency\r\nIBM,International Business Machines Corp,2025-04-23,2025-03-31,1.42,USD\r\nXYZ,Some Company, Inc.,2025-05-01,2025-03-31,,USD\r\n";
+        let calendar = parse_earnings_calendar(body);
+        assert_eq!(calendar.len(), 2);
+        assert_eq!(calendar[0].report_date, NaiveDate::from_ymd_opt(2025, 4, 23).unwrap());
+        assert_eq!(calendar[0].estimate, Some(1.42));
+        assert_eq!(calendar[1].symbol, "XYZ");
+        assert_eq!(calendar[1].estimate, None);
+    }
+}
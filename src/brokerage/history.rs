@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::bank::error::BankError;
+
+use super::Broker;
+
+/// Where portfolio snapshots are appended unless the broker is given another path
+pub const DEFAULT_HISTORY_PATH: &str = "~/.trading_engine/history.jsonl";
+
+/// The value of an investment account at one moment, as recorded in the portfolio history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PortfolioSnapshot{
+    pub timestamp: DateTime<Utc>,
+    pub account_id: u32,
+    /// Cash plus equity
+    pub total_value: Decimal,
+    pub cash: Decimal,
+    /// The market value of every holding
+    pub equity: Decimal,
+}
+
+impl Broker{
+    pub fn get_history_path(&self) -> &Path{
+        &self.history_path
+    }
+
+    /// Sets the file portfolio snapshots are appended to and loaded from, one JSON object per line
+    pub fn set_history_path(&mut self, path: impl Into<PathBuf>){
+        self.history_path = path.into();
+    }
+
+    pub fn get_record_history(&self) -> bool{
+        self.record_history
+    }
+
+    /// Sets whether `get_account_value` appends a snapshot to the portfolio history every time it values an account.
+    /// It does not by default
+    pub fn set_record_history(&mut self, record_history: bool){
+        self.record_history = record_history;
+    }
+
+    /// Values an investment account and appends the snapshot to the portfolio history,
+    /// so that an equity curve builds up over real use without revaluing the past
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date limit to get prices at, which is also the time of the snapshot. Defaults to now
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, a holding cannot be priced, or the history cannot be written
+    pub async fn record_portfolio_snapshot(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<PortfolioSnapshot, BankError>{
+        let (cash, equity) = self.cash_and_equity(account_id, date_limit).await?;
+        self.append_portfolio_snapshot(account_id, cash, equity, date_limit).await
+    }
+
+    /// Appends the snapshot of an account valued at the date limit to the portfolio history
+    pub(crate) async fn append_portfolio_snapshot(&self, account_id: u32, cash: Decimal, equity: Decimal, date_limit: Option<DateTime<FixedOffset>>) -> Result<PortfolioSnapshot, BankError>{
+        let snapshot = PortfolioSnapshot{
+            timestamp: date_limit.map(|date| date.to_utc()).unwrap_or_else(Utc::now),
+            account_id,
+            total_value: cash + equity,
+            cash,
+            equity,
+        };
+        let mut line = serde_json::to_string(&snapshot)
+            .map_err(|e| BankError::Other(e.to_string()))?;
+        line.push('\n');
+        if let Some(parent) = self.history_path.parent().filter(|parent| !parent.as_os_str().is_empty()){
+            crate::state::create_private_dir_all(parent).map_err(BankError::OtherTokio)?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.history_path)
+            .await
+            .map_err(BankError::OtherTokio)?;
+        file.write_all(line.as_bytes()).await.map_err(BankError::OtherTokio)?;
+        Ok(snapshot)
+    }
+
+    /// Loads every recorded portfolio snapshot, of every account, in the order they were recorded.
+    /// Nothing has been recorded if the history file does not exist
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the history cannot be read, or a line of it is not a snapshot
+    pub async fn load_portfolio_history(&self) -> Result<Vec<PortfolioSnapshot>, BankError>{
+        let contents = match tokio::fs::read_to_string(&self.history_path).await{
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(BankError::OtherTokio(e)),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| BankError::Other(format!("Bad portfolio history entry: {}", e))))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_portfolio_history(){
+        let path = "tests/test_portfolio_history/history.jsonl";
+        let _ = std::fs::remove_dir_all("tests/test_portfolio_history");
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 2.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let (day1, day2) = (Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap(), Utc.with_ymd_and_hms(2024, 1, 3, 15, 0, 0).unwrap());
+        broker.set_price_source(MockPriceSource::new().with_series("AAPL", vec![(day1, dec!(100.0)), (day2, dec!(150.0))]));
+        broker.set_history_path(path);
+        assert!(broker.load_portfolio_history().await.unwrap().is_empty());
+
+        broker.record_portfolio_snapshot(id, Some(day1.fixed_offset())).await.unwrap();
+        let snapshot = broker.record_portfolio_snapshot(id, Some(day2.fixed_offset())).await.unwrap();
+        assert_eq!(snapshot.cash, dec!(800.0));
+        assert_eq!(snapshot.equity, dec!(300.0));
+        assert_eq!(snapshot.total_value, dec!(1100.0));
+
+        let history = broker.load_portfolio_history().await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].timestamp, day1);
+        assert_eq!(history[0].total_value, dec!(1000.0));
+        assert_eq!(history[1], snapshot);
+
+        // valuing the account records nothing until the broker is told to
+        assert_eq!(broker.get_account_value(id, Some(day2.fixed_offset())).await.unwrap(), dec!(1100.0));
+        assert_eq!(broker.load_portfolio_history().await.unwrap().len(), 2);
+        broker.set_record_history(true);
+        broker.get_account_value(id, Some(day1.fixed_offset())).await.unwrap();
+        let history = broker.load_portfolio_history().await.unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2], history[0]);
+        std::fs::remove_dir_all("tests/test_portfolio_history").unwrap();
+    }
+}
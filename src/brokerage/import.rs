@@ -0,0 +1,352 @@
+use std::{collections::{BTreeSet, HashMap}, path::Path};
+
+use chrono::DateTime;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::info;
+
+use crate::bank::{accounts::{Account, InvestmentAccount}, error::BankError, money, stock::{self, Asset}, transactions::{Transaction, TransactionType}};
+
+use super::Broker;
+
+/// What a transaction in a brokerage export does to the account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImportedKind{
+    Deposit,
+    Withdraw,
+    Buy,
+    Sell,
+    Dividend,
+}
+
+/// The names a brokerage export uses for each field the import reads. The export is an object holding
+/// a list of transactions, replayed oldest first, and optionally a list of holdings and the cash balance to check the replay against
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrokerageFieldMap{
+    /// The list of transactions
+    pub transactions: String,
+    /// The list of holdings, if the export has one
+    pub holdings: Option<String>,
+    /// The cash balance, if the export has one
+    pub cash: Option<String>,
+    /// The kind of a transaction
+    pub kind: String,
+    /// The date of a transaction, in RFC 3339
+    pub date: String,
+    pub symbol: String,
+    pub quantity: String,
+    /// The price per share of a trade
+    pub price: String,
+    /// The cash amount of a deposit, withdrawal or dividend
+    pub amount: String,
+    /// The commission of a trade, if the export has one, which is charged to the account as the trade's fee
+    pub commission: Option<String>,
+    /// The kind each value of the kind field stands for
+    pub kinds: HashMap<String, ImportedKind>,
+}
+
+impl Default for BrokerageFieldMap{
+    fn default() -> Self{
+        BrokerageFieldMap{
+            transactions: "transactions".to_string(),
+            holdings: Some("holdings".to_string()),
+            cash: Some("cash".to_string()),
+            kind: "type".to_string(),
+            date: "date".to_string(),
+            symbol: "symbol".to_string(),
+            quantity: "quantity".to_string(),
+            price: "price".to_string(),
+            amount: "amount".to_string(),
+            commission: Some("commission".to_string()),
+            kinds: HashMap::from([
+                ("deposit".to_string(), ImportedKind::Deposit),
+                ("withdraw".to_string(), ImportedKind::Withdraw),
+                ("buy".to_string(), ImportedKind::Buy),
+                ("sell".to_string(), ImportedKind::Sell),
+                ("dividend".to_string(), ImportedKind::Dividend),
+            ]),
+        }
+    }
+}
+
+/// The outcome of an import
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportReport{
+    /// The id of the investment account the export was imported into
+    pub account_id: u32,
+    pub transactions: usize,
+    /// The fields of the export the map does not name, which were ignored, as paths such as `transactions.commission`
+    pub unmapped_fields: Vec<String>,
+}
+
+fn field<'a>(object: &'a Map<String, Value>, name: &str, path: &str) -> Result<&'a Value, BankError>{
+    object.get(name).ok_or_else(|| BankError::Other(format!("Missing field {}.{} in brokerage export", path, name)))
+}
+
+fn string_field<'a>(object: &'a Map<String, Value>, name: &str, path: &str) -> Result<&'a str, BankError>{
+    field(object, name, path)?
+        .as_str()
+        .ok_or_else(|| BankError::Other(format!("Field {}.{} of brokerage export is not a string", path, name)))
+}
+
+/// Reads a number, which brokers write either as a JSON number or as a string
+fn decimal_field(object: &Map<String, Value>, name: &str, path: &str) -> Result<Decimal, BankError>{
+    let value = field(object, name, path)?;
+    let text = match value{
+        Value::String(text) => text.clone(),
+        Value::Number(number) => number.to_string(),
+        _ => String::new(),
+    };
+    text.parse().map_err(|_| BankError::Other(format!("Field {}.{} of brokerage export is not a number: {}", path, name, value)))
+}
+
+fn objects<'a>(value: &'a Value, path: &str) -> Result<Vec<&'a Map<String, Value>>, BankError>{
+    value
+        .as_array()
+        .ok_or_else(|| BankError::Other(format!("Field {} of brokerage export is not a list", path)))?
+        .iter()
+        .map(|item| item.as_object().ok_or_else(|| BankError::Other(format!("An entry of {} in brokerage export is not an object", path))))
+        .collect()
+}
+
+/// Collects the keys of the objects that are not among the mapped names, prefixed with the path of the objects
+fn unmapped<'a>(objects: impl IntoIterator<Item = &'a Map<String, Value>>, mapped: &[&str], path: Option<&str>, unmapped_fields: &mut BTreeSet<String>){
+    for object in objects{
+        for key in object.keys().filter(|key| !mapped.contains(&key.as_str())){
+            unmapped_fields.insert(path.map(|path| format!("{}.{}", path, key)).unwrap_or_else(|| key.clone()));
+        }
+    }
+}
+
+/// Replays the transactions of an export into an account, in order of date
+fn replay(account: &mut InvestmentAccount, transactions: &[&Map<String, Value>], map: &BrokerageFieldMap) -> Result<(), BankError>{
+    let path = map.transactions.as_str();
+    let mut parsed = Vec::new();
+    for transaction in transactions{
+        let kind = string_field(transaction, &map.kind, path)?;
+        let kind = *map.kinds
+            .get(kind)
+            .ok_or_else(|| BankError::Other(format!("Unknown transaction kind {} in brokerage export", kind)))?;
+        let date = DateTime::parse_from_rfc3339(string_field(transaction, &map.date, path)?)
+            .map_err(|e| BankError::Other(format!("Bad date in brokerage export: {}", e)))?
+            .to_utc();
+        parsed.push((date, kind, transaction));
+    }
+    // a stable sort, so transactions at the same time keep the order of the export
+    parsed.sort_by_key(|(date, _, _)| *date);
+
+    for (date, kind, transaction) in parsed{
+        match kind{
+            ImportedKind::Deposit => {
+                account.deposit_at(decimal_field(transaction, &map.amount, path)?, date)?;
+            },
+            ImportedKind::Withdraw => {
+                account.withdraw_at(decimal_field(transaction, &map.amount, path)?, date)?;
+            },
+            ImportedKind::Buy | ImportedKind::Sell => {
+                let symbol = string_field(transaction, &map.symbol, path)?.to_string();
+                let price = decimal_field(transaction, &map.price, path)?;
+                let quantity = money::to_f64(decimal_field(transaction, &map.quantity, path)?);
+                let commission = match &map.commission{
+                    Some(name) if transaction.contains_key(name) => decimal_field(transaction, name, path)?,
+                    _ => Decimal::ZERO,
+                };
+                if kind == ImportedKind::Buy{
+                    account.purchase_investment_at(symbol.clone(), price, quantity, date)?;
+                }else{
+                    account.sell_investment_at(symbol.clone(), price, quantity, date)?;
+                }
+                account.charge_commission_at(symbol, commission, date)?;
+            },
+            ImportedKind::Dividend => {
+                let symbol = string_field(transaction, &map.symbol, path)?.to_string();
+                let amount = decimal_field(transaction, &map.amount, path)?;
+                let quantity = match transaction.get(&map.quantity){
+                    Some(_) => money::to_f64(decimal_field(transaction, &map.quantity, path)?),
+                    None => account.get_investments().get(&symbol).map(|holding| holding.quantity).unwrap_or_default(),
+                };
+                account.pay_dividend(Transaction::new(TransactionType::Dividend(Asset::new(symbol), quantity), amount, date, None));
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Checks the replayed account against the holdings and cash the export reports
+fn reconcile(account: &InvestmentAccount, holdings: Option<&[&Map<String, Value>]>, cash: Option<Decimal>, map: &BrokerageFieldMap) -> Result<(), BankError>{
+    let mismatch = |detail: String| Err(BankError::Other(format!("Brokerage export does not reconcile: {}", detail)));
+    if let Some(cash) = cash{
+        if cash != account.get_balance(){
+            return mismatch(format!("the export has {} in cash, but its transactions leave {}", cash, account.get_balance()));
+        }
+    }
+    let Some(holdings) = holdings else {
+        return Ok(());
+    };
+    let path = map.holdings.as_deref().unwrap_or_default();
+    let epsilon = stock::quantity_epsilon(account.get_quantity_decimals());
+    let mut reported = HashMap::new();
+    for holding in holdings{
+        let symbol = string_field(holding, &map.symbol, path)?;
+        let quantity = money::to_f64(decimal_field(holding, &map.quantity, path)?);
+        *reported.entry(symbol.to_string()).or_insert(0.0) += quantity;
+    }
+    for (symbol, quantity) in reported.iter(){
+        let replayed = account.get_investments().get(symbol).map(|holding| holding.quantity).unwrap_or_default();
+        if (replayed - quantity).abs() >= epsilon{
+            return mismatch(format!("the export holds {} of {}, but its transactions leave {}", quantity, symbol, replayed));
+        }
+    }
+    if let Some(symbol) = account.get_investments().keys().find(|symbol| !reported.contains_key(*symbol)){
+        return mismatch(format!("the transactions leave a holding of {} that the export does not have", symbol));
+    }
+    Ok(())
+}
+
+impl Broker{
+    /// Imports a brokerage export into a new investment account, so a real portfolio can be simulated from where it stands.
+    /// The transactions are replayed into the account, and must add up to the holdings and cash the export reports, if it reports them
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The JSON file exported by the brokerage
+    /// * `map` - The names the export uses for each field
+    /// * `nickname` - An optional nickname for the new account
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, a mapped field is missing or malformed, a transaction cannot be replayed,
+    /// or the replay does not reconcile with the export. No account is opened if the import fails
+    pub async fn import_from_brokerage_json(&self, path: impl AsRef<Path>, map: &BrokerageFieldMap, nickname: Option<String>) -> Result<ImportReport, BankError>{
+        let contents = tokio::fs::read_to_string(path).await.map_err(BankError::OtherTokio)?;
+        let export: Value = serde_json::from_str(&contents)
+            .map_err(|e| BankError::Other(format!("Bad brokerage export: {}", e)))?;
+        let export = export
+            .as_object()
+            .ok_or_else(|| BankError::Other("Brokerage export is not an object".to_string()))?;
+
+        let transactions = objects(field(export, &map.transactions, "export")?, &map.transactions)?;
+        let holdings = match &map.holdings{
+            Some(name) => export.get(name).map(|holdings| objects(holdings, name)).transpose()?,
+            None => None,
+        };
+        let cash = match &map.cash{
+            Some(name) if export.contains_key(name) => Some(decimal_field(export, name, "export")?),
+            _ => None,
+        };
+
+        let mut unmapped_fields = BTreeSet::new();
+        let top_level: Vec<&str> = [Some(map.transactions.as_str()), map.holdings.as_deref(), map.cash.as_deref()].into_iter().flatten().collect();
+        unmapped([export], &top_level, None, &mut unmapped_fields);
+        let transaction_fields: Vec<&str> = [Some(map.kind.as_str()), Some(&map.date), Some(&map.symbol), Some(&map.quantity), Some(&map.price), Some(&map.amount), map.commission.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        unmapped(transactions.iter().copied(), &transaction_fields, Some(&map.transactions), &mut unmapped_fields);
+        if let (Some(holdings), Some(name)) = (&holdings, &map.holdings){
+            unmapped(holdings.iter().copied(), &[map.symbol.as_str(), &map.quantity], Some(name), &mut unmapped_fields);
+        }
+
+        // replayed into a copy of the opened account, which only takes its place once the whole export has replayed and reconciled.
+        // The bank stays locked throughout, so an account that fails is never seen
+        let mut bank = self.bank.lock().await;
+        let account_id = bank.open_investment_account(nickname, Default::default())?;
+        let mut account = bank.get_investment_account(account_id)?.clone();
+        let imported = replay(&mut account, &transactions, map).and_then(|_| reconcile(&account, holdings.as_deref(), cash, map));
+        if let Err(e) = imported{
+            bank.get_investment_accounts_mut().remove(&account_id);
+            return Err(e);
+        }
+        *bank.get_investment_account_mut(account_id)? = account;
+        info!(account_id, transactions = transactions.len(), unmapped = unmapped_fields.len(), "imported brokerage export");
+        Ok(ImportReport{
+            account_id,
+            transactions: transactions.len(),
+            unmapped_fields: unmapped_fields.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::bank::Bank;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_import_from_brokerage_json(){
+        let directory = "tests/test_import_from_brokerage_json";
+        let _ = std::fs::remove_dir_all(directory);
+        std::fs::create_dir_all(directory).unwrap();
+        let export = serde_json::json!({
+            "account_number": "X-1",
+            "balance": "573.50",
+            "positions": [{"ticker": "AAPL", "shares": 3}],
+            "activity": [
+                {"action": "BUY", "time": "2024-01-03T15:00:00Z", "ticker": "AAPL", "shares": 5, "unit_price": "100", "commission": 0},
+                {"action": "CASH_IN", "time": "2024-01-02T15:00:00Z", "value": 1000},
+                {"action": "SELL", "time": "2024-01-04T15:00:00Z", "ticker": "AAPL", "shares": 2, "unit_price": "110", "commission": "1.50"},
+                {"action": "DIV", "time": "2024-01-05T15:00:00Z", "ticker": "AAPL", "value": "0.75"},
+                {"action": "CASH_OUT", "time": "2024-01-06T15:00:00Z", "value": 145.75},
+            ],
+        });
+        let path = format!("{}/export.json", directory);
+        std::fs::write(&path, export.to_string()).unwrap();
+        let map = BrokerageFieldMap{
+            transactions: "activity".to_string(),
+            holdings: Some("positions".to_string()),
+            cash: Some("balance".to_string()),
+            kind: "action".to_string(),
+            date: "time".to_string(),
+            symbol: "ticker".to_string(),
+            quantity: "shares".to_string(),
+            price: "unit_price".to_string(),
+            amount: "value".to_string(),
+            commission: Some("commission".to_string()),
+            kinds: HashMap::from([
+                ("CASH_IN".to_string(), ImportedKind::Deposit),
+                ("CASH_OUT".to_string(), ImportedKind::Withdraw),
+                ("BUY".to_string(), ImportedKind::Buy),
+                ("SELL".to_string(), ImportedKind::Sell),
+                ("DIV".to_string(), ImportedKind::Dividend),
+            ]),
+        };
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+
+        let report = broker.import_from_brokerage_json(&path, &map, Some("Imported".to_string())).await.unwrap();
+        assert_eq!(report.transactions, 5);
+        assert_eq!(report.unmapped_fields, vec!["account_number".to_string()]);
+        {
+            let bank = broker.bank.lock().await;
+            let account = bank.get_investment_account(report.account_id).unwrap();
+            assert_eq!(account.get_balance(), dec!(573.5));
+            assert_eq!(account.get_investments()["AAPL"].quantity, 3.0);
+            assert_eq!(account.get_investments()["AAPL"].average_cost_per_unit, dec!(100.0));
+            // the commission of 0 on the buy charges nothing
+            assert_eq!(account.transactions[3].transaction_type, TransactionType::Commission(Asset::new("AAPL".to_string())));
+            assert_eq!(account.transactions[3].amount, dec!(1.5));
+            assert!(matches!(&account.transactions[4].transaction_type, TransactionType::Dividend(_, quantity) if *quantity == 3.0));
+        }
+
+        // an export whose holdings the transactions do not add up to is refused, and opens no account
+        let mut export = export;
+        export["positions"][0]["shares"] = serde_json::json!(4);
+        std::fs::write(&path, export.to_string()).unwrap();
+        assert!(matches!(broker.import_from_brokerage_json(&path, &map, None).await, Err(BankError::Other(_))));
+        assert_eq!(broker.bank.lock().await.get_investment_accounts().len(), 1);
+        // as is one that cannot be replayed
+        export["positions"][0]["shares"] = serde_json::json!(3);
+        export["activity"][4]["value"] = serde_json::json!(10000);
+        std::fs::write(&path, export.to_string()).unwrap();
+        assert!(matches!(broker.import_from_brokerage_json(&path, &map, None).await, Err(BankError::InsufficientFunds)));
+        assert_eq!(broker.bank.lock().await.get_investment_accounts().len(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
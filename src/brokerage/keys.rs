@@ -0,0 +1,184 @@
+use std::sync::{atomic::{AtomicUsize, Ordering}, Mutex};
+
+use alphavantage::cache_enabled::client::Client;
+use chrono::{DateTime, Days, Duration, Utc};
+use tracing::warn;
+
+use crate::bank::error::BankError;
+
+/// A set of clients, one per API key, used in rotation so that the rate limit of each key adds up.
+/// A key that reports its daily rate limit is benched until the next UTC day, and one that reports
+/// calling too often is benched for a minute.
+pub(crate) struct ClientPool{
+    clients: Vec<Client>,
+    /// The key behind each client, when known, for endpoints the client does not cover
+    keys: Vec<Option<String>>,
+    next: AtomicUsize,
+    benched_until: Mutex<Vec<Option<DateTime<Utc>>>>,
+}
+
+impl ClientPool{
+    pub(crate) fn new(clients: Vec<Client>) -> Self{
+        let keys = vec![None; clients.len()];
+        ClientPool::with_keys(clients, keys)
+    }
+
+    pub(crate) fn from_keys(keys: Vec<String>) -> Self{
+        let clients = keys.iter().map(|key| Client::new(key)).collect();
+        ClientPool::with_keys(clients, keys.into_iter().map(Some).collect())
+    }
+
+    fn with_keys(clients: Vec<Client>, keys: Vec<Option<String>>) -> Self{
+        let benched_until = Mutex::new(vec![None; clients.len()]);
+        ClientPool{
+            clients,
+            keys,
+            next: AtomicUsize::new(0),
+            benched_until,
+        }
+    }
+
+    /// Picks the next index in the rotation that is not benched and is accepted by the filter
+    fn next_index(&self, accept: impl Fn(usize) -> bool) -> Result<usize, BankError>{
+        let now = Utc::now();
+        let mut benched_until = self.benched_until.lock().unwrap();
+        for _ in 0..self.clients.len(){
+            let index = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+            if !accept(index){
+                continue;
+            }
+            match benched_until[index]{
+                Some(until) if until > now => continue,
+                _ => {
+                    benched_until[index] = None;
+                    return Ok(index);
+                }
+            }
+        }
+        Err(BankError::RateLimited)
+    }
+
+    /// Picks the next key in the rotation that is not benched
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::RateLimited` if every key is benched
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the index of the key, which is passed back to `check_rate_limit`, and its client
+    pub(crate) fn next_client(&self) -> Result<(usize, &Client), BankError>{
+        let index = self.next_index(|_| true)?;
+        Ok((index, &self.clients[index]))
+    }
+
+    /// Picks the next raw API key in the rotation that is not benched, for requests made without the client
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the pool was built from clients rather than keys, or every key is benched
+    pub(crate) fn next_key(&self) -> Result<(usize, &str), BankError>{
+        if self.keys.iter().all(|key| key.is_none()){
+            return Err(BankError::Other(String::from("This request needs an API key, create the broker with Broker::with_keys")));
+        }
+        let index = self.next_index(|index| self.keys[index].is_some())?;
+        Ok((index, self.keys[index].as_deref().unwrap()))
+    }
+
+    /// The first client, regardless of rotation
+    pub(crate) fn first(&self) -> &Client{
+        &self.clients[0]
+    }
+
+    /// Maps the result of a request made with the key at `index`,
+    /// benching the key if AlphaVantage reported that it hit its rate limit
+    pub(crate) fn check_rate_limit<T>(&self, index: usize, result: Result<T, alphavantage::error::Error>) -> Result<T, BankError>{
+        result.map_err(|e| {
+            if let Some(limit) = rate_limit(&e.to_string()){
+                self.bench(index, limit);
+            }
+            BankError::OtherAlphaVantage(e)
+        })
+    }
+
+    /// Benches the key at `index` until the limit it hit resets: the next UTC day for the daily limit, or a minute from now
+    pub(crate) fn bench(&self, index: usize, limit: RateLimit){
+        let now = Utc::now();
+        let until = match limit{
+            RateLimit::PerDay => now.date_naive().checked_add_days(Days::new(1)).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc(),
+            RateLimit::PerMinute => now + Duration::minutes(1),
+        };
+        warn!(key = index, ?limit, %until, "API key reached its rate limit, benching it");
+        self.benched_until.lock().unwrap()[index] = Some(until);
+    }
+}
+
+/// The rate limit a key hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RateLimit{
+    /// Too many calls in a minute
+    PerMinute,
+    /// The requests allowed in a day are used up
+    PerDay,
+}
+
+/// AlphaVantage reports an exhausted key with a note rather than a status code,
+/// so the limit is recognized from the message. A limit that does not say it is daily is taken to be per minute
+pub(crate) fn rate_limit(message: &str) -> Option<RateLimit>{
+    let message = message.to_lowercase();
+    if message.contains("requests per day"){
+        Some(RateLimit::PerDay)
+    }else if message.contains("rate limit") || message.contains("call frequency"){
+        Some(RateLimit::PerMinute)
+    }else{
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_rotation(){
+        let pool = ClientPool::new(vec![Client::new("a"), Client::new("b")]);
+        let order: Vec<usize> = (0..4).map(|_| pool.next_client().unwrap().0).collect();
+        assert_eq!(order, vec![0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn test_benched_key_is_skipped(){
+        let pool = ClientPool::new(vec![Client::new("a"), Client::new("b")]);
+        pool.benched_until.lock().unwrap()[0] = Some(Utc::now() + chrono::Duration::hours(1));
+        assert_eq!(pool.next_client().unwrap().0, 1);
+        assert_eq!(pool.next_client().unwrap().0, 1);
+        pool.benched_until.lock().unwrap()[1] = Some(Utc::now() + chrono::Duration::hours(1));
+        assert!(matches!(pool.next_client(), Err(BankError::RateLimited)));
+    }
+
+    #[test]
+    fn test_bench_by_rate_limit(){
+        let daily = rate_limit("Our standard API rate limit is 25 requests per day.");
+        let frequency = rate_limit("Our standard API call frequency is 5 calls per minute and 500 calls per day.");
+        assert_eq!(daily, Some(RateLimit::PerDay));
+        assert_eq!(frequency, Some(RateLimit::PerMinute));
+        assert_eq!(rate_limit("Invalid API call."), None);
+
+        let pool = ClientPool::new(vec![Client::new("a"), Client::new("b")]);
+        pool.bench(0, RateLimit::PerMinute);
+        pool.bench(1, RateLimit::PerDay);
+        let benched_until = pool.benched_until.lock().unwrap().clone();
+        let minute = benched_until[0].unwrap() - Utc::now();
+        assert!(minute > Duration::seconds(50) && minute <= Duration::minutes(1));
+        assert_eq!(benched_until[1].unwrap(), (Utc::now().date_naive() + Days::new(1)).and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+
+    #[test]
+    fn test_next_key(){
+        let pool = ClientPool::new(vec![Client::new("a")]);
+        assert!(pool.next_key().is_err());
+        let pool = ClientPool::from_keys(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(pool.next_key().unwrap(), (0, "a"));
+        assert_eq!(pool.next_key().unwrap(), (1, "b"));
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, Ordering}, Mutex}};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::bank::error::BankError;
+
+use super::Broker;
+
+/// Where the last known prices are kept unless the store is given another path
+pub const DEFAULT_LAST_PRICES_PATH: &str = "~/.trading_engine/last_prices.json";
+
+/// A price of a symbol, and the time it was for
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LastPrice{
+    pub price: Decimal,
+    pub at: DateTime<Utc>,
+}
+
+/// A price from `get_price_or_last_known`, which says whether it is fresh
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuotedPrice{
+    pub price: Decimal,
+    /// The time the price was for: the date limit for a fresh price, or when a fallback price was fetched
+    pub at: DateTime<Utc>,
+    /// Whether the price could not be fetched, and this is the last known price instead
+    pub stale: bool,
+}
+
+/// The last price fetched for each symbol, kept so that valuation can carry on when a price cannot be fetched
+#[derive(Debug)]
+pub struct LastPriceStore{
+    path: PathBuf,
+    prices: Mutex<HashMap<String, LastPrice>>,
+    /// Whether a price has changed since the store was opened or last saved
+    unsaved: AtomicBool,
+}
+
+impl LastPriceStore{
+    /// Opens the store saved at the path, or an empty one if nothing has been saved there
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the file exists but cannot be read or parsed
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, std::io::Error>{
+        let path = path.into();
+        let prices = match tokio::fs::read_to_string(&path).await{
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(LastPriceStore{ path, prices: Mutex::new(prices), unsaved: AtomicBool::new(false) })
+    }
+
+    /// Opens the store at `DEFAULT_LAST_PRICES_PATH`
+    pub async fn open_default() -> Result<Self, std::io::Error>{
+        LastPriceStore::open(super::utils::expand_tilde(DEFAULT_LAST_PRICES_PATH)).await
+    }
+
+    pub fn get_path(&self) -> &Path{
+        &self.path
+    }
+
+    /// Gets the last price recorded for a symbol
+    pub fn get(&self, symbol: &str) -> Option<LastPrice>{
+        self.prices.lock().unwrap().get(symbol).copied()
+    }
+
+    /// Records a price, unless a price for a later time is already recorded.
+    /// Only a new price for the symbol leaves the store to be saved by `flush`
+    pub fn record(&self, symbol: &str, at: DateTime<Utc>, price: Decimal){
+        let mut prices = self.prices.lock().unwrap();
+        let last = prices.get(symbol).copied();
+        if last.is_none_or(|last| last.at <= at){
+            prices.insert(symbol.to_string(), LastPrice{ price, at });
+            if last.is_none_or(|last| last.price != price){
+                self.unsaved.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Whether a price has changed since the store was opened or last saved
+    pub fn is_unsaved(&self) -> bool{
+        self.unsaved.load(Ordering::Relaxed)
+    }
+
+    /// Saves the store if a price has changed since it was opened or last saved
+    pub async fn flush(&self) -> Result<(), std::io::Error>{
+        if self.is_unsaved(){
+            self.save().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes every recorded price to the store's file
+    pub async fn save(&self) -> Result<(), std::io::Error>{
+        let json = serde_json::to_string(&*self.prices.lock().unwrap())?;
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()){
+            crate::state::create_private_dir_all(parent)?;
+        }
+        tokio::fs::write(&self.path, json).await?;
+        self.unsaved.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+impl Broker{
+    pub fn get_last_price_store(&self) -> Option<&LastPriceStore>{
+        self.last_prices.as_ref()
+    }
+
+    /// Sets the store every price `get_price` fetches is recorded in, for `get_price_or_last_known` to fall back on.
+    /// There is none by default
+    pub fn set_last_price_store(&mut self, last_prices: Option<LastPriceStore>){
+        self.last_prices = last_prices;
+    }
+
+    /// Gets the price of a symbol as `get_price` does, falling back to the last price recorded for it,
+    /// if it has one for no later than the date limit, when the price cannot be fetched.
+    /// The store is saved when a fresh price changes it. Without a store, this is `get_price`
+    /// 
+    /// # Errors
+    /// 
+    /// Returns the error of `get_price` if there is no last known price to fall back on, or an error if the store cannot be saved
+    pub async fn get_price_or_last_known(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<QuotedPrice, BankError>{
+        let at = date_limit.map(|date| date.to_utc()).unwrap_or_else(Utc::now);
+        match self.get_price(symbol, date_limit).await{
+            Ok(price) => {
+                if let Some(last_prices) = &self.last_prices{
+                    last_prices.flush().await.map_err(BankError::OtherTokio)?;
+                }
+                Ok(QuotedPrice{ price, at, stale: false })
+            },
+            Err(e) => {
+                let last = self.last_prices
+                    .as_ref()
+                    .and_then(|last_prices| last_prices.get(symbol))
+                    .filter(|last| last.at <= at);
+                match last{
+                    Some(last) => {
+                        warn!(symbol, error = %e, last_known_at = %last.at, "falling back to the last known price");
+                        Ok(QuotedPrice{ price: last.price, at: last.at, stale: true })
+                    },
+                    None => Err(e),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_last_known_price(){
+        let path = "tests/test_last_known_price/last_prices.json";
+        let _ = std::fs::remove_dir_all("tests/test_last_known_price");
+        let day = |day| Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(90.0), 2.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", day(2), dec!(100.0)));
+        broker.set_last_price_store(Some(LastPriceStore::open(path).await.unwrap()));
+
+        let fresh = broker.get_price_or_last_known("AAPL", Some(day(3).fixed_offset())).await.unwrap();
+        assert_eq!(fresh, QuotedPrice{ price: dec!(100.0), at: day(3), stale: false });
+        assert!(std::path::Path::new(path).exists());
+        // the same price again does not rewrite the store
+        std::fs::remove_file(path).unwrap();
+        broker.get_price_or_last_known("AAPL", Some(day(3).fixed_offset())).await.unwrap();
+        assert!(!broker.get_last_price_store().unwrap().is_unsaved());
+        assert!(!std::path::Path::new(path).exists());
+        broker.get_last_price_store().unwrap().save().await.unwrap();
+
+        // the source loses AAPL, and the saved price is used instead
+        broker.set_price_source(MockPriceSource::new());
+        broker.set_last_price_store(Some(LastPriceStore::open(path).await.unwrap()));
+        let fallback = broker.get_price_or_last_known("AAPL", Some(day(4).fixed_offset())).await.unwrap();
+        assert_eq!(fallback, QuotedPrice{ price: dec!(100.0), at: day(3), stale: true });
+        assert_eq!(broker.get_account_value(id, Some(day(4).fixed_offset())).await.unwrap(), dec!(1020.0));
+        // but not before it was known
+        assert!(broker.get_price_or_last_known("AAPL", Some(day(2).fixed_offset())).await.is_err());
+        assert!(broker.get_account_value(id, Some(day(2).fixed_offset())).await.is_err());
+        assert!(broker.get_price("AAPL", None).await.is_err());
+        std::fs::remove_dir_all("tests/test_last_known_price").unwrap();
+    }
+}
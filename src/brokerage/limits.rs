@@ -0,0 +1,139 @@
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
+use tracing::info;
+
+use crate::bank::{error::BankError, money};
+
+use super::Broker;
+
+/// What to do with a buy that would push a position over the limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PositionLimitAction{
+    /// Refuse the buy
+    #[default]
+    Reject,
+    /// Buy only as much as fits under the limit
+    Trim,
+}
+
+/// A cap on how much of an account's equity a single holding may make up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionLimit{
+    /// The largest fraction of the account's total equity, cash included, one holding may be worth
+    pub max_weight: Decimal,
+    pub action: PositionLimitAction,
+}
+
+impl PositionLimit{
+    pub fn new(max_weight: Decimal, action: PositionLimitAction) -> Self{
+        PositionLimit{ max_weight, action }
+    }
+}
+
+impl Broker{
+    pub fn get_position_limit(&self) -> Option<PositionLimit>{
+        self.position_limit
+    }
+
+    /// Sets the largest weight a holding may reach through a buy, or removes the limit
+    pub fn set_position_limit(&mut self, position_limit: Option<PositionLimit>){
+        self.position_limit = position_limit;
+    }
+
+    /// Checks a buy against the position limit, pricing the whole account at the date limit.
+    /// The weight is taken against the account's equity before commission.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::PositionLimitExceeded` if the buy would break the limit and cannot be trimmed
+    ///
+    /// # Returns
+    ///
+    /// Returns the quantity that may be bought, which is less than asked for only when trimming
+    pub(crate) async fn apply_position_limit(&self, account_id: u32, symbol: &str, price: Decimal, quantity: f64, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, BankError>{
+        let Some(limit) = self.position_limit else {
+            return Ok(quantity);
+        };
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        let quantity_decimals = self.bank.lock().await.get_investment_account(account_id)?.get_quantity_decimals();
+        let prices = self.price_holdings(&holdings, date_limit).await?;
+        let equity: Decimal = cash + holdings
+            .iter()
+            .map(|holding| prices[&holding.asset.symbol] * money::from_f64(holding.quantity))
+            .sum::<Decimal>();
+        let held = holdings
+            .iter()
+            .find(|holding| holding.asset.symbol == symbol)
+            .map(|holding| holding.quantity)
+            .unwrap_or(0.0);
+
+        // buying moves cash into the position, so the equity stays the same
+        let max_value = limit.max_weight * equity;
+        if price * money::from_f64(held + quantity) <= max_value{
+            return Ok(quantity);
+        }
+        if limit.action == PositionLimitAction::Reject || price.is_zero(){
+            return Err(BankError::PositionLimitExceeded);
+        }
+        let scale = 10f64.powi(quantity_decimals as i32);
+        let allowed = (money::to_f64(max_value / price) * scale).floor() / scale - held;
+        if allowed <= 0.0{
+            return Err(BankError::PositionLimitExceeded);
+        }
+        info!(symbol, requested = quantity, allowed, "trimmed a buy to the position limit");
+        Ok(allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    async fn broker(action: PositionLimitAction) -> (Broker, u32){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(100.0)));
+        broker.set_position_limit(Some(PositionLimit::new(dec!(0.25), action)));
+        (broker, id)
+    }
+
+    #[tokio::test]
+    async fn test_position_limit_rejects(){
+        let (mut broker, id) = broker(PositionLimitAction::Reject).await;
+        let date = Some(Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap().fixed_offset());
+        assert!(broker.buy("AAPL", 2.0, id, date).await.is_ok());
+        assert!(matches!(broker.buy("AAPL", 1.0, id, date).await, Err(BankError::PositionLimitExceeded)));
+    }
+
+    #[tokio::test]
+    async fn test_position_limit_trims(){
+        let (mut broker, id) = broker(PositionLimitAction::Trim).await;
+        let date = Some(Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap().fixed_offset());
+        let balance = broker.buy("AAPL", 5.0, id, date).await.unwrap();
+        assert_eq!(balance, dec!(750.0));
+        assert!(matches!(broker.buy("AAPL", 1.0, id, date).await, Err(BankError::PositionLimitExceeded)));
+    }
+
+    #[tokio::test]
+    async fn test_position_limit_trims_to_the_account_decimals(){
+        let (mut broker, id) = broker(PositionLimitAction::Trim).await;
+        broker.bank.lock().await.get_investment_account_mut(id).unwrap().set_quantity_decimals(0);
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(28.0)));
+        // 8.93 shares fit under the limit, which would round up to 9 and break it
+        let balance = broker.buy("AAPL", 20.0, id, Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(balance, dec!(776.0));
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
+use tracing::info;
+
+use crate::bank::{error::BankError, transactions::tags};
+
+use super::Broker;
+
+/// The outcome of closing every position of an account
+#[derive(Debug, Default)]
+pub struct Liquidation{
+    /// Each symbol sold, with the cash its sale brought in after commission
+    pub sold: Vec<(String, Decimal)>,
+    /// Each symbol that could not be sold, with the error, which is still held
+    pub failed: Vec<(String, BankError)>,
+    /// The cash brought in by every sale, after commission
+    pub proceeds: Decimal,
+}
+
+impl Broker{
+    /// Sells every holding of an investment account at market, leaving it in cash. The account stays open.
+    /// A holding that cannot be sold, such as one without a price, is reported and skipped,
+    /// and the rest are still sold. Holdings are sold in order of symbol
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date limit to get prices at
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error only if the account does not exist
+    pub async fn close_all_positions(&mut self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Liquidation, BankError>{
+        let (mut balance, mut holdings) = self.snapshot_holdings(account_id).await?;
+        holdings.sort_by(|a, b| a.asset.symbol.cmp(&b.asset.symbol));
+        let mut liquidation = Liquidation::default();
+        for holding in holdings{
+            let symbol = holding.asset.symbol;
+            match self.sell_tagged(&symbol, holding.quantity, account_id, date_limit, tags::LIQUIDATION).await{
+                Ok(new_balance) => {
+                    liquidation.sold.push((symbol, new_balance - balance));
+                    liquidation.proceeds += new_balance - balance;
+                    balance = new_balance;
+                }
+                Err(e) => liquidation.failed.push((symbol, e)),
+            }
+        }
+        info!(account_id, sold = liquidation.sold.len(), failed = liquidation.failed.len(), proceeds = %liquidation.proceeds, "closed all positions");
+        Ok(liquidation)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::Utc;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::{commission::CommissionSchedule, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_close_all_positions(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 2.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(50.0), 4.0).unwrap();
+        account.purchase_investment("XYZ".to_string(), dec!(10.0), 1.0).unwrap();
+        let bank = Arc::new(Mutex::new(bank));
+        let mut broker = Broker::new(Client::new("unused"), bank.clone());
+        let now = Utc::now();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", now, dec!(120.0)).with_price("MSFT", now, dec!(40.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+
+        let liquidation = broker.close_all_positions(id, None).await.unwrap();
+        assert_eq!(liquidation.sold, vec![("AAPL".to_string(), dec!(239.0)), ("MSFT".to_string(), dec!(159.0))]);
+        assert_eq!(liquidation.proceeds, dec!(398.0));
+        // XYZ has no price, and is left held
+        assert_eq!(liquidation.failed.len(), 1);
+        assert_eq!(liquidation.failed[0].0, "XYZ");
+
+        let bank = bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_balance(), dec!(988.0));
+        assert_eq!(account.get_investments().len(), 1);
+    }
+}
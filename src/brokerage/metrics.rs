@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+
+use alphavantage::cache_enabled::time_series::TimeSeries;
+use chrono::{DateTime, FixedOffset, NaiveDate};
+
+use super::prices::PriceField;
+
+/// Gets the price of each day of a daily series that falls between start and end, inclusive
+/// 
+/// # Returns
+/// 
+/// Returns `None` if any day in the window does not have the price field
+pub(crate) fn daily_closes(series: &TimeSeries, field: PriceField, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> Option<BTreeMap<NaiveDate, f64>>{
+    series.entries
+        .iter()
+        .filter(|entry| entry.date >= start && entry.date <= end)
+        .map(|entry| field.of(entry).map(|price| (entry.date.date_naive(), price)))
+        .collect()
+}
+
+/// Pairs up the returns of two price series over the days they share
+/// 
+/// # Returns
+/// 
+/// Returns the returns of `a` and the returns of `b`, for each consecutive pair of shared days
+pub(crate) fn aligned_returns(a: &BTreeMap<NaiveDate, f64>, b: &BTreeMap<NaiveDate, f64>) -> (Vec<f64>, Vec<f64>){
+    let (prices_a, prices_b): (Vec<f64>, Vec<f64>) = a
+        .iter()
+        .filter_map(|(date, price)| b.get(date).map(|other| (*price, *other)))
+        .unzip();
+    (simple_returns(&prices_a), simple_returns(&prices_b))
+}
+
+/// Computes the return from each price to the next
+pub fn simple_returns(prices: &[f64]) -> Vec<f64>{
+    prices
+        .windows(2)
+        .map(|pair| pair[1] / pair[0] - 1.0)
+        .collect()
+}
+
+pub fn mean(values: &[f64]) -> f64{
+    if values.is_empty(){
+        return 0.0;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// The sample variance of the values
+pub fn variance(values: &[f64]) -> f64{
+    covariance(values, values)
+}
+
+/// The sample covariance of two equally long series
+pub fn covariance(a: &[f64], b: &[f64]) -> f64{
+    let n = a.len().min(b.len());
+    if n < 2{
+        return 0.0;
+    }
+    let mean_a = mean(&a[..n]);
+    let mean_b = mean(&b[..n]);
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - mean_a) * (y - mean_b))
+        .sum::<f64>() / (n - 1) as f64
+}
+
+/// The sample standard deviation of the values
+pub fn standard_deviation(values: &[f64]) -> f64{
+    variance(values).sqrt()
+}
+
+/// The beta of an asset's returns against a benchmark's returns over the same periods
+/// 
+/// # Returns
+/// 
+/// Returns `None` if there are too few returns, or the benchmark never moves
+pub fn beta(asset_returns: &[f64], benchmark_returns: &[f64]) -> Option<f64>{
+    let benchmark_variance = variance(benchmark_returns);
+    if asset_returns.len().min(benchmark_returns.len()) < 2 || benchmark_variance == 0.0{
+        return None;
+    }
+    Some(covariance(asset_returns, benchmark_returns) / benchmark_variance)
+}
+
+/// Weights inversely proportional to each volatility, summing to one, so that every position carries the same risk
+/// 
+/// # Returns
+/// 
+/// Returns `None` if there are no volatilities, or one is not positive and finite
+pub fn inverse_volatility_weights(volatilities: &[f64]) -> Option<Vec<f64>>{
+    if volatilities.is_empty() || volatilities.iter().any(|volatility| !volatility.is_finite() || *volatility <= 0.0){
+        return None;
+    }
+    let total: f64 = volatilities.iter().map(|volatility| 1.0 / volatility).sum();
+    Some(volatilities.iter().map(|volatility| (1.0 / volatility) / total).collect())
+}
+
+/// The net present value of cash flows at the given rate per year
+/// 
+/// # Arguments
+/// 
+/// * `flows` - Each flow's time in years from the first, and its amount
+pub fn net_present_value(flows: &[(f64, f64)], rate: f64) -> f64{
+    flows.iter().map(|(years, amount)| amount / (1.0 + rate).powf(*years)).sum()
+}
+
+/// The internal rate of return of cash flows: the yearly rate at which their net present value is zero
+/// 
+/// # Arguments
+/// 
+/// * `flows` - Each flow's time in years from the first, and its amount. Money put in is negative, money taken out positive
+/// 
+/// # Returns
+/// 
+/// Returns `None` if no rate between -100% and a very large one makes the net present value change sign
+pub fn internal_rate_of_return(flows: &[(f64, f64)]) -> Option<f64>{
+    let mut low = -0.999_999;
+    let mut high = 1.0;
+    let npv_low = net_present_value(flows, low);
+    while (npv_low > 0.0) == (net_present_value(flows, high) > 0.0){
+        high *= 2.0;
+        if high > 1e9{
+            return None;
+        }
+    }
+    // bisection always converges once the root is bracketed
+    for _ in 0..200{
+        let middle = (low + high) / 2.0;
+        let npv_middle = net_present_value(flows, middle);
+        if npv_middle == 0.0 || high - low < 1e-12{
+            return Some(middle);
+        }
+        if (npv_middle > 0.0) == (npv_low > 0.0){
+            low = middle;
+        }else{
+            high = middle;
+        }
+    }
+    Some((low + high) / 2.0)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool{
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_simple_returns(){
+        let returns = simple_returns(&[100.0, 110.0, 99.0]);
+        assert_eq!(returns.len(), 2);
+        assert!(close(returns[0], 0.1));
+        assert!(close(returns[1], -0.1));
+    }
+
+    #[test]
+    fn test_variance(){
+        assert!(close(variance(&[1.0, 2.0, 3.0, 4.0]), 5.0 / 3.0));
+        assert_eq!(variance(&[1.0]), 0.0);
+    }
+
+    #[test]
+    fn test_inverse_volatility_weights(){
+        let weights = inverse_volatility_weights(&[0.1, 0.2, 0.4]).unwrap();
+        assert!(close(weights[0], 4.0 / 7.0));
+        assert!(close(weights[1], 2.0 / 7.0));
+        assert!(close(weights[2], 1.0 / 7.0));
+        assert!(inverse_volatility_weights(&[0.1, 0.0]).is_none());
+        assert!(inverse_volatility_weights(&[]).is_none());
+    }
+
+    #[test]
+    fn test_beta(){
+        let benchmark = [0.01, -0.02, 0.03, 0.005];
+        let doubled: Vec<f64> = benchmark.iter().map(|r| r * 2.0).collect();
+        assert!(close(beta(&benchmark, &benchmark).unwrap(), 1.0));
+        assert!(close(beta(&doubled, &benchmark).unwrap(), 2.0));
+        assert!(beta(&doubled, &[0.01, 0.01, 0.01, 0.01]).is_none());
+    }
+
+    #[test]
+    fn test_aligned_returns(){
+        let day = |d| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        let a = BTreeMap::from([(day(1), 10.0), (day(2), 11.0), (day(3), 12.1)]);
+        let b = BTreeMap::from([(day(1), 100.0), (day(3), 50.0)]);
+        let (returns_a, returns_b) = aligned_returns(&a, &b);
+        assert_eq!(returns_a.len(), 1);
+        assert!(close(returns_a[0], 0.21));
+        assert!(close(returns_b[0], -0.5));
+    }
+
+    #[test]
+    fn test_internal_rate_of_return(){
+        assert!(close(internal_rate_of_return(&[(0.0, -100.0), (1.0, 110.0)]).unwrap(), 0.1));
+        // the XIRR example from the spreadsheet documentation, in years of 365 days
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let start = date(2008, 1, 1);
+        let flows: Vec<(f64, f64)> = [
+            (start, -10000.0),
+            (date(2008, 3, 1), 2750.0),
+            (date(2008, 10, 30), 4250.0),
+            (date(2009, 2, 15), 3250.0),
+            (date(2009, 4, 1), 2750.0),
+        ]
+            .iter()
+            .map(|(day, amount)| ((*day - start).num_days() as f64 / 365.0, *amount))
+            .collect();
+        assert!((internal_rate_of_return(&flows).unwrap() - 0.373362535).abs() < 1e-8);
+        assert!(internal_rate_of_return(&[(0.0, 100.0), (1.0, 110.0)]).is_none());
+    }
+}
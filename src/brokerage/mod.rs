@@ -1,15 +1,91 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::{BTreeMap, HashMap}, ops::{Bound, RangeBounds}, path::PathBuf, sync::Arc};
 
 use alphavantage::{cache_enabled::{client::Client, tickers::{Entry, SearchResults}, time_series::{self, TimeSeries}}, corprate_actions::{DividendEntry, DividendResults}, time_series::IntradayInterval};
-use chrono::{format::Fixed, DateTime, FixedOffset, TimeZone, Utc};
+use chrono::{format::Fixed, DateTime, FixedOffset, NaiveDate, NaiveTime, TimeZone, Utc};
 use disk_cache::cache_async;
-use tokio::sync::Mutex;
+use rand::{rngs::StdRng, SeedableRng};
+use rust_decimal::Decimal;
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{debug, info, instrument};
 use utils::expand_tilde;
-use crate::bank::{self, accounts::Account, error::BankError, stock::Asset, transactions::{Transaction, TransactionType}, Bank};
+use alerts::PriceAlerts;
+use calendar::{TradingCalendar, UsEquityCalendar};
+use commission::CommissionSchedule;
+use concurrency::DEFAULT_MAX_CONCURRENCY;
+use day_trading::PatternDayTraderRule;
+use dividends::DividendEligibility;
+use history::DEFAULT_HISTORY_PATH;
+use keys::ClientPool;
+use last_prices::LastPriceStore;
+use limits::PositionLimit;
+use orders::OrderBook;
+use slippage::SlippageModel;
+use prices::{PriceField, PriceSource};
+use provider::{AlphaVantageProvider, MarketDataProvider};
+use crate::config::EngineConfig;
+use crate::bank::{self, accounts::Account, error::BankError, lots::WashSaleRule, money, stock::Asset, transactions::{tags, Transaction, TransactionType}, Bank};
+
+pub mod alerts;
+pub mod backtest;
+pub mod cache;
+pub mod calendar;
+pub mod cash;
+pub mod commission;
+pub mod concurrency;
+pub mod day_trading;
+pub mod dividends;
+pub mod fees;
+pub mod fundamentals;
+pub mod history;
+pub mod import;
+pub mod limits;
+pub mod liquidation;
+mod keys;
+pub mod last_prices;
+pub mod metrics;
+pub mod orders;
+pub mod performance;
+pub mod portfolio;
+pub mod prices;
+pub mod provider;
+pub mod rebalance;
+pub mod report;
+pub mod risk;
+pub mod screener;
+pub mod slippage;
+pub mod splits;
+pub mod stale;
+pub mod stream;
+pub mod tax;
+pub mod trade;
 
 pub struct Broker {
-    client: Client,
-    bank: Arc<Mutex<Bank>>
+    clients: Arc<ClientPool>,
+    provider: Box<dyn MarketDataProvider>,
+    bank: Arc<Mutex<Bank>>,
+    commission_schedule: CommissionSchedule,
+    wash_sale_rule: WashSaleRule,
+    price_source: Option<Box<dyn PriceSource>>,
+    price_overrides: HashMap<String, Decimal>,
+    manual_prices: HashMap<String, BTreeMap<DateTime<Utc>, Decimal>>,
+    price_field: PriceField,
+    intraday_price_field: PriceField,
+    calendar: Box<dyn TradingCalendar>,
+    position_limit: Option<PositionLimit>,
+    pattern_day_trader_rule: Option<PatternDayTraderRule>,
+    orders: OrderBook,
+    price_alerts: PriceAlerts,
+    slippage_model: SlippageModel,
+    dividend_eligibility: DividendEligibility,
+    fractional_shares: bool,
+    idle_cash_threshold: Option<Decimal>,
+    rng: StdRng,
+    extended_hours: bool,
+    max_concurrency: usize,
+    batch_permits: Semaphore,
+    history_path: PathBuf,
+    record_history: bool,
+    last_prices: Option<LastPriceStore>,
 }
 
 impl Broker {
@@ -17,68 +93,193 @@ impl Broker {
     where
         T: Into<Arc<Mutex<Bank>>>,
     {
+        Broker::with_pool(ClientPool::new(vec![client]), bank)
+    }
+
+    /// Creates a broker that rotates through several AlphaVantage API keys, one per request,
+    /// so that the rate limits of the keys add up
+    /// 
+    /// # Panics
+    /// 
+    /// Panics if no keys are given
+    pub fn with_keys<T>(keys: Vec<String>, bank: T) -> Self
+    where
+        T: Into<Arc<Mutex<Bank>>>,
+    {
+        assert!(!keys.is_empty(), "A broker needs at least one API key");
+        Broker::with_pool(ClientPool::from_keys(keys), bank)
+    }
+
+    fn with_pool<T>(clients: ClientPool, bank: T) -> Self
+    where
+        T: Into<Arc<Mutex<Bank>>>,
+    {
+        let clients = Arc::new(clients);
         Broker {
-            client,
+            provider: Box::new(AlphaVantageProvider::new(clients.clone())),
+            clients,
             bank: bank.into(),
+            commission_schedule: CommissionSchedule::free(),
+            wash_sale_rule: WashSaleRule::default(),
+            price_source: None,
+            price_overrides: HashMap::new(),
+            manual_prices: HashMap::new(),
+            price_field: PriceField::default(),
+            intraday_price_field: PriceField::Close,
+            calendar: Box::new(UsEquityCalendar),
+            position_limit: None,
+            pattern_day_trader_rule: None,
+            orders: OrderBook::default(),
+            price_alerts: PriceAlerts::default(),
+            slippage_model: SlippageModel::default(),
+            dividend_eligibility: DividendEligibility::default(),
+            fractional_shares: true,
+            idle_cash_threshold: None,
+            rng: StdRng::from_entropy(),
+            extended_hours: true,
+            max_concurrency: DEFAULT_MAX_CONCURRENCY,
+            batch_permits: Semaphore::new(DEFAULT_MAX_CONCURRENCY),
+            history_path: expand_tilde(DEFAULT_HISTORY_PATH),
+            record_history: false,
+            last_prices: None,
         }
     }
 
+    /// Applies the engine settings that belong to the broker, such as the seed of its random models, the concurrency limit and the price fields
+    pub fn apply_config(&mut self, config: &EngineConfig) {
+        if let Some(seed) = config.seed {
+            self.set_seed(seed);
+        }
+        if let Some(max_concurrency) = config.max_concurrency {
+            self.set_max_concurrency(max_concurrency);
+        }
+        if let Some(price_field) = config.price_field {
+            self.set_price_field(price_field);
+        }
+        if let Some(intraday_price_field) = config.intraday_price_field {
+            self.set_intraday_price_field(intraday_price_field);
+        }
+    }
+
+    /// Gets the client of the first API key
     pub fn get_client(&self) -> &Client {
-        &self.client
+        self.clients.first()
     }
 
     pub fn get_bank(&self) -> Arc<Mutex<Bank>> {
         self.bank.clone()
     }
 
+    pub fn get_commission_schedule(&self) -> &CommissionSchedule {
+        &self.commission_schedule
+    }
+
+    /// Sets the commission charged on every buy and sell
+    pub fn set_commission_schedule(&mut self, commission_schedule: CommissionSchedule) {
+        self.commission_schedule = commission_schedule;
+    }
+
+    pub fn get_wash_sale_rule(&self) -> WashSaleRule {
+        self.wash_sale_rule
+    }
+
+    /// Sets how buys that would be wash sales are treated
+    pub fn set_wash_sale_rule(&mut self, wash_sale_rule: WashSaleRule) {
+        self.wash_sale_rule = wash_sale_rule;
+    }
+
+    /// Rejects a series with no entries, such as for a delisted symbol, so that it is not mistaken for a series
+    /// that has no entry at the requested date
+    fn require_entries(symbol: &str, series: TimeSeries) -> Result<TimeSeries, bank::error::BankError> {
+        if series.entries.is_empty() {
+            return Err(BankError::PriceUnavailable(symbol.to_string()));
+        }
+        Ok(series)
+    }
+
     /// Gets the time series intraday data for the given symbol and interval
     /// 
     /// # Errors
     /// 
-    /// Returns an error if the symbol is invalid
+    /// Returns an error if the symbol is invalid, or `BankError::PriceUnavailable` if the series has no entries
     /// 
     /// # Returns
     /// 
     /// Returns the time series data
+    #[instrument(level = "debug", skip(self, interval), err(level = "warn"))]
     pub async fn get_time_series_intraday(&self, symbol: &str, interval: IntradayInterval) ->Result<TimeSeries, bank::error::BankError>
     {
-        self.client.get_time_series_intraday(symbol, interval).await
-            .map_err(|e| bank::error::BankError::OtherTokio(e))?
-            .map_err(|e| bank::error::BankError::OtherAlphaVantage(e))
+        Self::require_entries(symbol, self.provider.intraday(symbol, interval).await?)
     }
 
+    /// Gets the full daily series for the given symbol
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the symbol is invalid, or `BankError::PriceUnavailable` if the series has no entries
+    #[instrument(level = "debug", skip(self), err(level = "warn"))]
     pub async fn get_time_series_daily_full(&self, symbol: &str) ->Result<TimeSeries, bank::error::BankError>
     {
-        self.client.get_time_series_daily_full(symbol).await
-            .map_err(|e| bank::error::BankError::OtherTokio(e))?
-            .map_err(|e| bank::error::BankError::OtherAlphaVantage(e))
+        Self::require_entries(symbol, self.provider.daily(symbol).await?)
     }
 
+    /// Gets the full weekly series, adjusted for splits and dividends for the given symbol
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the symbol is invalid, or `BankError::PriceUnavailable` if the series has no entries
+    #[instrument(level = "debug", skip(self), err(level = "warn"))]
     pub async fn get_time_series_weekly_full(&self, symbol: &str) ->Result<TimeSeries, bank::error::BankError>
     {
-        self.client.get_time_series_weekly_adjusted_full(symbol).await
-            .map_err(|e| bank::error::BankError::OtherTokio(e))?
-            .map_err(|e| bank::error::BankError::OtherAlphaVantage(e))
+        Self::require_entries(symbol, self.provider.weekly(symbol).await?)
     }
 
+    /// Gets the full monthly series, adjusted for splits and dividends for the given symbol
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the symbol is invalid, or `BankError::PriceUnavailable` if the series has no entries
+    #[instrument(level = "debug", skip(self), err(level = "warn"))]
     pub async fn get_time_series_monthly_full(&self, symbol: &str) ->Result<TimeSeries, bank::error::BankError>
     {
-        self.client.get_time_series_monthly_adjusted_full(symbol).await
-            .map_err(|e| bank::error::BankError::OtherTokio(e))?
-            .map_err(|e| bank::error::BankError::OtherAlphaVantage(e))
+        Self::require_entries(symbol, self.provider.monthly(symbol).await?)
     }
 
     /// Gets the price of a stock with the given symbol
     /// The price is the closing price of the most recent minute
+    /// Without extended hours, minutes outside the regular trading session are skipped
+    /// A price override for the symbol is returned instead, whatever the date limit
     /// 
     /// # Errors
     /// 
-    /// Returns an error if the symbol is invalid
+    /// Returns an error if the symbol is invalid, or `BankError::PriceUnavailable` if there is no price before the date limit
     /// 
     /// # Returns
     /// 
     /// Returns the price of the stock
-    pub async fn get_price(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, bank::error::BankError> {
+    pub async fn get_price(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, bank::error::BankError> {
+        if let Some(price) = self.price_overrides.get(symbol) {
+            return Ok(*price);
+        }
+        if self.manual_prices.contains_key(symbol) {
+            return self.manual_price(symbol, date_limit);
+        }
+        let price = self.fetch_price(symbol, date_limit).await?;
+        if let Some(last_prices) = &self.last_prices {
+            last_prices.record(symbol, date_limit.map(|date| date.to_utc()).unwrap_or_else(Utc::now), price);
+        }
+        Ok(price)
+    }
+
+    /// Gets the price of a symbol from the price source, or from AlphaVantage, as `get_price` does after the overrides and manual prices
+    async fn fetch_price(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, bank::error::BankError> {
+        if let Some(price_source) = &self.price_source {
+            return price_source.price(symbol, date_limit).await;
+        }
+        let session = match self.extended_hours {
+            true => None,
+            false => Some(self.regular_session_utc(symbol).await?),
+        };
         let price = self.get_time_series_intraday(symbol, IntradayInterval::FiveMinutes).await?
             .entries
             .iter()
@@ -89,12 +290,16 @@ impl Broker {
                     true
                 }
             })
+            .filter(|entry| match session {
+                Some((open, close)) => entry.date.to_utc().time() >= open && entry.date.to_utc().time() <= close,
+                None => true,
+            })
             .collect::<Vec<&time_series::Entry>>()
             .last()
-            .ok_or_else(||BankError::Other(String::from("Cannot find price")))
-            .map(|entry| entry.adjusted_close.unwrap_or(entry.close))?;
+            .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))
+            .and_then(|entry| self.intraday_price_field.price(symbol, entry))?;
 
-        Ok(price)
+        Ok(money::from_f64(price))
     }
 
     /// Gets the ticker for the given symbol
@@ -127,28 +332,60 @@ impl Broker {
         Ok(FixedOffset::east_opt(hours * 3600 + minutes * 60).unwrap())
     }
 
+    /// Whether the symbol trades at the given date, by the trading calendar and the hours of its exchange
     async fn is_market_open(&self, symbol: &str, date_limit: DateTime<FixedOffset>) -> Result<bool, BankError> {
+        // manually priced assets have no exchange to ask, so they trade on any trading day
+        if self.manual_prices.contains_key(symbol) {
+            return Ok(self.calendar.is_trading_day(date_limit.date_naive()));
+        }
+        if let Some(price_source) = &self.price_source {
+            if !self.calendar.is_trading_day(date_limit.date_naive()) {
+                return Ok(false)
+            }
+            return price_source.is_market_open(symbol, date_limit).await;
+        }
         let ticker = self.get_ticker(symbol.to_string()).await.map_err(|e| BankError::OtherTokio(e))??;
-        let market_open = ticker.market_open;
-        let market_close = ticker.market_close;
-        // println!("Market open: {}, Market close: {}", market_open, market_close);
-        // println!("tz:{}", ticker.timezone);
-        // println!("Date limit: {}", date_limit.to_utc().time());
         let offset = self.parse_utc_offset(&ticker.timezone)?;
-        // put the offset in the open and close times
-        // println!("Market open: {}, Market close: {}", market_open, market_close);
+        if !self.calendar.is_trading_day(date_limit.with_timezone(&offset).date_naive()) {
+            return Ok(false)
+        }
         // now, make sure it is currently trading, based on date_limit
-        println!("Oprn: {}, Close: {}", market_open - offset, market_close - offset);
-        println!("offset: {}", offset);
-        println!("Date limit: {}", date_limit.to_utc().time());
-        if date_limit.to_utc().time() < market_open - offset || date_limit.to_utc().time() > market_close - offset {
+        let (market_open, market_close) = self.regular_session_utc(symbol).await?;
+        debug!(
+            symbol,
+            open = %market_open,
+            close = %market_close,
+            at = %date_limit.to_utc().time(),
+            "checking market hours in UTC"
+        );
+        if date_limit.to_utc().time() < market_open || date_limit.to_utc().time() > market_close {
             return Ok(false)
         }
         Ok(true)
     }
 
+    /// Gets the open and close of a symbol's regular trading session, in UTC
+    async fn regular_session_utc(&self, symbol: &str) -> Result<(NaiveTime, NaiveTime), BankError> {
+        let ticker = self.get_ticker(symbol.to_string()).await.map_err(BankError::OtherTokio)??;
+        let offset = self.parse_utc_offset(&ticker.timezone)?;
+        // put the offset in the open and close times
+        Ok((ticker.market_open - offset, ticker.market_close - offset))
+    }
+
+    pub fn get_extended_hours(&self) -> bool {
+        self.extended_hours
+    }
+
+    /// Sets whether `get_price` uses pre and post market prices from AlphaVantage,
+    /// or only prices from the regular trading session
+    pub fn set_extended_hours(&mut self, extended_hours: bool) {
+        self.extended_hours = extended_hours;
+    }
+
     /// Buys a stock with the given symbol and quantity for the given account
     /// The price is the closing price of the most recent minute
+    /// The commission schedule is charged on top of the cost of the shares
+    /// The slippage model moves the fill price above the quote
     /// 
     /// # Arguments
     /// 
@@ -159,28 +396,60 @@ impl Broker {
     /// 
     /// # Errors
     /// 
-    /// Returns an error if the symbol is invalid, the account does not have enough funds, or the market is closed
+    /// Returns an error if the symbol is invalid, the account does not have enough funds, or `BankError::MarketClosed` if the market is closed.
+    /// Under the strict wash sale rule, also returns an error if the symbol was sold at a loss within the wash sale window.
+    /// With a position limit set, also returns an error if the holding would grow past it and the buy is not trimmed.
     /// 
     /// # Returns
     /// 
     /// Returns the new balance of the account
-    pub async fn buy(&mut self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, bank::error::BankError>{
-        let price = self.get_price(symbol, date_limit).await?;
+    pub async fn buy(&mut self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, bank::error::BankError>{
+        self.buy_tagged(symbol, quantity, account_id, date_limit, tags::MANUAL).await
+    }
+
+    /// Buys a stock as in `buy`, tagging the transactions it records with the given tag
+    #[instrument(skip(self), err(level = "warn"))]
+    pub(crate) async fn buy_tagged(&mut self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, tag: &str) -> Result<Decimal, bank::error::BankError>{
+        let price = self.draw_fill_price(self.get_price(symbol, date_limit).await?, true);
         match self.is_market_open(symbol, date_limit.unwrap_or(chrono::Utc::now().into())).await?{
             true => {},
-            false => return Err(BankError::Other(String::from("Market is closed"))),
+            false => return Err(BankError::MarketClosed),
         }
+        let quantity = self.apply_position_limit(account_id, symbol, price, quantity, date_limit).await?;
+        let cost = price * money::from_f64(quantity);
+        let commission = self.commission_schedule.commission(cost, quantity);
         let mut bank = self.bank
             .lock()
             .await;
 
         let account = bank.get_investment_account_mut(account_id)?;
-        account.purchase_investment(symbol.to_string(), price, quantity)?;
+        if account.is_frozen() {
+            return Err(BankError::AccountFrozen);
+        }
+        if account.get_balance() < cost + commission {
+            return Err(BankError::InsufficientFunds);
+        }
+        let trade_date = date_limit.map(|date| date.to_utc()).unwrap_or(chrono::Utc::now());
+        if self.wash_sale_rule == WashSaleRule::Strict && account.would_wash_sale(symbol, trade_date) {
+            return Err(BankError::WashSale);
+        }
+        let first_new_transaction = account.transactions.len();
+        account.purchase_investment_at(symbol.to_string(), price, quantity, trade_date)?;
+        account.charge_commission_at(symbol.to_string(), commission, trade_date)?;
+        if self.wash_sale_rule == WashSaleRule::Advisory {
+            account.record_wash_sale(symbol, quantity, trade_date);
+        }
+        for transaction in account.transactions[first_new_transaction..].iter_mut() {
+            transaction.add_tag(tag);
+        }
+        info!(%price, %commission, balance = %account.get_balance(), "bought");
 
         Ok(account.get_balance())
     }
 
     /// Sell a stock
+    /// The commission schedule is charged out of the proceeds
+    /// The slippage model moves the fill price below the quote
     /// 
     /// # Arguments
     /// 
@@ -196,38 +465,83 @@ impl Broker {
     /// # Returns
     /// 
     /// Returns the new balance of the account
-    pub async fn sell(&mut self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, bank::error::BankError>{
-        let price = self.get_price(symbol, date_limit).await?;
-        
+    pub async fn sell(&mut self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, bank::error::BankError>{
+        self.sell_tagged(symbol, quantity, account_id, date_limit, tags::MANUAL).await
+    }
+
+    /// Sells a fraction of a holding, such as 0.5 for half of it
+    /// What the sale would leave is sold too if it is under the account's dust threshold
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the fraction is not above 0 and at most 1, the holding does not exist, or the sale fails
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the new balance of the account
+    pub async fn sell_fraction(&mut self, symbol: &str, fraction: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, bank::error::BankError>{
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(BankError::Other(format!("A fraction to sell must be above 0 and at most 1, not {}", fraction)));
+        }
+        let held = self.bank.lock().await
+            .get_investment_account(account_id)?
+            .get_investments()
+            .get(symbol)
+            .map(|holding| holding.quantity)
+            .ok_or(BankError::HoldingNotFound)?;
+        self.sell(symbol, held * fraction, account_id, date_limit).await
+    }
+
+    /// Sells a stock as in `sell`, tagging the transactions it records with the given tag
+    #[instrument(skip(self), err(level = "warn"))]
+    pub(crate) async fn sell_tagged(&mut self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, tag: &str) -> Result<Decimal, bank::error::BankError>{
+        let price = self.draw_fill_price(self.get_price(symbol, date_limit).await?, false);
+        self.check_day_trade(account_id, symbol, date_limit).await?;
+
         let mut bank = self.bank
             .lock()
             .await;
 
         let account = bank.get_investment_account_mut(account_id)?;
-        account.sell_investment(symbol.to_string(), price, quantity)?;
+        if account.is_frozen() {
+            return Err(BankError::AccountFrozen);
+        }
+        // the account may round the quantity or sell off dust, so charge commission on what is actually sold
+        let quantity = account.sale_quantity(symbol, quantity)?;
+        let proceeds = price * money::from_f64(quantity);
+        let commission = self.commission_schedule.commission(proceeds, quantity);
+        if account.get_balance() + proceeds < commission {
+            return Err(BankError::InsufficientFunds);
+        }
+        let trade_date = date_limit.map(|date| date.to_utc()).unwrap_or(chrono::Utc::now());
+        let first_new_transaction = account.transactions.len();
+        account.sell_investment_at(symbol.to_string(), price, quantity, trade_date)?;
+        account.charge_commission_at(symbol.to_string(), commission, trade_date)?;
+        for transaction in account.transactions[first_new_transaction..].iter_mut() {
+            transaction.add_tag(tag);
+        }
+        info!(%price, %commission, balance = %account.get_balance(), "sold");
 
         Ok(account.get_balance())
     }
 
 
     /// Query for a list of ticker symbols that match the given query
+    #[instrument(level = "debug", skip(self), err(level = "warn"))]
     pub async fn get_tickers(&self, query: &str) -> Result<SearchResults, bank::error::BankError> {
-        self.client.get_tickers(query).await
-            .map_err(|e| bank::error::BankError::OtherTokio(e))?
-            .map_err(|e| bank::error::BankError::OtherAlphaVantage(e))
+        self.provider.tickers(query).await
     }
 
     /// Gets the current value of the given quantity of the stock with the given symbol
     /// The price is the closing price of the most recent minute, before the given date limit
-    pub async fn get_current_value(&self, symbol: &str, quantity: f64, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, bank::error::BankError> {
+    pub async fn get_current_value(&self, symbol: &str, quantity: f64, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, bank::error::BankError> {
         let price = self.get_price(symbol, date_limit).await?;
-        Ok(price * quantity)
+        Ok(price * money::from_f64(quantity))
     }
 
+    #[instrument(level = "debug", skip(self), err(level = "warn"))]
     async fn load_dividend_data(&self, symbol: &str) -> Result<DividendResults, bank::error::BankError> {
-        self.client.get_dividend_data(symbol).await
-            .map_err(|e| bank::error::BankError::OtherTokio(e))?
-            .map_err(|e| bank::error::BankError::OtherAlphaVantage(e))
+        self.provider.dividends(symbol).await
     }
 
     /// Checks for the earliest dividend payment date for all accounts
@@ -240,6 +554,9 @@ impl Broker {
         let cache_path = expand_tilde("~/.cache/trading_engine/earliest_dividend_date.json");
         // if the file does not exist, then we have never checked for dividends before
         if !cache_path.exists() {
+            if let Some(cache_root) = cache_path.parent() {
+                crate::state::create_private_dir_all(cache_root)?;
+            }
             // write NOW
             let date = date_time.to_rfc3339();
             tokio::fs::write(cache_path, date).await?;
@@ -255,6 +572,7 @@ impl Broker {
     /// Checks for dividend payments for all accounts, and all assets, at a given date.
     /// If the date is not provided, it will use the current date
     /// If there is a payment on the date, it will add the payment to all investment accounts
+    /// that held the stock before its ex-dividend date, or as set by the dividend eligibility
     /// Before adding the payment, we check to make sure the transaction has not already been added
     /// 
     /// # Arguments
@@ -262,79 +580,143 @@ impl Broker {
     /// * `date` - The date to check for dividend payments
     /// 
     /// TODO: Test this somehow
+    #[instrument(skip(self), err)]
     pub async fn check_dividend_payments(&self, date: Option<DateTime<FixedOffset>>) -> Result<(), bank::error::BankError> {
         let date = date.unwrap_or(chrono::Utc::now().into());
         let last_loaded = 
             self.check_earliest_dividend(date)
             .await
             .map_err(|e| BankError::OtherTokio(e))?;
-        let mut valid_dividend_data_memoized : HashMap<String, Vec<DividendEntry>> = HashMap::new();
-        // now, we have the last loaded date, we can check for dividend payments
-        // only find payments after last loaded, and before or equal to todays date. ignore time.
-        // we do last loaded because anything before last loaded it is impossible to have an asset
-        let mut bank = self.bank.lock().await;
-        for (_, account) in bank.get_investment_accounts_mut().iter_mut() {
-            let mut transactions_to_add = Vec::new();
-            for (symbol, holding) in account.assets.iter() {
-                let valid_dividend_data = match valid_dividend_data_memoized.get(symbol){
-                    Some(data) => data,
-                    None => {
-                        let data = self.parse_valid_dividend_data(symbol, &last_loaded, &date).await?;
-                        valid_dividend_data_memoized.insert(symbol.clone(), data.clone());
-                        valid_dividend_data_memoized.get(symbol).unwrap()
+        debug!(%last_loaded, "checking for dividends since the last check");
+        // the last check already paid the dividends on its own date
+        self.pay_dividends(None, (Bound::Excluded(last_loaded.date_naive()), Bound::Included(date.date_naive()))).await?;
+        Ok(())
+    }
+
+    /// Pays every dividend owed since a date to an account, ignoring when dividends were last checked.
+    /// This is for holdings whose history was imported after the engine had already moved past it,
+    /// so the dividends earned in the imported period were never paid.
+    /// The shares held at each dividend's cutoff are taken from the account's history,
+    /// and dividends already in the account are not paid again.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `since` - The first payment date to pay dividends from, included
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, or the dividend data cannot be loaded
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the number of dividends paid
+    #[instrument(skip(self), err)]
+    pub async fn backfill_dividends(&self, account_id: u32, since: DateTime<FixedOffset>) -> Result<usize, BankError> {
+        self.bank.lock().await.get_investment_account(account_id)?;
+        self.pay_dividends(Some(account_id), since.date_naive()..=Utc::now().date_naive()).await
+    }
+
+    /// Pays the dividends with a payment date in the range, to one account or all of them
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the number of dividends paid
+    async fn pay_dividends(&self, only_account: Option<u32>, payment_dates: impl RangeBounds<NaiveDate>) -> Result<usize, BankError> {
+        // snapshot every symbol each account has held, so the bank is not locked while the dividend data is fetched.
+        // symbols sold since are included, as they may still be owed a dividend that went ex before the sale
+        let holdings: Vec<(u32, Asset)> = {
+            let bank = self.bank.lock().await;
+            let mut holdings = Vec::new();
+            for (id, account) in bank.get_investment_accounts() {
+                if only_account.is_some_and(|only| only != *id) {
+                    continue;
+                }
+                let mut assets: Vec<Asset> = account.assets.values().map(|holding| holding.asset.clone()).collect();
+                for transaction in account.transactions.iter() {
+                    if let TransactionType::Purchase(asset, _) = &transaction.transaction_type {
+                        if !assets.contains(asset) {
+                            assets.push(asset.clone());
+                        }
                     }
-                };
-                // filter out transactions that are not dividends for the current holding
-                let holding_filtered_transactions: Vec<&Transaction> = account.transactions
+                }
+                holdings.extend(assets.into_iter().map(|asset| (*id, asset)));
+            }
+            holdings
+        };
+        // only find payments in the range. ignore time.
+        let mut valid_dividend_data: HashMap<String, Vec<DividendEntry>> = HashMap::new();
+        for (_, asset) in holdings.iter() {
+            if !valid_dividend_data.contains_key(&asset.symbol) {
+                let data = self.parse_valid_dividend_data(&asset.symbol, &payment_dates).await?;
+                valid_dividend_data.insert(asset.symbol.clone(), data);
+            }
+        }
+
+        let mut bank = self.bank.lock().await;
+        let mut transactions_to_add: HashMap<u32, Vec<Transaction>> = HashMap::new();
+        for (account_id, asset) in holdings {
+            // the account may have been closed while the data was fetched
+            let Ok(account) = bank.get_investment_account(account_id) else {
+                continue;
+            };
+            let symbol = &asset.symbol;
+            for dividend in &valid_dividend_data[symbol] {
+                let payment_date = dividend.payment_date.unwrap();
+                // make sure the transaction has not already occurred on the date
+                let already_paid = account.transactions
                     .iter()
-                    .filter(|&transaction| {
-                        transaction.transaction_type == TransactionType::Dividend(holding.asset.clone(), holding.quantity)
-                    })
-                    .collect();
-                for dividend in valid_dividend_data {
-                    // make sure the transaction has not already occurred on the date
-                    let dividend_transactions: Vec<&&Transaction> = 
-                        holding_filtered_transactions
-                        .iter()
-                        .filter(|&transaction| {
-                            transaction.date.date_naive() == dividend.payment_date.unwrap()
-                        })
-                        .collect();
-                    if dividend_transactions.len() == 0 { // if the transaction has not already occurred
-                        let naive_datetime = dividend.payment_date.unwrap().and_hms_opt(0, 0, 0).unwrap();
-                        // we need to pay this dividend
-                        let payout = holding.quantity.clone() * dividend.amount;
-                        let transaction = Transaction::new(
-                            TransactionType::Dividend(holding.asset.clone(), holding.quantity.clone()), 
-                            payout, 
-                            Utc.from_utc_datetime(&naive_datetime), 
-                            Some(format!("Dividend payment for {} on {}", symbol, dividend.payment_date.unwrap().to_string()))
-                        );
-                        transactions_to_add.push(transaction); // add the transaction to todo list
-                    }
+                    .any(|transaction| matches!(&transaction.transaction_type, TransactionType::Dividend(paid, _) if *paid == asset)
+                        && transaction.date.date_naive() == payment_date);
+                if already_paid {
+                    continue;
+                }
+                // only the shares owned before the cutoff are paid, even if they have been sold since
+                let Some(held_at) = self.dividend_eligibility.held_at(dividend) else {
+                    continue;
+                };
+                let quantity = account.positions_at(held_at).1.get(symbol).copied().unwrap_or(0.0);
+                if quantity <= 0.0 {
+                    continue;
                 }
+                let naive_datetime = payment_date.and_hms_opt(0, 0, 0).unwrap();
+                // we need to pay this dividend
+                let payout = money::from_f64(quantity) * money::from_f64(dividend.amount);
+                let transaction = Transaction::new(
+                    TransactionType::Dividend(asset.clone(), quantity),
+                    payout,
+                    Utc.from_utc_datetime(&naive_datetime),
+                    Some(format!("Dividend payment for {} on {}", symbol, payment_date))
+                ).with_tags(&[tags::DIVIDEND]);
+                transactions_to_add.entry(account_id).or_default().push(transaction); // add the transaction to todo list
             }
-            // add the transactions
-            for transaction in transactions_to_add {
-                account.add_transaction(transaction);
+        }
+        // add the transactions
+        let mut paid = 0;
+        for (account_id, transactions) in transactions_to_add {
+            info!(account_id, payments = transactions.len(), "paying dividends");
+            let account = bank.get_investment_account_mut(account_id)?;
+            paid += transactions.len();
+            for transaction in transactions {
+                account.pay_dividend(transaction);
             }
         }
-        Ok(())
+        Ok(paid)
     }
 
     /**
-     * Parse the dividend data to only include dividends that are valid based on when we last loaded them.
+     * Parse the dividend data to only include dividends that are paid in the range of payment dates.
      * 
      * For example, if the engine is called 1 day ago, we filter out all dividends that were paid before 1 day ago.
      */
-    async fn parse_valid_dividend_data(&self, symbol: &str, last_loaded: &DateTime<FixedOffset>, date: &DateTime<FixedOffset>) -> Result<Vec<DividendEntry>, bank::error::BankError> {
+    async fn parse_valid_dividend_data(&self, symbol: &str, payment_dates: &impl RangeBounds<NaiveDate>) -> Result<Vec<DividendEntry>, bank::error::BankError> {
         let valid_dividend_data: Vec<DividendEntry> = 
             self.load_dividend_data(&symbol).await?
             .data
             .into_iter()
             .filter(|dividend| {
                 if let Some(dividend_date) = dividend.payment_date {
-                    dividend_date > last_loaded.date_naive() && dividend_date <= date.date_naive()
+                    payment_dates.contains(&dividend_date)
                 } else {
                     false
                 }
@@ -362,6 +744,96 @@ pub(crate) mod tests{
     use std::env;
     use super::*;
 
+    #[tokio::test]
+    async fn test_dividends_paid_on_backtested_holdings(){
+        use chrono::TimeZone;
+        use rust_decimal_macros::dec;
+
+        use crate::{bank::accounts::AccountType, brokerage::{prices::MockPriceSource, provider::mock::{self, MockProvider}}};
+
+        let ymd = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        let bought = chrono::Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", bought, dec!(100.0)));
+        broker.set_market_data_provider(MockProvider::new().with_dividends("AAPL", vec![
+            // went ex before the purchase, so it is not owed
+            mock::dividend(ymd(1, 1), ymd(1, 5), 1.0),
+            mock::dividend(ymd(2, 9), ymd(2, 15), 0.25),
+        ]));
+        broker.buy("AAPL", 10.0, id, Some(bought.fixed_offset())).await.unwrap();
+
+        assert_eq!(broker.pay_dividends(None, ymd(1, 1)..=ymd(3, 1)).await.unwrap(), 1);
+        let bank = broker.bank.lock().await;
+        let dividend = bank.get_investment_account(id).unwrap().transactions.last().unwrap().clone();
+        assert!(matches!(dividend.transaction_type, TransactionType::Dividend(_, quantity) if quantity == 10.0));
+        assert_eq!(dividend.amount, dec!(2.5));
+        assert_eq!(dividend.date.date_naive(), ymd(2, 15));
+    }
+
+    #[tokio::test]
+    async fn test_backfill_dividends(){
+        use chrono::TimeZone;
+        use rust_decimal_macros::dec;
+
+        use crate::{bank::accounts::AccountType, brokerage::{prices::MockPriceSource, provider::mock::{self, MockProvider}}};
+
+        let ymd = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        let bought = chrono::Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", bought, dec!(100.0)));
+        broker.set_market_data_provider(MockProvider::new().with_dividends("AAPL", vec![
+            // went ex the day the shares were bought, so it is not owed
+            mock::dividend(ymd(1, 2), ymd(2, 5), 1.0),
+            // paid before the backfill starts
+            mock::dividend(ymd(1, 10), ymd(1, 31), 1.0),
+            // paid the day the backfill starts
+            mock::dividend(ymd(1, 25), ymd(2, 1), 0.5),
+            mock::dividend(ymd(2, 9), ymd(2, 15), 0.25),
+        ]));
+        broker.buy("AAPL", 10.0, id, Some(bought.fixed_offset())).await.unwrap();
+
+        let since = chrono::Utc.with_ymd_and_hms(2024, 2, 1, 12, 0, 0).unwrap().fixed_offset();
+        assert_eq!(broker.backfill_dividends(id, since).await.unwrap(), 2);
+        // dividends already paid are not paid again
+        assert_eq!(broker.backfill_dividends(id, since).await.unwrap(), 0);
+        let bank = broker.bank.lock().await;
+        let paid: Vec<_> = bank.get_investment_account(id).unwrap().transactions
+            .iter()
+            .filter(|transaction| matches!(transaction.transaction_type, TransactionType::Dividend(..)))
+            .map(|transaction| (transaction.date.date_naive(), transaction.amount))
+            .collect();
+        assert_eq!(paid, vec![(ymd(2, 1), dec!(5.0)), (ymd(2, 15), dec!(2.5))]);
+    }
+
+    #[tokio::test]
+    async fn test_wash_sale_window_follows_trade_dates(){
+        use chrono::TimeZone;
+        use rust_decimal_macros::dec;
+
+        use crate::{bank::accounts::AccountType, brokerage::prices::MockPriceSource};
+
+        let date = |month, day| chrono::Utc.with_ymd_and_hms(2024, month, day, 15, 0, 0).unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_series("AAPL", vec![(date(1, 2), dec!(100.0)), (date(2, 1), dec!(80.0))]));
+        broker.set_wash_sale_rule(WashSaleRule::Strict);
+
+        broker.buy("AAPL", 4.0, id, Some(date(1, 2).fixed_offset())).await.unwrap();
+        broker.sell("AAPL", 4.0, id, Some(date(2, 1).fixed_offset())).await.unwrap();
+        assert_eq!(broker.bank.lock().await.get_investment_account(id).unwrap().closed_lots[0].closed_at, date(2, 1));
+        // within 30 days of the sale at a loss
+        assert!(matches!(broker.buy("AAPL", 1.0, id, Some(date(2, 20).fixed_offset())).await, Err(BankError::WashSale)));
+        broker.buy("AAPL", 1.0, id, Some(date(3, 5).fixed_offset())).await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_dividend_check(){
         let client = Client::new(env::var("ALPHAVANTAGE_TOKEN").unwrap().as_str());
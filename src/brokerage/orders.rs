@@ -0,0 +1,470 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::{info, instrument, warn};
+
+use crate::bank::{error::BankError, transactions::{tags, TransactionType}};
+
+use super::Broker;
+
+pub type OrderId = u64;
+
+/// When an order fills, and on which side
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OrderKind{
+    /// Buys once the price is at or below the limit
+    LimitBuy{ limit: Decimal },
+    /// Sells once the price is at or above the limit
+    LimitSell{ limit: Decimal },
+    /// Sells once the price is at or below the stop
+    StopSell{ stop: Decimal },
+}
+
+impl OrderKind{
+    /// Whether an order of this kind fills at the given price
+    pub fn is_triggered(&self, price: Decimal) -> bool{
+        match self{
+            OrderKind::LimitBuy{ limit } => price <= *limit,
+            OrderKind::LimitSell{ limit } => price >= *limit,
+            OrderKind::StopSell{ stop } => price <= *stop,
+        }
+    }
+
+    pub fn is_buy(&self) -> bool{
+        matches!(self, OrderKind::LimitBuy{ .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OrderStatus{
+    /// Waiting on its parent to fill before it becomes active
+    Waiting,
+    /// Checked against the price every time orders are processed
+    Active,
+    Filled{ price: Decimal },
+    Cancelled,
+    /// The order reached its expiry before it filled
+    Expired,
+    /// The trade failed when the order was triggered
+    Rejected(String),
+}
+
+impl OrderStatus{
+    /// Whether the order can no longer change
+    pub fn is_final(&self) -> bool{
+        matches!(self, OrderStatus::Filled{ .. } | OrderStatus::Cancelled | OrderStatus::Expired | OrderStatus::Rejected(_))
+    }
+}
+
+/// A change of an order's status
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderTransition{
+    pub status: OrderStatus,
+    pub at: DateTime<Utc>,
+}
+
+/// An order waiting on a price condition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Order{
+    pub id: OrderId,
+    pub account_id: u32,
+    pub symbol: String,
+    pub quantity: f64,
+    pub kind: OrderKind,
+    pub status: OrderStatus,
+    /// The order that must fill before this one becomes active
+    pub parent: Option<OrderId>,
+    /// The order that is cancelled when this one fills, and that cancels this one when it fills
+    pub one_cancels_other: Option<OrderId>,
+    /// Every status the order has had, oldest first
+    pub transitions: Vec<OrderTransition>,
+    /// When the order stops being worked, if it has not filled by then
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// What happened to the order book when it was brought up to date after the engine was stopped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrderReconciliation{
+    /// The orders whose expiry passed while the engine was stopped, and that will never fill
+    pub expired: Vec<OrderId>,
+    /// The orders that filled at the current price
+    pub filled: Vec<OrderId>,
+}
+
+impl Order{
+    fn transition(&mut self, status: OrderStatus, at: DateTime<Utc>){
+        self.transitions.push(OrderTransition{ status: status.clone(), at });
+        self.status = status;
+    }
+}
+
+/// Every order the broker has been given, which can be saved and restored with serde
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OrderBook{
+    orders: Vec<Order>,
+    next_id: OrderId,
+}
+
+impl OrderBook{
+    pub fn get_orders(&self) -> &[Order]{
+        &self.orders
+    }
+
+    pub fn get_order(&self, id: OrderId) -> Option<&Order>{
+        self.orders.iter().find(|order| order.id == id)
+    }
+
+    fn get_order_mut(&mut self, id: OrderId) -> Result<&mut Order, BankError>{
+        self.orders.iter_mut().find(|order| order.id == id).ok_or(BankError::OrderNotFound)
+    }
+
+    pub(crate) fn add(&mut self, account_id: u32, symbol: &str, quantity: f64, kind: OrderKind, status: OrderStatus, parent: Option<OrderId>) -> OrderId{
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut order = Order{
+            id,
+            account_id,
+            symbol: symbol.to_string(),
+            quantity,
+            kind,
+            status: status.clone(),
+            parent,
+            one_cancels_other: None,
+            transitions: Vec::new(),
+            expires_at: None,
+        };
+        order.transition(status, Utc::now());
+        self.orders.push(order);
+        id
+    }
+
+    /// Cancels an order and every order waiting on it
+    fn cancel(&mut self, id: OrderId, at: DateTime<Utc>){
+        let children: Vec<OrderId> = self.orders
+            .iter()
+            .filter(|order| order.parent == Some(id))
+            .map(|order| order.id)
+            .collect();
+        if let Ok(order) = self.get_order_mut(id){
+            if !order.status.is_final(){
+                order.transition(OrderStatus::Cancelled, at);
+            }
+        }
+        for child in children{
+            self.cancel(child, at);
+        }
+    }
+
+    /// Expires every order whose expiry is at or before the given time, cancelling the orders waiting on them
+    ///
+    /// # Returns
+    ///
+    /// Returns the ids of the orders that expired
+    fn expire(&mut self, at: DateTime<Utc>) -> Vec<OrderId>{
+        let expired: Vec<OrderId> = self.orders
+            .iter()
+            .filter(|order| !order.status.is_final() && order.expires_at.is_some_and(|expires_at| expires_at <= at))
+            .map(|order| order.id)
+            .collect();
+        for id in expired.iter(){
+            let children: Vec<OrderId> = self.orders.iter().filter(|order| order.parent == Some(*id)).map(|order| order.id).collect();
+            if let Ok(order) = self.get_order_mut(*id){
+                let expired_at = order.expires_at.unwrap_or(at);
+                order.transition(OrderStatus::Expired, expired_at);
+            }
+            for child in children{
+                self.cancel(child, at);
+            }
+        }
+        expired
+    }
+}
+
+impl Broker{
+    pub fn get_order_book(&self) -> &OrderBook{
+        &self.orders
+    }
+
+    /// Replaces the order book, such as with one loaded from disk
+    pub fn set_order_book(&mut self, orders: OrderBook){
+        self.orders = orders;
+    }
+
+    /// Places a bracket order: a limit buy that, once filled, activates a take-profit limit sell and a stop-loss for the
+    /// quantity it filled, which the position limit may have trimmed. Whichever of the two fills first cancels the other.
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol to trade
+    /// * `quantity` - The quantity to buy, and later sell
+    /// * `entry_limit` - The highest price to buy at
+    /// * `take_profit` - The price at or above which to sell for a profit
+    /// * `stop_loss` - The price at or below which to sell to cut the loss
+    /// * `account_id` - The id of the investment account to trade in
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::InvalidOrder` unless the quantity is positive and `stop_loss < entry_limit < take_profit`
+    ///
+    /// # Returns
+    ///
+    /// Returns the id of the entry order
+    pub fn place_bracket_order(&mut self, symbol: &str, quantity: f64, entry_limit: Decimal, take_profit: Decimal, stop_loss: Decimal, account_id: u32) -> Result<OrderId, BankError>{
+        if quantity <= 0.0{
+            return Err(BankError::InvalidOrder(String::from("quantity must be positive")));
+        }
+        if !(stop_loss < entry_limit && entry_limit < take_profit){
+            return Err(BankError::InvalidOrder(String::from("expected stop loss < entry limit < take profit")));
+        }
+        let entry = self.orders.add(account_id, symbol, quantity, OrderKind::LimitBuy{ limit: entry_limit }, OrderStatus::Active, None);
+        let profit = self.orders.add(account_id, symbol, quantity, OrderKind::LimitSell{ limit: take_profit }, OrderStatus::Waiting, Some(entry));
+        let stop = self.orders.add(account_id, symbol, quantity, OrderKind::StopSell{ stop: stop_loss }, OrderStatus::Waiting, Some(entry));
+        self.orders.get_order_mut(profit)?.one_cancels_other = Some(stop);
+        self.orders.get_order_mut(stop)?.one_cancels_other = Some(profit);
+        Ok(entry)
+    }
+
+    /// Sets when an order stops being worked if it has not filled, or removes its expiry.
+    /// Orders waiting on it are cancelled when it expires
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::OrderNotFound` if there is no such order, or `BankError::InvalidOrder` if it is already final
+    pub fn set_order_expiry(&mut self, id: OrderId, expires_at: Option<DateTime<Utc>>) -> Result<(), BankError>{
+        let order = self.orders.get_order_mut(id)?;
+        if order.status.is_final(){
+            return Err(BankError::InvalidOrder(format!("order {} can no longer change", id)));
+        }
+        order.expires_at = expires_at;
+        Ok(())
+    }
+
+    /// Cancels an order that has not filled yet, along with any orders waiting on it
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::OrderNotFound` if there is no such order
+    pub fn cancel_order(&mut self, id: OrderId) -> Result<(), BankError>{
+        self.orders.get_order_mut(id)?;
+        self.orders.cancel(id, Utc::now());
+        Ok(())
+    }
+
+    /// Brings an order book loaded from disk up to date: orders whose expiry passed while the engine was stopped
+    /// are expired without being checked against the prices they missed, and the rest are processed at the current time
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time, or the date limit when simulating
+    #[instrument(skip(self))]
+    pub async fn reconcile_orders(&mut self, now: Option<DateTime<FixedOffset>>) -> Result<OrderReconciliation, BankError>{
+        let at = now.map(|date| date.to_utc()).unwrap_or(Utc::now());
+        let expired = self.orders.expire(at);
+        if !expired.is_empty(){
+            warn!(?expired, "orders expired while the engine was stopped");
+        }
+        let filled = self.process_orders(now).await?;
+        Ok(OrderReconciliation{ expired, filled })
+    }
+
+    /// Checks every active order against the price at the date limit, and trades the ones that are triggered.
+    /// Orders past their expiry are expired first, and never fill.
+    /// Orders activated by a fill are first checked on the next call.
+    /// Orders that cannot be priced, or whose market is closed, stay active.
+    ///
+    /// # Returns
+    ///
+    /// Returns the ids of the orders that filled
+    #[instrument(skip(self))]
+    pub async fn process_orders(&mut self, date_limit: Option<DateTime<FixedOffset>>) -> Result<Vec<OrderId>, BankError>{
+        let at = date_limit.map(|date| date.to_utc()).unwrap_or(Utc::now());
+        for id in self.orders.expire(at){
+            info!(id, "order expired");
+        }
+        let active: Vec<Order> = self.orders.orders
+            .iter()
+            .filter(|order| order.status == OrderStatus::Active)
+            .cloned()
+            .collect();
+        let mut filled = Vec::new();
+        for order in active{
+            // a sibling that filled earlier in this pass may have cancelled it
+            if self.orders.get_order(order.id).map(|order| &order.status) != Some(&OrderStatus::Active){
+                continue;
+            }
+            let price = match self.get_price(&order.symbol, date_limit).await{
+                Ok(price) => price,
+                Err(BankError::PriceUnavailable(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            if !order.kind.is_triggered(price){
+                continue;
+            }
+            let result = if order.kind.is_buy(){
+                self.buy_tagged(&order.symbol, order.quantity, order.account_id, date_limit, tags::ORDER).await
+            }else{
+                self.sell_tagged(&order.symbol, order.quantity, order.account_id, date_limit, tags::ORDER).await
+            };
+            match result{
+                Ok(_) => {
+                    let quantity = if order.kind.is_buy(){
+                        self.last_purchase_quantity(order.account_id, &order.symbol).await?
+                    }else{
+                        order.quantity
+                    };
+                    info!(id = order.id, symbol = %order.symbol, %price, quantity, "order filled");
+                    self.orders.get_order_mut(order.id)?.transition(OrderStatus::Filled{ price }, at);
+                    // the exits sell what the entry bought, which may be less than it asked for
+                    for child in self.orders.orders.iter_mut().filter(|child| child.parent == Some(order.id) && child.status == OrderStatus::Waiting){
+                        child.quantity = quantity;
+                        child.transition(OrderStatus::Active, at);
+                    }
+                    if let Some(sibling) = order.one_cancels_other{
+                        self.orders.cancel(sibling, at);
+                    }
+                    filled.push(order.id);
+                },
+                Err(BankError::MarketClosed | BankError::PriceUnavailable(_)) => {},
+                Err(e) => {
+                    self.orders.get_order_mut(order.id)?.transition(OrderStatus::Rejected(e.to_string()), at);
+                    let children: Vec<OrderId> = self.orders.orders.iter().filter(|child| child.parent == Some(order.id)).map(|child| child.id).collect();
+                    for child in children{
+                        self.orders.cancel(child, at);
+                    }
+                },
+            }
+        }
+        Ok(filled)
+    }
+
+    /// The quantity of the most recent purchase of a symbol in an account
+    async fn last_purchase_quantity(&self, account_id: u32, symbol: &str) -> Result<f64, BankError>{
+        let bank = self.bank.lock().await;
+        bank.get_investment_account(account_id)?
+            .transactions
+            .iter()
+            .rev()
+            .find_map(|transaction| match &transaction.transaction_type{
+                TransactionType::Purchase(asset, quantity) if asset.symbol == symbol => Some(*quantity),
+                _ => None,
+            })
+            .ok_or(BankError::HoldingNotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    fn day(day: u32) -> DateTime<FixedOffset> {
+        Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap().fixed_offset()
+    }
+
+    fn broker(prices: &[(u32, Decimal)]) -> (Broker, u32){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_series("AAPL", prices.iter().map(|(d, price)| (day(*d).to_utc(), *price))));
+        (broker, id)
+    }
+
+    #[tokio::test]
+    async fn test_bracket_take_profit(){
+        let (mut broker, id) = broker(&[(2, dec!(105.0)), (3, dec!(99.0)), (4, dec!(111.0)), (5, dec!(80.0))]);
+        let entry = broker.place_bracket_order("AAPL", 2.0, dec!(100.0), dec!(110.0), dec!(90.0), id).unwrap();
+
+        // above the entry limit, nothing happens
+        assert!(broker.process_orders(Some(day(2))).await.unwrap().is_empty());
+        assert_eq!(broker.process_orders(Some(day(3))).await.unwrap(), vec![entry]);
+        assert_eq!(broker.process_orders(Some(day(4))).await.unwrap(), vec![entry + 1]);
+        // the stop was cancelled when the take profit filled
+        assert!(broker.process_orders(Some(day(5))).await.unwrap().is_empty());
+
+        let book = broker.get_order_book();
+        assert_eq!(book.get_order(entry).unwrap().status, OrderStatus::Filled{ price: dec!(99.0) });
+        assert_eq!(book.get_order(entry + 1).unwrap().status, OrderStatus::Filled{ price: dec!(111.0) });
+        let stop = book.get_order(entry + 2).unwrap();
+        assert_eq!(stop.status, OrderStatus::Cancelled);
+        assert_eq!(stop.transitions.len(), 3);
+        let bank = broker.get_bank();
+        assert_eq!(bank.lock().await.get_investment_account(id).unwrap().get_balance(), dec!(1024.0));
+    }
+
+    #[tokio::test]
+    async fn test_bracket_exits_follow_a_trimmed_entry(){
+        use crate::brokerage::limits::{PositionLimit, PositionLimitAction};
+
+        let (mut broker, id) = broker(&[(2, dec!(100.0)), (3, dec!(80.0))]);
+        broker.set_position_limit(Some(PositionLimit::new(dec!(0.25), PositionLimitAction::Trim)));
+        let entry = broker.place_bracket_order("AAPL", 5.0, dec!(100.0), dec!(110.0), dec!(90.0), id).unwrap();
+
+        // the entry is trimmed to the 2.5 shares the limit allows
+        assert_eq!(broker.process_orders(Some(day(2))).await.unwrap(), vec![entry]);
+        let book = broker.get_order_book();
+        assert_eq!(book.get_order(entry + 1).unwrap().quantity, 2.5);
+        assert_eq!(book.get_order(entry + 2).unwrap().quantity, 2.5);
+        assert_eq!(broker.process_orders(Some(day(3))).await.unwrap(), vec![entry + 2]);
+        let bank = broker.get_bank();
+        let bank = bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert!(account.get_investments().is_empty());
+        assert_eq!(account.get_balance(), dec!(950.0));
+    }
+
+    #[tokio::test]
+    async fn test_bracket_stop_loss_and_cancel(){
+        let (mut broker, id) = broker(&[(2, dec!(100.0)), (3, dec!(85.0))]);
+        let entry = broker.place_bracket_order("AAPL", 1.0, dec!(100.0), dec!(110.0), dec!(90.0), id).unwrap();
+        broker.process_orders(Some(day(2))).await.unwrap();
+        assert_eq!(broker.process_orders(Some(day(3))).await.unwrap(), vec![entry + 2]);
+        assert_eq!(broker.get_order_book().get_order(entry + 1).unwrap().status, OrderStatus::Cancelled);
+
+        let entry = broker.place_bracket_order("AAPL", 1.0, dec!(100.0), dec!(110.0), dec!(90.0), id).unwrap();
+        broker.cancel_order(entry).unwrap();
+        assert!(broker.get_order_book().get_orders().iter().filter(|order| order.id >= entry).all(|order| order.status == OrderStatus::Cancelled));
+        assert!(broker.place_bracket_order("AAPL", 1.0, dec!(100.0), dec!(90.0), dec!(110.0), id).is_err());
+
+        // the order book round trips through serde
+        let json = serde_json::to_string(broker.get_order_book()).unwrap();
+        let book: OrderBook = serde_json::from_str(&json).unwrap();
+        assert_eq!(book.get_orders().len(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_orders_expire_while_stopped(){
+        let (mut broker, id) = broker(&[(2, dec!(105.0)), (5, dec!(95.0))]);
+        let entry = broker.place_bracket_order("AAPL", 1.0, dec!(100.0), dec!(110.0), dec!(90.0), id).unwrap();
+        broker.set_order_expiry(entry, Some(day(3).to_utc())).unwrap();
+        assert!(broker.process_orders(Some(day(2))).await.unwrap().is_empty());
+        let json = serde_json::to_string(broker.get_order_book()).unwrap();
+
+        // the engine comes back after the expiry, when the price would have filled the entry
+        let (mut broker, id) = broker_with_book(&json, &[(5, dec!(95.0))]);
+        let keep = broker.place_bracket_order("AAPL", 1.0, dec!(100.0), dec!(110.0), dec!(90.0), id).unwrap();
+        let reconciliation = broker.reconcile_orders(Some(day(5))).await.unwrap();
+        assert_eq!(reconciliation, OrderReconciliation{ expired: vec![entry], filled: vec![keep] });
+        let book = broker.get_order_book();
+        assert_eq!(book.get_order(entry).unwrap().status, OrderStatus::Expired);
+        assert_eq!(book.get_order(entry).unwrap().transitions.last().unwrap().at, day(3).to_utc());
+        assert_eq!(book.get_order(entry + 1).unwrap().status, OrderStatus::Cancelled);
+        assert!(broker.set_order_expiry(entry, None).is_err());
+    }
+
+    fn broker_with_book(json: &str, prices: &[(u32, Decimal)]) -> (Broker, u32){
+        let (mut broker, id) = broker(prices);
+        broker.set_order_book(serde_json::from_str(json).unwrap());
+        (broker, id)
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+
+use crate::bank::{accounts::Account, error::BankError, money, transactions::TransactionType};
+
+use super::{metrics, Broker};
+
+/// How much one symbol made or lost an investment account over a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct HoldingAttribution {
+    pub symbol: String,
+    /// The gain of the lots sold in the window, against their cost basis
+    pub realized_gain: Decimal,
+    /// Every other change in the value of the holding, less what was paid for it and plus what it sold for
+    pub unrealized_gain: Decimal,
+    pub dividends: Decimal,
+    /// The realized and unrealized gains and the dividends together
+    pub total_gain: Decimal,
+    /// The total gain as a fraction of the capital in the account: its value at the start plus what was deposited in the window,
+    /// less what was withdrawn. The contributions of every symbol add up to the account's gain on that capital, less fees and interest
+    pub contribution: f64,
+}
+
+/// Gets the attribution of a symbol, starting it at nothing
+fn attribution_of<'a>(attributions: &'a mut BTreeMap<String, HoldingAttribution>, symbol: &str) -> &'a mut HoldingAttribution {
+    attributions.entry(symbol.to_string()).or_insert_with(|| HoldingAttribution {
+        symbol: symbol.to_string(),
+        realized_gain: Decimal::ZERO,
+        unrealized_gain: Decimal::ZERO,
+        dividends: Decimal::ZERO,
+        total_gain: Decimal::ZERO,
+        contribution: 0.0,
+    })
+}
+
+impl Broker {
+    /// Values the given cash and holdings at the given date
+    async fn value_positions(&self, cash: Decimal, quantities: &HashMap<String, f64>, date: DateTime<Utc>) -> Result<Decimal, BankError> {
+        let mut value = cash;
+        for (symbol, quantity) in quantities {
+            value += self.get_price(symbol, Some(date.fixed_offset())).await? * money::from_f64(*quantity);
+        }
+        Ok(value)
+    }
+
+    /// Gets the time-weighted return of an investment account between start and end, as a fraction
+    ///
+    /// The window is split at every deposit and withdrawal, each piece's return is taken from the account's
+    /// valuation at either end, and the returns are linked geometrically, so the timing of contributions does not
+    /// distort the result. Pieces where the account held nothing are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or a holding cannot be priced at one of the dates
+    pub async fn time_weighted_return(&self, account_id: u32, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<f64, BankError> {
+        let (flows, positions) = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            // flows at the start are part of the starting value
+            let mut flows: BTreeMap<DateTime<Utc>, Decimal> = BTreeMap::new();
+            for transaction in account.transactions.iter().filter(|transaction| transaction.date > start && transaction.date <= end) {
+                if matches!(transaction.transaction_type, TransactionType::Deposit | TransactionType::Withdraw) {
+                    *flows.entry(transaction.date).or_default() += transaction.cash_effect();
+                }
+            }
+            let mut dates = vec![start];
+            dates.extend(flows.keys().copied());
+            if dates.last() != Some(&end) {
+                dates.push(end);
+            }
+            let positions: Vec<_> = dates
+                .into_iter()
+                .map(|date| (date, account.positions_at(date)))
+                .collect();
+            (flows, positions)
+        };
+
+        let mut values = Vec::with_capacity(positions.len());
+        for (date, (cash, quantities)) in &positions {
+            values.push(self.value_positions(*cash, quantities, *date).await?);
+        }
+
+        let mut growth = 1.0;
+        for (i, (date, _)) in positions.iter().enumerate().skip(1) {
+            let opening = values[i - 1];
+            if opening <= Decimal::ZERO {
+                continue;
+            }
+            // the value just before the flow that ends this piece
+            let closing = values[i] - flows.get(date).copied().unwrap_or(Decimal::ZERO);
+            growth *= money::to_f64(closing / opening);
+        }
+        Ok(growth - 1.0)
+    }
+
+    /// Gets the money-weighted return of an investment account between start and end: the yearly internal rate of
+    /// return of its value at the start, its deposits and withdrawals, and its value at the end
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, a holding cannot be priced, or no rate of return can be found
+    pub async fn money_weighted_return(&self, account_id: u32, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<f64, BankError> {
+        let (flows, opening, closing) = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            let flows: Vec<(DateTime<Utc>, Decimal)> = account.transactions
+                .iter()
+                .filter(|transaction| transaction.date > start && transaction.date <= end)
+                .filter(|transaction| matches!(transaction.transaction_type, TransactionType::Deposit | TransactionType::Withdraw))
+                .map(|transaction| (transaction.date, transaction.cash_effect()))
+                .collect();
+            (flows, account.positions_at(start), account.positions_at(end))
+        };
+        let opening_value = self.value_positions(opening.0, &opening.1, start).await?;
+        let closing_value = self.value_positions(closing.0, &closing.1, end).await?;
+
+        let years = |date: DateTime<Utc>| (date - start).num_seconds() as f64 / (365.0 * 24.0 * 60.0 * 60.0);
+        // from the investor's side: what they put in is negative, and what they could take out is positive
+        let mut cash_flows = vec![(0.0, -money::to_f64(opening_value))];
+        cash_flows.extend(flows.into_iter().map(|(date, amount)| (years(date), -money::to_f64(amount))));
+        cash_flows.push((years(end), money::to_f64(closing_value)));
+        metrics::internal_rate_of_return(&cash_flows)
+            .ok_or_else(|| BankError::Other(String::from("Cannot find a rate of return")))
+    }
+
+    /// Breaks down what made or lost an investment account money between start and end, symbol by symbol.
+    /// Each symbol's gain is its value at the end, less its value at the start, less what was paid for it in the window,
+    /// plus what it sold for and the dividends it paid. Commissions are not charged to symbols
+    ///
+    /// # Returns
+    ///
+    /// Returns every symbol held or traded in the window, largest gain first
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or a holding cannot be priced at the start or the end
+    pub async fn attribution(&self, account_id: u32, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<HoldingAttribution>, BankError> {
+        let mut attributions: BTreeMap<String, HoldingAttribution> = BTreeMap::new();
+        let (opening, closing, net_deposits) = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            let mut net_deposits = Decimal::ZERO;
+            for transaction in account.get_transactions().iter().filter(|transaction| transaction.date > start && transaction.date <= end) {
+                match &transaction.transaction_type {
+                    TransactionType::Deposit | TransactionType::Withdraw => net_deposits += transaction.cash_effect(),
+                    TransactionType::Purchase(asset, _) => attribution_of(&mut attributions, &asset.symbol).total_gain -= transaction.amount,
+                    TransactionType::Sale(asset, _) => attribution_of(&mut attributions, &asset.symbol).total_gain += transaction.amount,
+                    TransactionType::Dividend(asset, _) => attribution_of(&mut attributions, &asset.symbol).dividends += transaction.amount,
+                    _ => {}
+                }
+            }
+            for lot in account.closed_lots.iter().filter(|lot| lot.closed_at > start && lot.closed_at <= end) {
+                attribution_of(&mut attributions, &lot.symbol).realized_gain += lot.realized_gain();
+            }
+            (account.positions_at(start), account.positions_at(end), net_deposits)
+        };
+
+        let symbols: BTreeSet<String> = opening.1.keys().chain(closing.1.keys()).cloned().collect();
+        let mut opening_value = opening.0;
+        for symbol in symbols {
+            let mut change = Decimal::ZERO;
+            if let Some(quantity) = opening.1.get(&symbol) {
+                let value = self.get_price(&symbol, Some(start.fixed_offset())).await? * money::from_f64(*quantity);
+                opening_value += value;
+                change -= value;
+            }
+            if let Some(quantity) = closing.1.get(&symbol) {
+                change += self.get_price(&symbol, Some(end.fixed_offset())).await? * money::from_f64(*quantity);
+            }
+            attribution_of(&mut attributions, &symbol).total_gain += change;
+        }
+
+        let capital = opening_value + net_deposits;
+        let mut attributions: Vec<HoldingAttribution> = attributions.into_values().collect();
+        for attribution in attributions.iter_mut() {
+            attribution.unrealized_gain = attribution.total_gain - attribution.realized_gain;
+            attribution.total_gain += attribution.dividends;
+            if capital > Decimal::ZERO {
+                attribution.contribution = money::to_f64(attribution.total_gain / capital);
+            }
+        }
+        // ties keep the order of the symbols
+        attributions.sort_by_key(|attribution| std::cmp::Reverse(attribution.total_gain));
+        Ok(attributions)
+    }
+
+    /// Gets the time-weighted return of an investment account between start and end as a yearly rate,
+    /// compounding the total return as `(1 + r)^(365 / days) - 1`, so windows shorter and longer than a year compare
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the window is empty, the account does not exist, or a holding cannot be priced
+    pub async fn annualized_return(&self, account_id: u32, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<f64, BankError> {
+        let days = (end - start).num_seconds() as f64 / (24.0 * 60.0 * 60.0);
+        if days <= 0.0 {
+            return Err(BankError::Other(String::from("Cannot annualize a return over an empty window")));
+        }
+        let total_return = self.time_weighted_return(account_id, start, end).await?;
+        Ok((1.0 + total_return).powf(365.0 / days) - 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::AccountType, stock::Asset, transactions::Transaction, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_time_weighted_return_ignores_deposits() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 10.0).unwrap();
+        account.deposit(dec!(1100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(110.0), 10.0).unwrap();
+        let dates = [day(2), day(2), day(3), day(3)];
+        for (transaction, date) in account.transactions.iter_mut().zip(dates) {
+            transaction.date = date;
+        }
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_series("AAPL", vec![(day(2), dec!(100.0)), (day(3), dec!(110.0)), (day(4), dec!(121.0))]));
+
+        // 10% before the second deposit, then 10% after it
+        let twr = broker.time_weighted_return(id, day(2), day(4)).await.unwrap();
+        assert!((twr - 0.21).abs() < 1e-9);
+        // the account was empty before the first deposit
+        let twr = broker.time_weighted_return(id, day(1), day(3)).await.unwrap();
+        assert!((twr - 0.1).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_money_weighted_return() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 10.0).unwrap();
+        let start = Utc.with_ymd_and_hms(2023, 1, 2, 15, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        for transaction in account.transactions.iter_mut() {
+            transaction.date = start;
+        }
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_series("AAPL", vec![(start, dec!(100.0)), (end, dec!(110.0))]));
+
+        // with no flows inside the window, the return is the plain growth over the year
+        let mwr = broker.money_weighted_return(id, start, end).await.unwrap();
+        assert!((mwr - 0.1).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_attribution() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit_at(dec!(1000.0), day(1)).unwrap();
+        account.purchase_investment_at("AAPL".to_string(), dec!(100.0), 5.0, day(1)).unwrap();
+        account.purchase_investment_at("MSFT".to_string(), dec!(10.0), 10.0, day(1)).unwrap();
+        account.sell_investment_at("AAPL".to_string(), dec!(110.0), 2.0, day(2)).unwrap();
+        account.pay_dividend(Transaction::new(TransactionType::Dividend(Asset::new("MSFT".to_string()), 10.0), dec!(5.0), day(3), None));
+        account.purchase_investment_at("GOOG".to_string(), dec!(20.0), 1.0, day(3)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_series("AAPL", vec![(day(1), dec!(100.0)), (day(4), dec!(120.0))])
+            .with_series("MSFT", vec![(day(1), dec!(10.0)), (day(4), dec!(9.0))])
+            .with_series("GOOG", vec![(day(3), dec!(20.0)), (day(4), dec!(21.0))]));
+
+        let attribution = broker.attribution(id, day(1), day(4)).await.unwrap();
+        let symbols: Vec<&str> = attribution.iter().map(|attribution| attribution.symbol.as_str()).collect();
+        assert_eq!(symbols, vec!["AAPL", "GOOG", "MSFT"]);
+        // 2 sold for a gain of 20, and 3 held that gained 60
+        assert_eq!(attribution[0].realized_gain, dec!(20.0));
+        assert_eq!(attribution[0].unrealized_gain, dec!(60.0));
+        assert_eq!(attribution[0].total_gain, dec!(80.0));
+        assert!((attribution[0].contribution - 0.08).abs() < 1e-9);
+        // bought in the window, so only its gain since is attributed
+        assert_eq!(attribution[1].total_gain, dec!(1.0));
+        // the dividend makes up for half of the fall
+        assert_eq!(attribution[2].unrealized_gain, dec!(-10.0));
+        assert_eq!(attribution[2].dividends, dec!(5.0));
+        assert!((attribution[2].contribution + 0.005).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_annualized_return() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 10.0).unwrap();
+        let start = Utc.with_ymd_and_hms(2022, 1, 3, 15, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 3, 15, 0, 0).unwrap();
+        for transaction in account.transactions.iter_mut() {
+            transaction.date = start;
+        }
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_series("AAPL", vec![(start, dec!(100.0)), (end, dec!(121.0))]));
+
+        // 21% over the 730 days is 10% a year
+        let annualized = broker.annualized_return(id, start, end).await.unwrap();
+        assert!((annualized - 0.1).abs() < 1e-9);
+        assert!(broker.annualized_return(id, end, start).await.is_err());
+    }
+}
@@ -0,0 +1,528 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
+
+use crate::bank::{accounts::Account, error::BankError, lots::ClosedLot, money, stock::Holding, transactions::{Transaction, TransactionType}};
+
+use super::Broker;
+
+/// A single row of a positions table, comparing a holding's cost basis against the market
+#[derive(Debug, Clone)]
+pub struct PositionDetail{
+    pub symbol: String,
+    pub quantity: f64,
+    pub average_cost_per_unit: Decimal,
+    /// The price used to value the holding
+    pub price: Decimal,
+    pub market_value: Decimal,
+    pub cost_basis: Decimal,
+    pub unrealized_gain: Decimal,
+    /// The unrealized gain as a percent of the cost basis
+    pub unrealized_gain_percent: Decimal,
+    /// The market value as a fraction of the account's total value, cash included
+    pub weight: Decimal,
+}
+
+/// Everything an account has done with one symbol
+#[derive(Debug, Clone)]
+pub struct SymbolActivity{
+    pub symbol: String,
+    /// Purchases, oldest first
+    pub purchases: Vec<Transaction>,
+    /// Sales, oldest first
+    pub sales: Vec<Transaction>,
+    /// Dividend payments, oldest first
+    pub dividends: Vec<Transaction>,
+    /// Commissions charged on trades of the symbol, oldest first
+    pub commissions: Vec<Transaction>,
+    pub closed_lots: Vec<ClosedLot>,
+    /// The current holding, if any shares are still held
+    pub holding: Option<Holding>,
+    /// The gain of every sale, against the average cost at the time
+    pub realized_gain: Decimal,
+    /// The gain of the shares still held, against the market at the date limit
+    pub unrealized_gain: Decimal,
+    pub total_dividends: Decimal,
+    pub total_commissions: Decimal,
+}
+
+/// One symbol held across every investment account
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidatedHolding{
+    pub symbol: String,
+    /// The quantity held in every account together
+    pub quantity: f64,
+    /// The average cost of every account's holding, weighted by quantity
+    pub average_cost_per_unit: Decimal,
+    pub price: Decimal,
+    pub market_value: Decimal,
+    /// The ids of the accounts that hold the symbol, in order
+    pub account_ids: Vec<u32>,
+}
+
+/// The cash and holdings an investment account had at a point in time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountSnapshot{
+    pub cash: Decimal,
+    /// The quantity held of each symbol
+    pub holdings: HashMap<String, f64>,
+}
+
+impl Broker{
+    /// Copies the cash balance and holdings of an investment account, so that the bank is not
+    /// locked while the holdings are priced
+    pub(crate) async fn snapshot_holdings(&self, account_id: u32) -> Result<(Decimal, Vec<Holding>), BankError>{
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        Ok((account.get_balance(), account.get_investments().values().cloned().collect()))
+    }
+
+    /// Gets every symbol held in any investment account
+    pub async fn held_symbols(&self) -> HashSet<String>{
+        self.held_quantities().await.into_keys().collect()
+    }
+
+    /// Gets the quantity of each symbol held across every investment account
+    pub async fn held_quantities(&self) -> HashMap<String, f64>{
+        let bank = self.bank.lock().await;
+        let mut quantities = HashMap::new();
+        for account in bank.get_investment_accounts().values(){
+            for (symbol, holding) in account.get_investments(){
+                *quantities.entry(symbol.clone()).or_default() += holding.quantity;
+            }
+        }
+        quantities
+    }
+
+    /// Gets the holdings of every investment account merged by symbol, with each symbol priced once
+    /// 
+    /// # Arguments
+    /// 
+    /// * `date_limit` - The date limit to get prices at
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if a holding cannot be priced
+    pub async fn consolidated_holdings(&self, date_limit: Option<DateTime<FixedOffset>>) -> Result<HashMap<String, ConsolidatedHolding>, BankError>{
+        // the quantity and total cost of each symbol, and where it is held
+        let mut merged: HashMap<String, (f64, Decimal, Vec<u32>)> = HashMap::new();
+        {
+            let bank = self.bank.lock().await;
+            for (id, account) in bank.get_investment_accounts(){
+                for (symbol, holding) in account.get_investments(){
+                    let (quantity, cost, account_ids) = merged.entry(symbol.clone()).or_default();
+                    *quantity += holding.quantity;
+                    *cost += holding.average_cost_per_unit * money::from_f64(holding.quantity);
+                    account_ids.push(*id);
+                }
+            }
+        }
+
+        let mut consolidated = HashMap::new();
+        for (symbol, (quantity, cost, mut account_ids)) in merged{
+            let price = self.get_price(&symbol, date_limit).await?;
+            let average_cost_per_unit = if quantity == 0.0{
+                Decimal::ZERO
+            }else{
+                cost / money::from_f64(quantity)
+            };
+            account_ids.sort();
+            consolidated.insert(symbol.clone(), ConsolidatedHolding{
+                symbol,
+                quantity,
+                average_cost_per_unit,
+                price,
+                market_value: price * money::from_f64(quantity),
+                account_ids,
+            });
+        }
+        Ok(consolidated)
+    }
+
+    /// Prices each of the given holdings once
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the price of each symbol
+    pub(crate) async fn price_holdings(&self, holdings: &[Holding], date_limit: Option<DateTime<FixedOffset>>) -> Result<HashMap<String, Decimal>, BankError>{
+        let mut prices = HashMap::new();
+        for holding in holdings{
+            let price = self.get_price(&holding.asset.symbol, date_limit).await?;
+            prices.insert(holding.asset.symbol.clone(), price);
+        }
+        Ok(prices)
+    }
+
+    /// Gets the cash of an investment account, and the market value of its holdings.
+    /// A holding that cannot be priced is valued at its last known price, if there is a last price store
+    pub(crate) async fn cash_and_equity(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<(Decimal, Decimal), BankError>{
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        let mut equity = Decimal::ZERO;
+        for holding in holdings{
+            let quote = self.get_price_or_last_known(&holding.asset.symbol, date_limit).await?;
+            equity += quote.price * money::from_f64(holding.quantity);
+        }
+        Ok((cash, equity))
+    }
+
+    /// Gets the total value of an investment account: its cash plus the market value of every holding.
+    /// A holding that cannot be priced is valued at its last known price, if there is a last price store
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, or a holding cannot be priced and has no last known price,
+    /// or, if the broker records history, the snapshot cannot be written
+    pub async fn get_account_value(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError>{
+        let (cash, equity) = self.cash_and_equity(account_id, date_limit).await?;
+        if self.get_record_history(){
+            self.append_portfolio_snapshot(account_id, cash, equity, date_limit).await?;
+        }
+        Ok(cash + equity)
+    }
+
+    /// Gets the cost basis and market valuation of one holding, side by side
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `symbol` - The symbol of the holding
+    /// * `date_limit` - The date limit to get prices at
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account or holding does not exist, or a holding cannot be priced
+    pub async fn position_detail(&self, account_id: u32, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<PositionDetail, BankError>{
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        if !holdings.iter().any(|holding| holding.asset.symbol == symbol){
+            return Err(BankError::HoldingNotFound);
+        }
+        // every holding is priced, as the weight is relative to the whole account
+        let details = self.detail_positions(cash, &holdings, date_limit).await?;
+        Ok(details
+            .into_iter()
+            .find(|detail| detail.symbol == symbol)
+            .expect("the holding was found in the snapshot"))
+    }
+
+    /// Gets the cost basis and market valuation of each of the given holdings, weighted against the cash and every holding together
+    pub(crate) async fn detail_positions(&self, cash: Decimal, holdings: &[Holding], date_limit: Option<DateTime<FixedOffset>>) -> Result<Vec<PositionDetail>, BankError>{
+        let prices = self.price_holdings(holdings, date_limit).await?;
+        let total_value: Decimal = cash + holdings
+            .iter()
+            .map(|holding| prices[&holding.asset.symbol] * money::from_f64(holding.quantity))
+            .sum::<Decimal>();
+
+        Ok(holdings.iter().map(|holding| {
+            let price = prices[&holding.asset.symbol];
+            let market_value = price * money::from_f64(holding.quantity);
+            let cost_basis = holding.average_cost_per_unit * money::from_f64(holding.quantity);
+            let unrealized_gain = market_value - cost_basis;
+            let unrealized_gain_percent = if cost_basis.is_zero(){
+                Decimal::ZERO
+            }else{
+                unrealized_gain / cost_basis * Decimal::ONE_HUNDRED
+            };
+            let weight = if total_value.is_zero(){
+                Decimal::ZERO
+            }else{
+                market_value / total_value
+            };
+
+            PositionDetail{
+                symbol: holding.asset.symbol.clone(),
+                quantity: holding.quantity,
+                average_cost_per_unit: holding.average_cost_per_unit,
+                price,
+                market_value,
+                cost_basis,
+                unrealized_gain,
+                unrealized_gain_percent,
+                weight,
+            }
+        }).collect())
+    }
+
+    /// Gathers every purchase, sale, dividend and commission of one symbol in an investment account,
+    /// along with the current holding and its realized and unrealized gains
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `symbol` - The symbol to gather
+    /// * `date_limit` - The date limit to price the current holding at
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, or the symbol is still held and cannot be priced
+    pub async fn symbol_activity(&self, account_id: u32, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<SymbolActivity, BankError>{
+        let mut activity = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            let mut activity = SymbolActivity{
+                symbol: symbol.to_string(),
+                purchases: Vec::new(),
+                sales: Vec::new(),
+                dividends: Vec::new(),
+                commissions: Vec::new(),
+                closed_lots: account.closed_lots.iter().filter(|lot| lot.symbol == symbol).cloned().collect(),
+                holding: account.get_investments().get(symbol).cloned(),
+                realized_gain: Decimal::ZERO,
+                unrealized_gain: Decimal::ZERO,
+                total_dividends: Decimal::ZERO,
+                total_commissions: Decimal::ZERO,
+            };
+            for transaction in account.transactions.iter(){
+                match &transaction.transaction_type{
+                    TransactionType::Purchase(asset, _) if asset.symbol == symbol => activity.purchases.push(transaction.clone()),
+                    TransactionType::Sale(asset, _) if asset.symbol == symbol => activity.sales.push(transaction.clone()),
+                    TransactionType::Dividend(asset, _) if asset.symbol == symbol => activity.dividends.push(transaction.clone()),
+                    TransactionType::Commission(asset) if asset.symbol == symbol => activity.commissions.push(transaction.clone()),
+                    _ => {}
+                }
+            }
+            activity
+        };
+        activity.realized_gain = activity.closed_lots.iter().map(|lot| lot.realized_gain()).sum();
+        activity.total_dividends = activity.dividends.iter().map(|transaction| transaction.amount).sum();
+        activity.total_commissions = activity.commissions.iter().map(|transaction| transaction.amount).sum();
+        if let Some(holding) = &activity.holding{
+            let quantity = money::from_f64(holding.quantity);
+            let price = self.get_price(symbol, date_limit).await?;
+            activity.unrealized_gain = (price - holding.average_cost_per_unit) * quantity;
+        }
+        Ok(activity)
+    }
+
+    /// Gets the market value of each holding in an investment account, largest first
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date limit to get prices at
+    /// * `top` - Only return this many of the largest holdings, if given
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, or a holding cannot be priced
+    pub async fn holdings_ranked(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, top: Option<usize>) -> Result<Vec<(String, Decimal)>, BankError>{
+        let (_, holdings) = self.snapshot_holdings(account_id).await?;
+        let prices = self.price_holdings(&holdings, date_limit).await?;
+        let mut ranked: Vec<(String, Decimal)> = holdings
+            .iter()
+            .map(|holding| (holding.asset.symbol.clone(), prices[&holding.asset.symbol] * money::from_f64(holding.quantity)))
+            .collect();
+        // ties are broken by symbol, so the order does not depend on the map the holdings came from
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        if let Some(top) = top{
+            ranked.truncate(top);
+        }
+        Ok(ranked)
+    }
+
+    /// Gets the transactions of every account, checking and investment, oldest first,
+    /// each with the id of the account it belongs to
+    /// 
+    /// # Arguments
+    /// 
+    /// * `since` - Only return transactions on or after this date, if given
+    pub async fn trade_log(&self, since: Option<DateTime<Utc>>) -> Vec<(u32, Transaction)>{
+        let bank = self.bank.lock().await;
+        let checking = bank.get_checking_accounts().iter().map(|(id, account)| (*id, account.get_transactions()));
+        let investment = bank.get_investment_accounts().iter().map(|(id, account)| (*id, &account.transactions));
+        let mut log: Vec<(u32, Transaction)> = checking
+            .chain(investment)
+            .flat_map(|(id, transactions)| transactions.iter().map(move |transaction| (id, transaction)))
+            .filter(|(_, transaction)| since.is_none_or(|since| transaction.date >= since))
+            .map(|(id, transaction)| (id, transaction.clone()))
+            .collect();
+        // ties are broken by account, so the order does not depend on the maps the accounts came from
+        log.sort_by(|a, b| a.1.date.cmp(&b.1.date).then_with(|| a.0.cmp(&b.0)));
+        log
+    }
+
+    /// Reconstructs the cash and holdings an investment account had at a past date from its transaction log.
+    /// Needs no prices, so it works offline
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `date` - The date to reconstruct the account at. Transactions at exactly this date are included
+    /// 
+    /// # Returns
+    /// 
+    /// Returns an empty snapshot if the account had no transactions by the date
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist
+    pub async fn state_as_of(&self, account_id: u32, date: DateTime<Utc>) -> Result<AccountSnapshot, BankError>{
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        if !account.transactions.iter().any(|transaction| transaction.date <= date){
+            return Ok(AccountSnapshot::default());
+        }
+        let (cash, holdings) = account.positions_at(date);
+        Ok(AccountSnapshot{ cash, holdings })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::AccountType, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_held_quantities(){
+        let mut bank = Bank::empty();
+        for (symbol, quantity) in [("AAPL", 2.0), ("MSFT", 1.0)]{
+            let id = bank.open_account(None, AccountType::Investment).unwrap();
+            let account = bank.get_investment_account_mut(id).unwrap();
+            account.deposit(dec!(100.0)).unwrap();
+            account.purchase_investment("AAPL".to_string(), dec!(10.0), 3.0).unwrap();
+            account.purchase_investment(symbol.to_string(), dec!(10.0), quantity).unwrap();
+        }
+        bank.open_account(None, AccountType::Checking).unwrap();
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+
+        assert_eq!(broker.held_quantities().await, HashMap::from([("AAPL".to_string(), 8.0), ("MSFT".to_string(), 1.0)]));
+        assert_eq!(broker.held_symbols().await, HashSet::from(["AAPL".to_string(), "MSFT".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn test_consolidated_holdings(){
+        let mut bank = Bank::empty();
+        let mut ids = Vec::new();
+        for (price, quantity) in [(dec!(10.0), 2.0), (dec!(16.0), 1.0)]{
+            let id = bank.open_account(None, AccountType::Investment).unwrap();
+            ids.push(id);
+            let account = bank.get_investment_account_mut(id).unwrap();
+            account.deposit(dec!(100.0)).unwrap();
+            account.purchase_investment("AAPL".to_string(), price, quantity).unwrap();
+        }
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(5.0), 4.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(20.0))
+            .with_price("MSFT", date, dec!(6.0)));
+
+        let consolidated = broker.consolidated_holdings(None).await.unwrap();
+        assert_eq!(consolidated.len(), 2);
+        let aapl = &consolidated["AAPL"];
+        assert_eq!(aapl.quantity, 3.0);
+        assert_eq!(aapl.average_cost_per_unit, dec!(12.0));
+        assert_eq!(aapl.market_value, dec!(60.0));
+        ids.sort();
+        assert_eq!(aapl.account_ids, ids);
+        assert_eq!(consolidated["MSFT"].market_value, dec!(24.0));
+    }
+
+    #[tokio::test]
+    async fn test_holdings_ranked(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 5.0).unwrap();
+        account.purchase_investment("GOOG".to_string(), dec!(10.0), 1.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(50.0))
+            .with_price("MSFT", date, dec!(10.0))
+            .with_price("GOOG", date, dec!(30.0)));
+
+        let ranked = broker.holdings_ranked(id, None, None).await.unwrap();
+        assert_eq!(ranked, vec![
+            ("AAPL".to_string(), dec!(100.0)),
+            ("MSFT".to_string(), dec!(50.0)),
+            ("GOOG".to_string(), dec!(30.0)),
+        ]);
+        let top = broker.holdings_ranked(id, None, Some(1)).await.unwrap();
+        assert_eq!(top, vec![("AAPL".to_string(), dec!(100.0))]);
+    }
+
+    #[tokio::test]
+    async fn test_symbol_activity(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 4.0).unwrap();
+        account.charge_commission("AAPL".to_string(), dec!(1.0)).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 1.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(15.0), 1.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(20.0)));
+
+        let activity = broker.symbol_activity(id, "AAPL", None).await.unwrap();
+        assert_eq!(activity.purchases.len(), 1);
+        assert_eq!(activity.sales.len(), 1);
+        assert_eq!(activity.commissions.len(), 1);
+        assert_eq!(activity.realized_gain, dec!(5.0));
+        assert_eq!(activity.unrealized_gain, dec!(30.0));
+        assert_eq!(activity.total_commissions, dec!(1.0));
+        assert_eq!(activity.holding.unwrap().quantity, 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_trade_log(){
+        let mut bank = Bank::empty();
+        let checking = bank.open_account(None, AccountType::Checking).unwrap();
+        let investment = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_checking_account_mut(checking).unwrap().deposit(dec!(10.0)).unwrap();
+        bank.get_investment_account_mut(investment).unwrap().deposit(dec!(20.0)).unwrap();
+        bank.get_checking_account_mut(checking).unwrap().withdraw(dec!(5.0)).unwrap();
+        let dates = [
+            Utc.with_ymd_and_hms(2024, 1, 3, 0, 0, 0).unwrap(),
+            Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        ];
+        for (transaction, date) in bank.get_checking_account_mut(checking).unwrap().get_transactions_mut().iter_mut().zip(dates){
+            transaction.date = date;
+        }
+        bank.get_investment_account_mut(investment).unwrap().transactions[0].date = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+
+        let log = broker.trade_log(None).await;
+        let amounts: Vec<Decimal> = log.iter().map(|(_, transaction)| transaction.amount).collect();
+        assert_eq!(amounts, vec![dec!(5.0), dec!(20.0), dec!(10.0)]);
+        let log = broker.trade_log(Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap())).await;
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].0, investment);
+    }
+
+    #[tokio::test]
+    async fn test_state_as_of(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 3.0).unwrap();
+        for (day, transaction) in account.transactions.iter_mut().enumerate(){
+            transaction.date = Utc.with_ymd_and_hms(2024, 1, 2 + day as u32, 0, 0, 0).unwrap();
+        }
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+
+        let before = broker.state_as_of(id, Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()).await.unwrap();
+        assert_eq!(before, AccountSnapshot::default());
+        let snapshot = broker.state_as_of(id, Utc.with_ymd_and_hms(2024, 1, 3, 12, 0, 0).unwrap()).await.unwrap();
+        assert_eq!(snapshot.cash, dec!(80.0));
+        assert_eq!(snapshot.holdings, HashMap::from([("AAPL".to_string(), 2.0)]));
+        assert!(matches!(broker.state_as_of(id + 1, Utc::now()).await, Err(BankError::AccountNotFound)));
+    }
+}
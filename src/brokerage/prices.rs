@@ -0,0 +1,396 @@
+use std::collections::{BTreeMap, HashMap};
+
+use alphavantage::cache_enabled::time_series;
+use async_trait::async_trait;
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::bank::{error::BankError, money};
+
+use super::Broker;
+
+/// Which price of an AlphaVantage bar the broker values and trades at.
+/// The broker reads daily series with one field and intraday bars with another. A bar without the field
+/// is an error rather than a fallback, so adjusted and raw prices are never mixed in one valuation or return series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PriceField {
+    /// The close adjusted for splits and dividends. Only daily series carry it, so `get_price` fails with it as the intraday field
+    #[default]
+    AdjustedClose,
+    /// The raw close, which every series has
+    Close,
+    Open,
+    High,
+    Low,
+}
+
+impl PriceField {
+    /// Gets this field of a bar, if the bar has it
+    pub fn of(&self, entry: &time_series::Entry) -> Option<f64> {
+        match self {
+            PriceField::AdjustedClose => entry.adjusted_close,
+            PriceField::Close => Some(entry.close),
+            PriceField::Open => Some(entry.open),
+            PriceField::High => Some(entry.high),
+            PriceField::Low => Some(entry.low),
+        }
+    }
+
+    /// Gets this field of a bar of the symbol
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::PriceUnavailable` if the bar does not have the field
+    pub(crate) fn price(&self, symbol: &str, entry: &time_series::Entry) -> Result<f64, BankError> {
+        self.of(entry)
+            .ok_or_else(|| BankError::PriceUnavailable(format!("{} has no {:?} price on {}", symbol, self, entry.date)))
+    }
+}
+
+/// Somewhere the broker can get prices and market hours from, in place of AlphaVantage
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    /// Gets the most recent price of the symbol, at or before the date limit
+    async fn price(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError>;
+
+    /// Whether the symbol can be traded at the given date.
+    /// Markets are always open unless a source says otherwise
+    async fn is_market_open(&self, _symbol: &str, _date: DateTime<FixedOffset>) -> Result<bool, BankError> {
+        Ok(true)
+    }
+}
+
+/// An in memory price source, for tests and dry runs that should not touch the network
+#[derive(Debug, Clone)]
+pub struct MockPriceSource {
+    prices: HashMap<String, BTreeMap<DateTime<Utc>, Decimal>>,
+    market_open: bool,
+}
+
+impl Default for MockPriceSource {
+    fn default() -> Self {
+        MockPriceSource {
+            prices: HashMap::new(),
+            market_open: true,
+        }
+    }
+}
+
+impl MockPriceSource {
+    pub fn new() -> Self {
+        MockPriceSource::default()
+    }
+
+    /// Adds a price for the symbol at the given date
+    pub fn with_price(mut self, symbol: &str, date: DateTime<Utc>, price: Decimal) -> Self {
+        self.prices.entry(symbol.to_string()).or_default().insert(date, price);
+        self
+    }
+
+    /// Adds a series of prices for the symbol
+    pub fn with_series<I>(mut self, symbol: &str, series: I) -> Self
+    where
+        I: IntoIterator<Item = (DateTime<Utc>, Decimal)>,
+    {
+        self.prices.entry(symbol.to_string()).or_default().extend(series);
+        self
+    }
+
+    /// Sets whether every market is open
+    pub fn with_market_open(mut self, market_open: bool) -> Self {
+        self.market_open = market_open;
+        self
+    }
+}
+
+#[async_trait]
+impl PriceSource for MockPriceSource {
+    async fn price(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError> {
+        let series = self.prices.get(symbol)
+            .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))?;
+        let latest = match date_limit {
+            Some(date_limit) => series.range(..=date_limit.to_utc()).next_back(),
+            None => series.iter().next_back(),
+        };
+        latest
+            .map(|(_, price)| *price)
+            .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))
+    }
+
+    async fn is_market_open(&self, _symbol: &str, _date: DateTime<FixedOffset>) -> Result<bool, BankError> {
+        Ok(self.market_open)
+    }
+}
+
+impl Broker {
+    /// Makes the broker get its prices and market hours from the given source instead of AlphaVantage
+    pub fn set_price_source<S>(&mut self, price_source: S)
+    where
+        S: PriceSource + 'static,
+    {
+        self.price_source = Some(Box::new(price_source));
+    }
+
+    /// Makes `get_price`, and so every valuation and trade, use the given price for a symbol, such as to ask
+    /// "what if this stock were at $500". The override is kept apart from loaded prices, and replaces nothing in the cache
+    pub fn set_price_override(&mut self, symbol: &str, price: Decimal) {
+        self.price_overrides.insert(symbol.to_string(), price);
+    }
+
+    /// Goes back to the real price of a symbol
+    pub fn clear_price_override(&mut self, symbol: &str) {
+        self.price_overrides.remove(symbol);
+    }
+
+    pub fn get_price_overrides(&self) -> &HashMap<String, Decimal> {
+        &self.price_overrides
+    }
+
+    /// Records the net asset value of a symbol the data provider does not cover, such as a private fund, as of a date.
+    /// Once a symbol has a manual price it is never fetched: `get_price` uses the latest value at or before the date limit,
+    /// and the symbol trades on every day of the broker's calendar
+    pub fn set_manual_price(&mut self, symbol: &str, as_of: DateTime<Utc>, price: Decimal) {
+        self.manual_prices.entry(symbol.to_string()).or_default().insert(as_of, price);
+    }
+
+    /// Records a series of manual prices for a symbol, as `set_manual_price` does for one
+    pub fn set_manual_prices<I>(&mut self, symbol: &str, prices: I)
+    where
+        I: IntoIterator<Item = (DateTime<Utc>, Decimal)>,
+    {
+        self.manual_prices.entry(symbol.to_string()).or_default().extend(prices);
+    }
+
+    /// Forgets every manual price of a symbol, so it is fetched again
+    pub fn clear_manual_prices(&mut self, symbol: &str) {
+        self.manual_prices.remove(symbol);
+    }
+
+    pub fn get_manual_prices(&self, symbol: &str) -> Option<&BTreeMap<DateTime<Utc>, Decimal>> {
+        self.manual_prices.get(symbol)
+    }
+
+    /// Gets the latest manual price of a symbol at or before the date limit
+    pub(crate) fn manual_price(&self, symbol: &str, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError> {
+        let series = self.manual_prices.get(symbol)
+            .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))?;
+        let latest = match date_limit {
+            Some(date_limit) => series.range(..=date_limit.to_utc()).next_back(),
+            None => series.iter().next_back(),
+        };
+        latest
+            .map(|(_, price)| *price)
+            .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))
+    }
+
+    /// Gets the field of daily bars the broker prices with
+    pub fn get_price_field(&self) -> PriceField {
+        self.price_field
+    }
+
+    /// Sets the field of daily bars the broker prices with, for backtests, risk measures and `close_price_on`
+    pub fn set_price_field(&mut self, price_field: PriceField) {
+        self.price_field = price_field;
+    }
+
+    /// Gets the field of intraday bars `get_price` prices with
+    pub fn get_intraday_price_field(&self) -> PriceField {
+        self.intraday_price_field
+    }
+
+    /// Sets the field of intraday bars `get_price` prices with. Intraday bars carry no adjusted close
+    pub fn set_intraday_price_field(&mut self, intraday_price_field: PriceField) {
+        self.intraday_price_field = intraday_price_field;
+    }
+
+    /// Gets the broker's price field of a symbol on a date from the daily series, for strategies that trade once a day.
+    /// Only the daily series is loaded, which is cached and much smaller than the intraday series `get_price` uses
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::MarketClosed` if the date is not a trading day in the broker's calendar,
+    /// or `BankError::PriceUnavailable` if the series has no entry for it, or the entry lacks the price field
+    pub async fn close_price_on(&self, symbol: &str, date: NaiveDate) -> Result<Decimal, BankError> {
+        if !self.calendar.is_trading_day(date) {
+            return Err(BankError::MarketClosed);
+        }
+        let series = self.get_time_series_daily_full(symbol).await?;
+        let entry = series.entries
+            .iter()
+            .find(|entry| entry.date.date_naive() == date)
+            .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))?;
+        self.price_field.price(symbol, entry).map(money::from_f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::commission::CommissionSchedule};
+
+    use super::*;
+
+    fn day(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, 15, 0, 0).unwrap()
+    }
+
+    fn mock() -> MockPriceSource {
+        MockPriceSource::new()
+            .with_series("AAPL", vec![(day(2), dec!(100.0)), (day(3), dec!(110.0)), (day(4), dec!(105.0))])
+    }
+
+    #[tokio::test]
+    async fn test_mock_price_uses_latest_before_limit() {
+        let source = mock();
+        assert_eq!(source.price("AAPL", None).await.unwrap(), dec!(105.0));
+        assert_eq!(source.price("AAPL", Some(day(3).fixed_offset())).await.unwrap(), dec!(110.0));
+        assert!(matches!(source.price("AAPL", Some(day(1).fixed_offset())).await, Err(BankError::PriceUnavailable(_))));
+        assert!(matches!(source.price("MSFT", None).await, Err(BankError::PriceUnavailable(_))));
+    }
+
+    #[tokio::test]
+    async fn test_broker_trades_against_mock() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let bank = Arc::new(Mutex::new(bank));
+        let mut broker = Broker::new(Client::new("unused"), bank.clone());
+        broker.set_price_source(mock());
+
+        let balance = broker.buy("AAPL", 5.0, id, Some(day(2).fixed_offset())).await.unwrap();
+        assert_eq!(balance, dec!(500.0));
+        let balance = broker.sell("AAPL", 5.0, id, Some(day(3).fixed_offset())).await.unwrap();
+        assert_eq!(balance, dec!(1050.0));
+    }
+
+    #[tokio::test]
+    async fn test_broker_trades_are_dated_at_date_limit() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let bank = Arc::new(Mutex::new(bank));
+        let mut broker = Broker::new(Client::new("unused"), bank.clone());
+        broker.set_price_source(mock());
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+
+        broker.buy("AAPL", 5.0, id, Some(day(2).fixed_offset())).await.unwrap();
+        broker.sell("AAPL", 2.0, id, Some(day(3).fixed_offset())).await.unwrap();
+        let bank = bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        let dates: Vec<DateTime<Utc>> = account.transactions[1..].iter().map(|transaction| transaction.date).collect();
+        // the purchase and its commission, then the sale and its commission
+        assert_eq!(dates, vec![day(2), day(2), day(3), day(3)]);
+        assert_eq!(account.get_investments()["AAPL"].get_first_acquired(), day(2));
+        assert_eq!(account.closed_lots[0].closed_at, day(3));
+        assert_eq!(account.closed_lots[0].opened_at, Some(day(2)));
+    }
+
+    #[tokio::test]
+    async fn test_closed_mock_market_rejects_buys() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(mock().with_market_open(false));
+
+        assert!(matches!(broker.buy("AAPL", 1.0, id, Some(day(2).fixed_offset())).await, Err(BankError::MarketClosed)));
+    }
+
+    #[tokio::test]
+    async fn test_price_override() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 2.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(mock());
+
+        broker.set_price_override("AAPL", dec!(500.0));
+        assert_eq!(broker.get_price("AAPL", Some(day(2).fixed_offset())).await.unwrap(), dec!(500.0));
+        assert_eq!(broker.get_account_value(id, None).await.unwrap(), dec!(1800.0));
+
+        broker.clear_price_override("AAPL");
+        assert!(broker.get_price_overrides().is_empty());
+        assert_eq!(broker.get_account_value(id, None).await.unwrap(), dec!(1010.0));
+    }
+
+    #[test]
+    fn test_price_field_config() {
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        assert_eq!(broker.get_price_field(), PriceField::AdjustedClose);
+        assert_eq!(broker.get_intraday_price_field(), PriceField::Close);
+        let config: crate::config::EngineConfig = serde_json::from_str(r#"{"price_field": "Close", "intraday_price_field": "Open"}"#).unwrap();
+        broker.apply_config(&config);
+        assert_eq!(broker.get_price_field(), PriceField::Close);
+        assert_eq!(broker.get_intraday_price_field(), PriceField::Open);
+        // leaving the fields out keeps the broker's choice
+        broker.apply_config(&crate::config::EngineConfig::default());
+        assert_eq!(broker.get_price_field(), PriceField::Close);
+        assert_eq!(broker.get_intraday_price_field(), PriceField::Open);
+    }
+
+    #[tokio::test]
+    async fn test_close_price_on_non_trading_day() {
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        // a Saturday and New Year's Day are refused without loading the series
+        for date in [NaiveDate::from_ymd_opt(2024, 1, 6).unwrap(), NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()] {
+            assert!(matches!(broker.close_price_on("AAPL", date).await, Err(BankError::MarketClosed)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_close_price_on() {
+        use crate::brokerage::provider::mock::{self, MockProvider};
+
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        broker.set_market_data_provider(MockProvider::new().with_daily("AAPL", vec![mock::bar(day(2), 101.25)]));
+        assert_eq!(broker.close_price_on("AAPL", day(2).date_naive()).await.unwrap(), dec!(101.25));
+        assert!(matches!(broker.close_price_on("AAPL", day(3).date_naive()).await, Err(BankError::PriceUnavailable(_))));
+    }
+
+    #[tokio::test]
+    async fn test_bar_without_the_price_field() {
+        use crate::brokerage::provider::mock::{self, MockProvider};
+
+        let mut raw = mock::bar(day(2), 101.25);
+        raw.adjusted_close = None;
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        broker.set_market_data_provider(MockProvider::new().with_daily("AAPL", vec![raw]));
+        // the raw close is not taken in place of the missing adjusted close
+        assert!(matches!(broker.close_price_on("AAPL", day(2).date_naive()).await, Err(BankError::PriceUnavailable(_))));
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        assert!(matches!(broker.dca_backtest("AAPL", dec!(100.0), crate::brokerage::backtest::DcaInterval::Monthly, start, day(2).date_naive()).await, Err(BankError::PriceUnavailable(_))));
+        broker.set_price_field(PriceField::Close);
+        assert_eq!(broker.close_price_on("AAPL", day(2).date_naive()).await.unwrap(), dec!(101.25));
+    }
+
+    #[tokio::test]
+    async fn test_manual_prices() {
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        // no price source is set, so anything not priced manually would go to the network
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_manual_prices("FUND", vec![(day(2), dec!(10.0)), (day(4), dec!(12.5))]);
+
+        assert_eq!(broker.get_price("FUND", Some(day(3).fixed_offset())).await.unwrap(), dec!(10.0));
+        assert_eq!(broker.get_price("FUND", None).await.unwrap(), dec!(12.5));
+        assert!(matches!(broker.get_price("FUND", Some(day(1).fixed_offset())).await, Err(BankError::PriceUnavailable(_))));
+
+        broker.buy("FUND", 10.0, id, Some(day(2).fixed_offset())).await.unwrap();
+        assert_eq!(broker.get_account_value(id, Some(day(4).fixed_offset())).await.unwrap(), dec!(1025.0));
+        // a Saturday is not a trading day
+        assert!(matches!(broker.buy("FUND", 1.0, id, Some(day(6).fixed_offset())).await, Err(BankError::MarketClosed)));
+
+        broker.clear_manual_prices("FUND");
+        assert!(broker.get_manual_prices("FUND").is_none());
+    }
+}
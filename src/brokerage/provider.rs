@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use alphavantage::{cache_enabled::{tickers::SearchResults, time_series::TimeSeries}, corprate_actions::DividendResults, time_series::IntradayInterval};
+use async_trait::async_trait;
+
+use crate::bank::error::BankError;
+
+use super::{keys::ClientPool, Broker};
+
+/// The market data the broker needs, so that AlphaVantage can be swapped for another provider
+#[async_trait]
+pub trait MarketDataProvider: Send + Sync {
+    async fn intraday(&self, symbol: &str, interval: IntradayInterval) -> Result<TimeSeries, BankError>;
+
+    async fn daily(&self, symbol: &str) -> Result<TimeSeries, BankError>;
+
+    /// Weekly series, adjusted for splits and dividends
+    async fn weekly(&self, symbol: &str) -> Result<TimeSeries, BankError>;
+
+    /// Monthly series, adjusted for splits and dividends
+    async fn monthly(&self, symbol: &str) -> Result<TimeSeries, BankError>;
+
+    /// Searches for tickers matching the query
+    async fn tickers(&self, query: &str) -> Result<SearchResults, BankError>;
+
+    async fn dividends(&self, symbol: &str) -> Result<DividendResults, BankError>;
+}
+
+/// The default provider, which rotates through the broker's AlphaVantage clients
+pub(crate) struct AlphaVantageProvider {
+    clients: Arc<ClientPool>,
+}
+
+impl AlphaVantageProvider {
+    pub(crate) fn new(clients: Arc<ClientPool>) -> Self {
+        AlphaVantageProvider { clients }
+    }
+}
+
+#[async_trait]
+impl MarketDataProvider for AlphaVantageProvider {
+    async fn intraday(&self, symbol: &str, interval: IntradayInterval) -> Result<TimeSeries, BankError> {
+        let (key, client) = self.clients.next_client()?;
+        let result = client.get_time_series_intraday(symbol, interval).await
+            .map_err(BankError::OtherTokio)?;
+        self.clients.check_rate_limit(key, result)
+    }
+
+    async fn daily(&self, symbol: &str) -> Result<TimeSeries, BankError> {
+        let (key, client) = self.clients.next_client()?;
+        let result = client.get_time_series_daily_full(symbol).await
+            .map_err(BankError::OtherTokio)?;
+        self.clients.check_rate_limit(key, result)
+    }
+
+    async fn weekly(&self, symbol: &str) -> Result<TimeSeries, BankError> {
+        let (key, client) = self.clients.next_client()?;
+        let result = client.get_time_series_weekly_adjusted_full(symbol).await
+            .map_err(BankError::OtherTokio)?;
+        self.clients.check_rate_limit(key, result)
+    }
+
+    async fn monthly(&self, symbol: &str) -> Result<TimeSeries, BankError> {
+        let (key, client) = self.clients.next_client()?;
+        let result = client.get_time_series_monthly_adjusted_full(symbol).await
+            .map_err(BankError::OtherTokio)?;
+        self.clients.check_rate_limit(key, result)
+    }
+
+    async fn tickers(&self, query: &str) -> Result<SearchResults, BankError> {
+        let (key, client) = self.clients.next_client()?;
+        let result = client.get_tickers(query).await
+            .map_err(BankError::OtherTokio)?;
+        self.clients.check_rate_limit(key, result)
+    }
+
+    async fn dividends(&self, symbol: &str) -> Result<DividendResults, BankError> {
+        let (key, client) = self.clients.next_client()?;
+        let result = client.get_dividend_data(symbol).await
+            .map_err(BankError::OtherTokio)?;
+        self.clients.check_rate_limit(key, result)
+    }
+}
+
+impl Broker {
+    /// Makes the broker get its time series, tickers and dividends from the given provider instead of AlphaVantage.
+    /// Earnings still come from AlphaVantage, as they are not part of the provider
+    pub fn set_market_data_provider<P>(&mut self, provider: P)
+    where
+        P: MarketDataProvider + 'static,
+    {
+        self.provider = Box::new(provider);
+    }
+}
+
+/// An in memory provider for tests, serving fixed data
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::{collections::HashMap, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}};
+
+    use alphavantage::{cache_enabled::time_series::Entry, corprate_actions::DividendEntry};
+    use chrono::{DateTime, NaiveDate, Utc};
+
+    use super::*;
+
+    /// A daily or intraday bar that closed at the given price, with the same adjusted close
+    pub(crate) fn bar(date: DateTime<Utc>, close: f64) -> Entry {
+        Entry {
+            date: date.fixed_offset(),
+            open: close,
+            high: close,
+            low: close,
+            close,
+            adjusted_close: Some(close),
+            volume: 0,
+        }
+    }
+
+    /// A dividend of the given amount per share, going ex on one date and paid on another
+    pub(crate) fn dividend(ex_dividend_date: NaiveDate, payment_date: NaiveDate, amount: f64) -> DividendEntry {
+        DividendEntry {
+            ex_dividend_date: Some(ex_dividend_date),
+            declaration_date: None,
+            record_date: None,
+            payment_date: Some(payment_date),
+            amount,
+        }
+    }
+
+    /// A request made of the provider
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Request {
+        Intraday(String),
+        Daily(String),
+        Tickers(String),
+        Dividends(String),
+    }
+
+    /// Serves the series and dividends it was given. Symbols without a series have an empty one, and no ticker is ever found.
+    /// Once taken offline, it only answers requests it answered before, as a caching client would, and is rate limited otherwise
+    #[derive(Clone, Default)]
+    pub(crate) struct MockProvider {
+        intraday: HashMap<String, Vec<Entry>>,
+        daily: HashMap<String, Vec<Entry>>,
+        dividends: HashMap<String, Vec<DividendEntry>>,
+        /// Every request answered so far. Clones of the provider share it
+        answered: Arc<Mutex<Vec<Request>>>,
+        offline: Arc<AtomicBool>,
+    }
+
+    impl MockProvider {
+        pub(crate) fn new() -> Self {
+            MockProvider::default()
+        }
+
+        pub(crate) fn with_intraday(mut self, symbol: &str, bars: Vec<Entry>) -> Self {
+            self.intraday.insert(symbol.to_string(), bars);
+            self
+        }
+
+        pub(crate) fn with_daily(mut self, symbol: &str, bars: Vec<Entry>) -> Self {
+            self.daily.insert(symbol.to_string(), bars);
+            self
+        }
+
+        pub(crate) fn with_dividends(mut self, symbol: &str, dividends: Vec<DividendEntry>) -> Self {
+            self.dividends.insert(symbol.to_string(), dividends);
+            self
+        }
+
+        /// Takes the provider, and every clone of it, offline
+        pub(crate) fn go_offline(&self) {
+            self.offline.store(true, Ordering::SeqCst);
+        }
+
+        fn request(&self, request: Request) -> Result<(), BankError> {
+            let mut answered = self.answered.lock().unwrap();
+            if self.offline.load(Ordering::SeqCst) && !answered.contains(&request) {
+                return Err(BankError::RateLimited);
+            }
+            answered.push(request);
+            Ok(())
+        }
+
+        fn series(bars: &HashMap<String, Vec<Entry>>, symbol: &str) -> TimeSeries {
+            TimeSeries { entries: bars.get(symbol).cloned().unwrap_or_default() }
+        }
+    }
+
+    #[async_trait]
+    impl MarketDataProvider for MockProvider {
+        async fn intraday(&self, symbol: &str, _interval: IntradayInterval) -> Result<TimeSeries, BankError> {
+            self.request(Request::Intraday(symbol.to_string()))?;
+            Ok(Self::series(&self.intraday, symbol))
+        }
+
+        async fn daily(&self, symbol: &str) -> Result<TimeSeries, BankError> {
+            self.request(Request::Daily(symbol.to_string()))?;
+            Ok(Self::series(&self.daily, symbol))
+        }
+
+        async fn weekly(&self, symbol: &str) -> Result<TimeSeries, BankError> {
+            self.daily(symbol).await
+        }
+
+        async fn monthly(&self, symbol: &str) -> Result<TimeSeries, BankError> {
+            self.daily(symbol).await
+        }
+
+        async fn tickers(&self, query: &str) -> Result<SearchResults, BankError> {
+            self.request(Request::Tickers(query.to_string()))?;
+            Ok(SearchResults { entries: Vec::new() })
+        }
+
+        async fn dividends(&self, symbol: &str) -> Result<DividendResults, BankError> {
+            self.request(Request::Dividends(symbol.to_string()))?;
+            Ok(DividendResults { symbol: symbol.to_string(), data: self.dividends.get(symbol).cloned().unwrap_or_default() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use tokio::sync::Mutex;
+
+    use crate::bank::Bank;
+
+    use super::*;
+
+    /// A provider that has run out of requests
+    struct ExhaustedProvider;
+
+    #[async_trait]
+    impl MarketDataProvider for ExhaustedProvider {
+        async fn intraday(&self, _symbol: &str, _interval: IntradayInterval) -> Result<TimeSeries, BankError> {
+            Err(BankError::RateLimited)
+        }
+
+        async fn daily(&self, _symbol: &str) -> Result<TimeSeries, BankError> {
+            Err(BankError::RateLimited)
+        }
+
+        async fn weekly(&self, _symbol: &str) -> Result<TimeSeries, BankError> {
+            Err(BankError::RateLimited)
+        }
+
+        async fn monthly(&self, _symbol: &str) -> Result<TimeSeries, BankError> {
+            Err(BankError::RateLimited)
+        }
+
+        async fn tickers(&self, _query: &str) -> Result<SearchResults, BankError> {
+            Err(BankError::RateLimited)
+        }
+
+        async fn dividends(&self, _symbol: &str) -> Result<DividendResults, BankError> {
+            Err(BankError::RateLimited)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broker_goes_through_provider() {
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        broker.set_market_data_provider(ExhaustedProvider);
+
+        assert!(matches!(broker.get_price("AAPL", None).await, Err(BankError::RateLimited)));
+        assert!(matches!(broker.get_time_series_daily_full("AAPL").await, Err(BankError::RateLimited)));
+        assert!(matches!(broker.get_tickers("AAPL").await, Err(BankError::RateLimited)));
+    }
+}
@@ -0,0 +1,668 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::{Decimal, RoundingStrategy};
+use tracing::info;
+
+use crate::bank::{accounts::Account, error::BankError, lots::WashSaleRule, money, stock, transactions::tags};
+
+use super::{limits::PositionLimitAction, trade::Side, Broker};
+
+/// Splits a contribution between target weights, giving the most to the positions furthest under their target.
+/// Positions at or over their target get nothing, and whatever is left once every position reaches its target
+/// is split by the target weights.
+///
+/// # Arguments
+///
+/// * `values` - The market value of each position now
+/// * `targets` - The target weight of each symbol, summing to one
+/// * `contribution` - The new cash to split
+pub fn underweight_allocation(values: &HashMap<String, Decimal>, targets: &BTreeMap<String, Decimal>, contribution: Decimal) -> BTreeMap<String, Decimal>{
+    let total = values.values().copied().sum::<Decimal>() + contribution;
+    let deficits: BTreeMap<String, Decimal> = targets
+        .iter()
+        .map(|(symbol, weight)| {
+            let value = values.get(symbol).copied().unwrap_or(Decimal::ZERO);
+            (symbol.clone(), (weight * total - value).max(Decimal::ZERO))
+        })
+        .collect();
+    let total_deficit: Decimal = deficits.values().copied().sum();
+    if total_deficit.is_zero(){
+        return targets.iter().map(|(symbol, weight)| (symbol.clone(), weight * contribution)).collect();
+    }
+    if total_deficit >= contribution{
+        return deficits.into_iter().map(|(symbol, deficit)| (symbol, contribution * deficit / total_deficit)).collect();
+    }
+    let remainder = contribution - total_deficit;
+    deficits.into_iter().map(|(symbol, deficit)| {
+        let weight = targets[&symbol];
+        (symbol, deficit + weight * remainder)
+    }).collect()
+}
+
+/// A trade a rebalance would make, as previewed by `rebalance_preview`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProposedTrade{
+    pub symbol: String,
+    pub side: Side,
+    pub quantity: f64,
+    /// The expected fill price, after slippage
+    pub price: Decimal,
+    /// What the shares would cost, or bring in, before commission
+    pub notional: Decimal,
+    pub commission: Decimal,
+}
+
+/// The positions a banded rebalance trades, and what it needs to know to trade them
+struct BandedPlan{
+    cash: Decimal,
+    /// The quantity held of each symbol
+    held: HashMap<String, f64>,
+    /// The quoted price of every held or targeted symbol
+    prices: HashMap<String, Decimal>,
+    /// The value each traded position is traded to, in the order it is traded
+    target_values: Vec<(String, Decimal)>,
+    untouched: Vec<String>,
+    /// The decimals the account keeps quantities to
+    quantity_decimals: u32,
+}
+
+/// A trade a rebalance is about to make, checked by `check_legs` before any trade is made
+struct Leg{
+    symbol: String,
+    side: Side,
+    /// What the position is worth once the trade is made
+    value_after: Decimal,
+}
+
+/// The trade that takes a position to its target value
+enum PositionTrade{
+    /// Buy this much more of the position
+    Buy(Decimal),
+    /// Sell this quantity of it
+    Sell(f64),
+}
+
+/// What a banded rebalance did
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BandedRebalance{
+    /// The quantity traded of each position that drifted out of its band: positive when bought, negative when sold
+    pub traded: BTreeMap<String, f64>,
+    /// The positions left as they were, within their band, or drifted under it with too little cash to buy back up
+    pub untouched: Vec<String>,
+}
+
+impl Broker{
+    /// Rebalances only the positions that have drifted outside their band, so that small drifts do not cost
+    /// a trade and its commission. A position whose weight is more than the band away from its target is traded
+    /// back to the target, with overweight positions sold before underweight ones are bought.
+    /// Holdings not in the targets count toward the account's value but are never traded
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `targets` - The target weight of each symbol, summing to at most one, with the rest held as cash
+    /// * `band` - How far a weight may drift either side of its target before it is traded, such as `dec!(0.05)` for five points
+    /// * `date_limit` - The date limit to get prices at
+    ///
+    /// Every position is priced and every trade is checked, as `check_legs` checks it, before the first sale,
+    /// so an error from the checks leaves the account as it was. A trade can then only fail on something that changed
+    /// after the checks, in which case the trades already made are kept
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the targets are negative or sum to more than one, the band is negative, the account does not exist,
+    /// a holding cannot be priced, or a trade would not go through
+    pub async fn rebalance_banded(&mut self, account_id: u32, targets: &HashMap<String, Decimal>, band: Decimal, date_limit: Option<DateTime<FixedOffset>>) -> Result<BandedRebalance, BankError>{
+        let plan = self.plan_banded(account_id, targets, band, date_limit).await?;
+        let value = |symbol: &String| plan.prices[symbol] * money::from_f64(plan.held.get(symbol).copied().unwrap_or(0.0));
+        let legs: Vec<Leg> = plan.target_values
+            .iter()
+            .map(|(symbol, target_value)| Leg{
+                symbol: symbol.clone(),
+                side: if *target_value < value(symbol) { Side::Sell } else { Side::Buy },
+                value_after: *target_value,
+            })
+            .collect();
+        let equity = plan.cash + plan.held.keys().map(value).sum::<Decimal>();
+        self.check_legs(account_id, &legs, equity, date_limit).await?;
+
+        let mut result = BandedRebalance{ untouched: plan.untouched, ..Default::default() };
+        for (symbol, target_value) in plan.target_values{
+            match self.set_position_value_tagged(&symbol, target_value, account_id, date_limit, tags::REBALANCE).await{
+                Ok(quantity) if quantity != 0.0 => {
+                    result.traded.insert(symbol, quantity);
+                },
+                Ok(_) | Err(BankError::InsufficientFunds) => result.untouched.push(symbol),
+                Err(e) => return Err(e),
+            }
+        }
+        result.untouched.sort();
+        info!(account_id, %band, traded = ?result.traded, "rebalanced the positions outside their band");
+        Ok(result)
+    }
+
+    /// Previews `rebalance_banded` without trading: the trades it would make, in the order it would make them,
+    /// at the expected fill price after slippage. A band of zero previews trading every position back to its target.
+    /// Buys the cash left after the sales would not pay for are left out, as the rebalance would skip them
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `targets` - The target weight of each symbol, summing to at most one, with the rest held as cash
+    /// * `band` - How far a weight may drift either side of its target before it is traded
+    /// * `date_limit` - The date limit to get prices at
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as `rebalance_banded` does, except that no trade can fail
+    pub async fn rebalance_preview(&self, account_id: u32, targets: &HashMap<String, Decimal>, band: Decimal, date_limit: Option<DateTime<FixedOffset>>) -> Result<Vec<ProposedTrade>, BankError>{
+        let plan = self.plan_banded(account_id, targets, band, date_limit).await?;
+        let mut cash = plan.cash;
+        let mut trades = Vec::new();
+        for (symbol, target_value) in plan.target_values{
+            let quote = plan.prices[&symbol];
+            let held = plan.held.get(&symbol).copied().unwrap_or(0.0);
+            let (side, price, quantity) = match self.position_trade(quote, held, target_value, plan.quantity_decimals){
+                None => continue,
+                Some(PositionTrade::Sell(quantity)) => (Side::Sell, self.slippage_model.fill_price(quote, false), quantity),
+                Some(PositionTrade::Buy(notional)) => {
+                    let price = self.slippage_model.fill_price(quote, true);
+                    (Side::Buy, price, self.affordable_quantity(price, notional, plan.quantity_decimals))
+                },
+            };
+            if quantity <= 0.0{
+                continue;
+            }
+            let notional = price * money::from_f64(quantity);
+            let commission = self.commission_schedule.commission(notional, quantity);
+            match side{
+                Side::Sell => cash += notional - commission,
+                Side::Buy if cash >= notional + commission => cash -= notional + commission,
+                Side::Buy => continue,
+            }
+            trades.push(ProposedTrade{ symbol, side, quantity, price, notional, commission });
+        }
+        Ok(trades)
+    }
+
+    /// Works out which positions of an account a banded rebalance trades, and the value each is traded to:
+    /// overweight positions first, then underweight ones, each in order of symbol
+    async fn plan_banded(&self, account_id: u32, targets: &HashMap<String, Decimal>, band: Decimal, date_limit: Option<DateTime<FixedOffset>>) -> Result<BandedPlan, BankError>{
+        if targets.values().any(|weight| weight.is_sign_negative()) || targets.values().copied().sum::<Decimal>() > Decimal::ONE{
+            return Err(BankError::Other(String::from("Target weights must not be negative and must sum to at most one")));
+        }
+        if band.is_sign_negative(){
+            return Err(BankError::Other(String::from("A rebalancing band must not be negative")));
+        }
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        let quantity_decimals = self.bank.lock().await.get_investment_account(account_id)?.get_quantity_decimals();
+        let mut prices = self.price_holdings(&holdings, date_limit).await?;
+        for symbol in targets.keys(){
+            if !prices.contains_key(symbol){
+                prices.insert(symbol.clone(), self.get_price(symbol, date_limit).await?);
+            }
+        }
+        let held: HashMap<String, f64> = holdings
+            .iter()
+            .map(|holding| (holding.asset.symbol.clone(), holding.quantity))
+            .collect();
+        let values: HashMap<&String, Decimal> = held
+            .iter()
+            .map(|(symbol, quantity)| (symbol, prices[symbol] * money::from_f64(*quantity)))
+            .collect();
+        let total = cash + values.values().copied().sum::<Decimal>();
+        let mut overweight = Vec::new();
+        let mut underweight = Vec::new();
+        let mut untouched = Vec::new();
+        if total <= Decimal::ZERO{
+            untouched = targets.keys().cloned().collect();
+        }else{
+            let targets: BTreeMap<&String, Decimal> = targets.iter().map(|(symbol, weight)| (symbol, *weight)).collect();
+            for (symbol, target) in targets{
+                let weight = values.get(symbol).copied().unwrap_or(Decimal::ZERO) / total;
+                if (weight - target).abs() <= band{
+                    untouched.push(symbol.clone());
+                }else if weight > target{
+                    overweight.push((symbol.clone(), target * total));
+                }else{
+                    underweight.push((symbol.clone(), target * total));
+                }
+            }
+        }
+        Ok(BandedPlan{ cash, held, prices, target_values: overweight.into_iter().chain(underweight).collect(), untouched, quantity_decimals })
+    }
+
+    /// Rebalances an investment account to risk parity weights over the symbols, as `risk_parity_weights` computes them
+    /// from the daily returns between start and end, trading only positions that drift outside the band as `rebalance_banded` does.
+    /// The weights are rounded down to a millionth, so a sliver of the account can be left in cash
+    ///
+    /// # Errors
+    ///
+    /// Returns an error as `risk_parity_weights` and `rebalance_banded` do
+    pub async fn rebalance_to_risk_parity(&mut self, account_id: u32, symbols: &[&str], start: DateTime<FixedOffset>, end: DateTime<FixedOffset>, band: Decimal, date_limit: Option<DateTime<FixedOffset>>) -> Result<BandedRebalance, BankError>{
+        let targets: HashMap<String, Decimal> = self.risk_parity_weights(symbols, start, end).await?
+            .into_iter()
+            .map(|(symbol, weight)| (symbol, money::from_f64(weight).round_dp_with_strategy(6, RoundingStrategy::ToZero)))
+            .collect();
+        self.rebalance_banded(account_id, &targets, band, date_limit).await
+    }
+
+    /// Deposits a contribution into an investment account and invests it in the positions under their target weights,
+    /// moving the account toward its targets without selling anything, so no gains are realized.
+    /// Holdings not in the targets count toward the account's value but are never bought.
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `contribution` - The new cash to deposit and invest
+    /// * `targets` - The target weight of each symbol, summing to one
+    /// * `date_limit` - The date limit to get prices at
+    ///
+    /// Every buy is priced and checked, as `check_legs` checks it, before the contribution is deposited,
+    /// so an error from the checks leaves the account as it was. A buy can then only fail on something that changed
+    /// after the checks, in which case the contribution stays deposited and the buys already made are kept
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the targets are negative or do not sum to one, the account does not exist,
+    /// a holding or target cannot be priced, or a buy would not go through. Buys too small for a single share are skipped
+    ///
+    /// # Returns
+    ///
+    /// Returns what was spent on each symbol, commission included
+    pub async fn rebalance_dca(&mut self, account_id: u32, contribution: Decimal, targets: &HashMap<String, Decimal>, date_limit: Option<DateTime<FixedOffset>>) -> Result<BTreeMap<String, Decimal>, BankError>{
+        if targets.values().any(|weight| weight.is_sign_negative()) || targets.values().copied().sum::<Decimal>() != Decimal::ONE{
+            return Err(BankError::Other(String::from("Target weights must not be negative and must sum to one")));
+        }
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        let prices = self.price_holdings(&holdings, date_limit).await?;
+        let values: HashMap<String, Decimal> = holdings
+            .iter()
+            .map(|holding| (holding.asset.symbol.clone(), prices[&holding.asset.symbol] * money::from_f64(holding.quantity)))
+            .collect();
+        let targets: BTreeMap<String, Decimal> = targets.iter().map(|(symbol, weight)| (symbol.clone(), *weight)).collect();
+        let allocation = underweight_allocation(&values, &targets, contribution);
+
+        let mut legs = Vec::new();
+        for (symbol, amount) in allocation.iter().filter(|(_, amount)| **amount > Decimal::ZERO){
+            if !prices.contains_key(symbol){
+                self.get_price(symbol, date_limit).await?;
+            }
+            let value_after = values.get(symbol).copied().unwrap_or(Decimal::ZERO) + amount;
+            legs.push(Leg{ symbol: symbol.clone(), side: Side::Buy, value_after });
+        }
+        let equity = cash + contribution + values.values().copied().sum::<Decimal>();
+        self.check_legs(account_id, &legs, equity, date_limit).await?;
+
+        self.bank.lock().await.get_investment_account_mut(account_id)?.deposit(contribution)?;
+        let mut spent = BTreeMap::new();
+        for (symbol, amount) in allocation{
+            if amount <= Decimal::ZERO{
+                continue;
+            }
+            let before = self.bank.lock().await.get_investment_account(account_id)?.get_balance();
+            match self.buy_notional_tagged(&symbol, amount, account_id, date_limit, tags::REBALANCE).await{
+                Ok(_) | Err(BankError::InsufficientFunds) => {},
+                Err(e) => return Err(e),
+            }
+            let after = self.bank.lock().await.get_investment_account(account_id)?.get_balance();
+            if before > after{
+                spent.insert(symbol, before - after);
+            }
+        }
+        info!(account_id, %contribution, ?spent, "invested a contribution toward the targets");
+        Ok(spent)
+    }
+
+    /// Checks the trades a rebalance is about to make before it makes any, against what `buy` and `sell` check
+    /// that does not depend on the trades before them: the market being open, the account being frozen,
+    /// the position limit and the wash sale rule for buys, and the day trade rule for sells.
+    /// Cash is left to the trades themselves, as rebalances skip the buys they cannot pay for
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `legs` - The trades to check
+    /// * `equity` - What the account is worth, cash included, while the trades are made
+    /// * `date_limit` - The date limit the trades are made at
+    ///
+    /// # Errors
+    ///
+    /// Returns the error the first trade that would not go through would fail with
+    async fn check_legs(&self, account_id: u32, legs: &[Leg], equity: Decimal, date_limit: Option<DateTime<FixedOffset>>) -> Result<(), BankError>{
+        let date = date_limit.unwrap_or(chrono::Utc::now().into());
+        for leg in legs{
+            if !self.is_market_open(&leg.symbol, date).await?{
+                return Err(BankError::MarketClosed);
+            }
+            if leg.side == Side::Sell{
+                self.check_day_trade(account_id, &leg.symbol, date_limit).await?;
+            }
+        }
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        if account.is_frozen(){
+            return Err(BankError::AccountFrozen);
+        }
+        for leg in legs.iter().filter(|leg| leg.side == Side::Buy){
+            if let Some(limit) = self.position_limit.filter(|limit| limit.action == PositionLimitAction::Reject){
+                if leg.value_after > limit.max_weight * equity{
+                    return Err(BankError::PositionLimitExceeded);
+                }
+            }
+            if self.wash_sale_rule == WashSaleRule::Strict && account.would_wash_sale(&leg.symbol, date.to_utc()){
+                return Err(BankError::WashSale);
+            }
+        }
+        Ok(())
+    }
+
+    /// Buys or sells a symbol until the position is worth the target value at the current price.
+    /// Nothing is traded when the difference is less than the smallest quantity that can be traded,
+    /// one share unless fractional shares are allowed, or less than a cent. A target of zero sells the whole position
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol of the position
+    /// * `target_value` - What the position should be worth
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date limit to get the price at
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::InvalidAmount` if the target is negative, `BankError::MarketClosed` if the market is closed,
+    /// or an error as `buy_notional` and `sell` do
+    ///
+    /// # Returns
+    ///
+    /// Returns the quantity traded: positive when bought, negative when sold
+    pub async fn set_position_value(&mut self, symbol: &str, target_value: Decimal, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<f64, BankError>{
+        self.set_position_value_tagged(symbol, target_value, account_id, date_limit, tags::MANUAL).await
+    }
+
+    /// Trades a position to a target value as in `set_position_value`, tagging the transactions it records with the given tag
+    pub(crate) async fn set_position_value_tagged(&mut self, symbol: &str, target_value: Decimal, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, tag: &str) -> Result<f64, BankError>{
+        if target_value.is_sign_negative(){
+            return Err(BankError::InvalidAmount);
+        }
+        let price = self.get_price(symbol, date_limit).await?;
+        if !self.is_market_open(symbol, date_limit.unwrap_or(chrono::Utc::now().into())).await?{
+            return Err(BankError::MarketClosed);
+        }
+        let (held, quantity_decimals) = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            (account.get_investments().get(symbol).map(|holding| holding.quantity).unwrap_or(0.0), account.get_quantity_decimals())
+        };
+        let quantity = match self.position_trade(price, held, target_value, quantity_decimals){
+            None => return Ok(0.0),
+            Some(PositionTrade::Buy(difference)) => {
+                let quantity = self.buy_notional_tagged(symbol, difference, account_id, date_limit, tag).await?;
+                info!(account_id, symbol, quantity, %target_value, "bought up to the target value");
+                return Ok(quantity);
+            },
+            Some(PositionTrade::Sell(quantity)) => quantity,
+        };
+        self.sell_tagged(symbol, quantity, account_id, date_limit, tag).await?;
+        info!(account_id, symbol, quantity, %target_value, "sold down to the target value");
+        Ok(-quantity)
+    }
+
+    /// Works out the trade that takes a position of `held` shares at `price` to the target value, as `set_position_value` makes it,
+    /// in an account that keeps quantities to `quantity_decimals`
+    fn position_trade(&self, price: Decimal, held: f64, target_value: Decimal, quantity_decimals: u32) -> Option<PositionTrade>{
+        let step = self.quantity_step(quantity_decimals);
+        let difference = target_value - price * money::from_f64(held);
+        let tolerance = (price * money::from_f64(step)).max(Decimal::new(1, 2));
+        if difference.abs() < tolerance && !(target_value.is_zero() && held > 0.0){
+            return None;
+        }
+        if difference.is_sign_positive(){
+            return Some(PositionTrade::Buy(difference));
+        }
+        let quantity = if target_value.is_zero(){
+            held
+        }else{
+            stock::round_quantity((money::to_f64(-difference / price) / step).floor() * step, quantity_decimals)
+        };
+        (quantity > 0.0).then_some(PositionTrade::Sell(quantity))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::AccountType, Bank}, brokerage::{commission::CommissionSchedule, limits::PositionLimit, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[test]
+    fn test_underweight_allocation(){
+        let values = HashMap::from([("AAPL".to_string(), dec!(600.0)), ("MSFT".to_string(), dec!(200.0))]);
+        let targets = BTreeMap::from([("AAPL".to_string(), dec!(0.5)), ("MSFT".to_string(), dec!(0.5))]);
+        // only MSFT is under its target of 500
+        let allocation = underweight_allocation(&values, &targets, dec!(200.0));
+        assert_eq!(allocation, BTreeMap::from([("AAPL".to_string(), dec!(0.0)), ("MSFT".to_string(), dec!(200.0))]));
+        // past the targets, the rest is split by weight
+        let allocation = underweight_allocation(&values, &targets, dec!(1000.0));
+        assert_eq!(allocation, BTreeMap::from([("AAPL".to_string(), dec!(300.0)), ("MSFT".to_string(), dec!(700.0))]));
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_dca_never_sells(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(500.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 5.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(100.0))
+            .with_price("MSFT", date, dec!(50.0)));
+
+        let targets = HashMap::from([("AAPL".to_string(), dec!(0.4)), ("MSFT".to_string(), dec!(0.6))]);
+        let spent = broker.rebalance_dca(id, dec!(500.0), &targets, Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(spent, BTreeMap::from([("MSFT".to_string(), dec!(500.0))]));
+        assert!(broker.rebalance_dca(id, dec!(1.0), &HashMap::from([("AAPL".to_string(), dec!(0.5))]), None).await.is_err());
+        let bank = broker.get_bank();
+        let bank = bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].quantity, 5.0);
+        assert_eq!(account.get_investments()["MSFT"].quantity, 10.0);
+        assert_eq!(account.transactions_with_tag(tags::REBALANCE).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_dca_checks_every_buy_first(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(100.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(10.0))
+            .with_price("MSFT", date, dec!(10.0)));
+        let transactions = broker.bank.lock().await.get_investment_account(id).unwrap().transactions.len();
+
+        // GOOG cannot be priced, so AAPL is not bought and the contribution is not deposited
+        let targets = HashMap::from([("AAPL".to_string(), dec!(0.5)), ("GOOG".to_string(), dec!(0.5))]);
+        assert!(broker.rebalance_dca(id, dec!(100.0), &targets, Some(date.fixed_offset())).await.is_err());
+        // MSFT would make up 90 percent of the account
+        broker.set_position_limit(Some(PositionLimit::new(dec!(0.5), PositionLimitAction::Reject)));
+        let targets = HashMap::from([("AAPL".to_string(), dec!(0.1)), ("MSFT".to_string(), dec!(0.9))]);
+        let result = broker.rebalance_dca(id, dec!(900.0), &targets, Some(date.fixed_offset())).await;
+        assert!(matches!(result, Err(BankError::PositionLimitExceeded)));
+
+        let bank = broker.bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_balance(), dec!(100.0));
+        assert_eq!(account.transactions.len(), transactions);
+        assert!(account.get_investments().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_banded_checks_every_trade_first(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 10.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(100.0))
+            .with_price("MSFT", date, dec!(50.0)));
+        broker.set_position_limit(Some(PositionLimit::new(dec!(0.5), PositionLimitAction::Reject)));
+
+        // buying MSFT up to 80 percent breaks the limit, so AAPL is not sold to pay for it
+        let targets = HashMap::from([("AAPL".to_string(), dec!(0.2)), ("MSFT".to_string(), dec!(0.8))]);
+        let result = broker.rebalance_banded(id, &targets, dec!(0.05), Some(date.fixed_offset())).await;
+        assert!(matches!(result, Err(BankError::PositionLimitExceeded)));
+        let bank = broker.bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].quantity, 10.0);
+        assert_eq!(account.get_balance(), Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_to_risk_parity(){
+        use crate::brokerage::provider::mock::{self, MockProvider};
+
+        let day = |day| Utc.with_ymd_and_hms(2024, 1, day, 21, 0, 0).unwrap();
+        // AAPL moves twice as much as MSFT every day, so it gets half the weight
+        let series = |step: f64| {
+            let mut close = 100.0;
+            [2, 3, 4, 5, 8].into_iter().enumerate().map(|(i, date)| {
+                if i > 0{
+                    close *= if i % 2 == 1 { 1.0 + step } else { 1.0 - step };
+                }
+                mock::bar(day(date), close)
+            }).collect()
+        };
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(3000.03)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_market_data_provider(MockProvider::new()
+            .with_daily("AAPL", series(0.02))
+            .with_daily("MSFT", series(0.01)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 10, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(100.0))
+            .with_price("MSFT", date, dec!(100.0)));
+        broker.set_fractional_shares(false);
+        let (start, end) = (day(1).fixed_offset(), day(9).fixed_offset());
+
+        let weights = broker.risk_parity_weights(&["AAPL", "MSFT"], start, end).await.unwrap();
+        assert!((weights["AAPL"] - 1.0 / 3.0).abs() < 1e-9);
+        assert!((weights["MSFT"] - 2.0 / 3.0).abs() < 1e-9);
+
+        let result = broker.rebalance_to_risk_parity(id, &["AAPL", "MSFT"], start, end, dec!(0.05), Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(result.traded, BTreeMap::from([("AAPL".to_string(), 10.0), ("MSFT".to_string(), 20.0)]));
+        let bank = broker.bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].quantity, 10.0);
+        assert_eq!(account.get_investments()["MSFT"].quantity, 20.0);
+        assert_eq!(account.transactions_with_tag(tags::REBALANCE).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_set_position_value(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(20.0), 10.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let time = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", time, dec!(20.0)));
+        let date = Some(time.fixed_offset());
+
+        assert_eq!(broker.set_position_value("AAPL", dec!(300.0), id, date).await.unwrap(), 5.0);
+        assert_eq!(broker.set_position_value("AAPL", dec!(100.0), id, date).await.unwrap(), -10.0);
+        assert_eq!(broker.set_position_value("AAPL", dec!(100.0), id, date).await.unwrap(), 0.0);
+        // fractions go no finer than the account keeps
+        broker.bank.lock().await.get_investment_account_mut(id).unwrap().set_quantity_decimals(1);
+        assert_eq!(broker.set_position_value("AAPL", dec!(93.0), id, date).await.unwrap(), -0.3);
+        // with whole shares only, less than a share's worth of difference is left alone
+        broker.set_fractional_shares(false);
+        assert_eq!(broker.set_position_value("AAPL", dec!(110.0), id, date).await.unwrap(), 0.0);
+        assert_eq!(broker.set_position_value("AAPL", dec!(0.0), id, date).await.unwrap(), -4.7);
+        assert!(broker.bank.lock().await.get_investment_account(id).unwrap().get_investments().is_empty());
+        assert!(matches!(broker.set_position_value("AAPL", dec!(-1.0), id, date).await, Err(BankError::InvalidAmount)));
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_banded(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 42.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 28.0).unwrap();
+        account.purchase_investment("GOOG".to_string(), dec!(10.0), 30.0).unwrap();
+        let bank = Arc::new(Mutex::new(bank));
+        let mut broker = Broker::new(Client::new("unused"), bank.clone());
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(10.0))
+            .with_price("MSFT", date, dec!(10.0))
+            .with_price("GOOG", date, dec!(10.0)));
+        let targets = HashMap::from([
+            ("AAPL".to_string(), dec!(0.3)),
+            ("MSFT".to_string(), dec!(0.3)),
+            ("GOOG".to_string(), dec!(0.3)),
+        ]);
+
+        // AAPL is 12 points over and MSFT 2 points under, GOOG is on target
+        let result = broker.rebalance_banded(id, &targets, dec!(0.05), Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(result.traded, BTreeMap::from([("AAPL".to_string(), -12.0)]));
+        assert_eq!(result.untouched, vec!["GOOG".to_string(), "MSFT".to_string()]);
+        let bank = bank.lock().await;
+        let account = bank.get_investment_account(id).unwrap();
+        assert_eq!(account.get_investments()["AAPL"].quantity, 30.0);
+        assert_eq!(account.get_investments()["MSFT"].quantity, 28.0);
+        assert!(account.transactions.last().unwrap().has_tag(tags::REBALANCE));
+        drop(bank);
+
+        assert!(broker.rebalance_banded(id, &targets, dec!(-0.01), Some(date.fixed_offset())).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rebalance_preview(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 60.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 20.0).unwrap();
+        let bank = Arc::new(Mutex::new(bank));
+        let mut broker = Broker::new(Client::new("unused"), bank.clone());
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(10.0))
+            .with_price("MSFT", date, dec!(10.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+        let targets = HashMap::from([("AAPL".to_string(), dec!(0.4)), ("MSFT".to_string(), dec!(0.4))]);
+
+        // AAPL is sold down from 600 to 400 before MSFT is bought up from 200, with the commission out of the budget
+        let trades = broker.rebalance_preview(id, &targets, Decimal::ZERO, Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(trades, vec![
+            ProposedTrade{ symbol: "AAPL".to_string(), side: Side::Sell, quantity: 20.0, price: dec!(10.0), notional: dec!(200.0), commission: dec!(1.0) },
+            ProposedTrade{ symbol: "MSFT".to_string(), side: Side::Buy, quantity: 19.9, price: dec!(10.0), notional: dec!(199.0), commission: dec!(1.0) },
+        ]);
+        // nothing was traded
+        assert_eq!(bank.lock().await.get_investment_account(id).unwrap().get_balance(), dec!(200.0));
+
+        let result = broker.rebalance_banded(id, &targets, Decimal::ZERO, Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(result.traded, BTreeMap::from([("AAPL".to_string(), -20.0), ("MSFT".to_string(), 19.9)]));
+    }
+}
@@ -0,0 +1,247 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use rust_decimal::Decimal;
+
+use crate::bank::{error::BankError, money::{self, MoneyFormat}, transactions::TransactionType};
+
+use super::Broker;
+
+/// How a portfolio report is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportFormat{
+    #[default]
+    Markdown,
+    Html,
+}
+
+/// A part of a report under its own heading
+enum Section{
+    /// Labelled figures, one per line
+    Figures{ title: &'static str, rows: Vec<(&'static str, String)> },
+    Table{ title: &'static str, headers: Vec<&'static str>, rows: Vec<Vec<String>> },
+}
+
+fn percent(value: Decimal) -> String{
+    format!("{:.2}%", value)
+}
+
+fn render_markdown(title: &str, sections: &[Section]) -> String{
+    let mut out = format!("# {}\n", title);
+    for section in sections{
+        match section{
+            Section::Figures{ title, rows } => {
+                out.push_str(&format!("\n## {}\n\n", title));
+                for (label, value) in rows{
+                    out.push_str(&format!("- **{}:** {}\n", label, value));
+                }
+            }
+            Section::Table{ title, headers, rows } => {
+                out.push_str(&format!("\n## {}\n\n", title));
+                if rows.is_empty(){
+                    out.push_str("None\n");
+                    continue;
+                }
+                out.push_str(&format!("| {} |\n", headers.join(" | ")));
+                out.push_str(&format!("|{}\n", " --- |".repeat(headers.len())));
+                for row in rows{
+                    out.push_str(&format!("| {} |\n", row.join(" | ")));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn render_html(title: &str, sections: &[Section]) -> String{
+    let title = escape_html(title);
+    let mut out = format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n", title);
+    for section in sections{
+        match section{
+            Section::Figures{ title, rows } => {
+                out.push_str(&format!("<h2>{}</h2>\n<dl>\n", escape_html(title)));
+                for (label, value) in rows{
+                    out.push_str(&format!("<dt>{}</dt><dd>{}</dd>\n", escape_html(label), escape_html(value)));
+                }
+                out.push_str("</dl>\n");
+            }
+            Section::Table{ title, headers, rows } => {
+                out.push_str(&format!("<h2>{}</h2>\n", escape_html(title)));
+                if rows.is_empty(){
+                    out.push_str("<p>None</p>\n");
+                    continue;
+                }
+                out.push_str("<table>\n<tr>");
+                for header in headers{
+                    out.push_str(&format!("<th>{}</th>", escape_html(header)));
+                }
+                out.push_str("</tr>\n");
+                for row in rows{
+                    out.push_str("<tr>");
+                    for cell in row{
+                        out.push_str(&format!("<td>{}</td>", escape_html(cell)));
+                    }
+                    out.push_str("</tr>\n");
+                }
+                out.push_str("</table>\n");
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+impl Broker{
+    /// Writes a report of an investment account to save or share: a summary of its value, gains, income and costs,
+    /// its holdings against their cost basis, its allocation, and what it paid in fees.
+    /// The holdings are the current ones priced at the date limit, and the totals count activity up to it
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date to price the holdings at and to total activity up to, or now if `None`
+    /// * `format` - Whether to write Markdown or a standalone HTML page
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or a holding cannot be priced at the date limit
+    /// or at the start of the account's activity
+    pub async fn generate_report(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, format: ReportFormat) -> Result<String, BankError>{
+        let end = date_limit.map(|date| date.with_timezone(&Utc)).unwrap_or_else(Utc::now);
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        let (first_activity, realized_gain, dividends, quantity_decimals) = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            let first_activity = account.transactions.iter().map(|transaction| transaction.date).min();
+            let realized_gain: Decimal = account.closed_lots
+                .iter()
+                .filter(|lot| lot.closed_at <= end)
+                .map(|lot| lot.realized_gain())
+                .sum();
+            let dividends: Decimal = account.transactions
+                .iter()
+                .filter(|transaction| transaction.date <= end && matches!(transaction.transaction_type, TransactionType::Dividend(..)))
+                .map(|transaction| transaction.amount)
+                .sum();
+            (first_activity, realized_gain, dividends, account.get_quantity_decimals())
+        };
+
+        let mut details = self.detail_positions(cash, &holdings, date_limit).await?;
+        details.sort_by(|a, b| b.market_value.cmp(&a.market_value).then_with(|| a.symbol.cmp(&b.symbol)));
+        let fees = self.fee_summary(account_id, DateTime::<Utc>::MIN_UTC, end).await?;
+        let time_weighted_return = match first_activity{
+            Some(start) if start < end => Some(self.time_weighted_return(account_id, start, end).await?),
+            _ => None,
+        };
+
+        let dollars = |amount: Decimal| money::format_money(amount, &MoneyFormat::default());
+        let holdings_value: Decimal = details.iter().map(|detail| detail.market_value).sum();
+        let unrealized_gain: Decimal = details.iter().map(|detail| detail.unrealized_gain).sum();
+        let total_value = cash + holdings_value;
+
+        let summary = Section::Figures{
+            title: "Summary",
+            rows: vec![
+                ("Total value", dollars(total_value)),
+                ("Cash", dollars(cash)),
+                ("Holdings", dollars(holdings_value)),
+                ("Unrealized gain", dollars(unrealized_gain)),
+                ("Realized gain", dollars(realized_gain)),
+                ("Dividend income", dollars(dividends)),
+                ("Fees", dollars(fees.total)),
+                ("Time-weighted return", time_weighted_return
+                    .map(|value| percent(money::from_f64(value * 100.0)))
+                    .unwrap_or_else(|| String::from("n/a"))),
+            ],
+        };
+        let positions = Section::Table{
+            title: "Holdings",
+            headers: vec!["Symbol", "Quantity", "Average cost", "Price", "Market value", "Cost basis", "Unrealized gain", "Gain"],
+            rows: details.iter().map(|detail| vec![
+                detail.symbol.clone(),
+                money::format_quantity(detail.quantity, quantity_decimals),
+                dollars(detail.average_cost_per_unit),
+                dollars(detail.price),
+                dollars(detail.market_value),
+                dollars(detail.cost_basis),
+                dollars(detail.unrealized_gain),
+                percent(detail.unrealized_gain_percent),
+            ]).collect(),
+        };
+        let cash_weight = if total_value.is_zero(){
+            Decimal::ZERO
+        }else{
+            cash / total_value
+        };
+        let mut allocation_rows: Vec<Vec<String>> = details
+            .iter()
+            .map(|detail| vec![detail.symbol.clone(), dollars(detail.market_value), percent(detail.weight * Decimal::ONE_HUNDRED)])
+            .collect();
+        allocation_rows.push(vec![String::from("Cash"), dollars(cash), percent(cash_weight * Decimal::ONE_HUNDRED)]);
+        let allocation = Section::Table{
+            title: "Allocation",
+            headers: vec!["Holding", "Value", "Weight"],
+            rows: allocation_rows,
+        };
+        let costs = Section::Figures{
+            title: "Fees",
+            rows: vec![
+                ("Commissions", dollars(fees.commissions)),
+                ("Estimated slippage", dollars(fees.slippage)),
+                ("Total", dollars(fees.total)),
+            ],
+        };
+
+        let title = format!("Account {} as of {}", account_id, end.format("%Y-%m-%d %H:%M UTC"));
+        let sections = [summary, positions, allocation, costs];
+        Ok(match format{
+            ReportFormat::Markdown => render_markdown(&title, &sections),
+            ReportFormat::Html => render_html(&title, &sections),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, stock::Asset, transactions::Transaction, Bank}, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_generate_report(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(2000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 10.0).unwrap();
+        account.sell_investment("AAPL".to_string(), dec!(120.0), 5.0).unwrap();
+        account.pay_dividend(Transaction::new(TransactionType::Dividend(Asset::new("AAPL".to_string()), 5.0), dec!(2.5), Utc::now(), None));
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap(), dec!(130.0)));
+
+        let markdown = broker.generate_report(id, None, ReportFormat::Markdown).await.unwrap();
+        assert!(markdown.starts_with(&format!("# Account {} as of", id)));
+        assert!(markdown.contains("- **Total value:** $2,252.50"));
+        assert!(markdown.contains("- **Unrealized gain:** $150.00"));
+        assert!(markdown.contains("- **Realized gain:** $100.00"));
+        assert!(markdown.contains("- **Dividend income:** $2.50"));
+        assert!(markdown.contains("| AAPL | 5 | $100.00 | $130.00 | $650.00 | $500.00 | $150.00 | 30.00% |"));
+        assert!(markdown.contains("| Cash | $1,602.50 |"));
+
+        let html = broker.generate_report(id, None, ReportFormat::Html).await.unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<dt>Total value</dt><dd>$2,252.50</dd>"));
+        assert!(html.contains("<td>AAPL</td>"));
+
+        assert!(matches!(broker.generate_report(id + 1, None, ReportFormat::Markdown).await, Err(BankError::AccountNotFound)));
+    }
+}
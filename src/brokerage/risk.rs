@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
+
+use crate::bank::{error::BankError, money};
+
+use super::{metrics, Broker};
+
+impl Broker{
+    /// Computes the beta of an investment account's holdings against a benchmark
+    /// Each holding's beta comes from its daily returns against the benchmark's between start and end,
+    /// and the betas are weighted by the market value of each holding at the last close in the window.
+    /// Cash is not included, so an account with no holdings has a beta of zero.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `benchmark_symbol` - The symbol to measure against, such as SPY
+    /// * `start` - The start of the window of daily returns
+    /// * `end` - The end of the window of daily returns
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, a series cannot be loaded,
+    /// or a holding does not share enough days with the benchmark
+    pub async fn portfolio_beta(&self, account_id: u32, benchmark_symbol: &str, start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> Result<f64, BankError>{
+        let (_, holdings) = self.snapshot_holdings(account_id).await?;
+        if holdings.is_empty(){
+            return Ok(0.0);
+        }
+        let benchmark = metrics::daily_closes(&self.get_time_series_daily_full(benchmark_symbol).await?, self.price_field, start, end)
+            .ok_or_else(|| BankError::PriceUnavailable(benchmark_symbol.to_string()))?;
+
+        let mut weighted_beta = 0.0;
+        let mut total_value = 0.0;
+        for holding in holdings.iter(){
+            let symbol = &holding.asset.symbol;
+            let closes = metrics::daily_closes(&self.get_time_series_daily_full(symbol).await?, self.price_field, start, end)
+                .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))?;
+            let (asset_returns, benchmark_returns) = metrics::aligned_returns(&closes, &benchmark);
+            let beta = metrics::beta(&asset_returns, &benchmark_returns)
+                .ok_or_else(|| BankError::Other(format!("Not enough price history to compute the beta of {}", symbol)))?;
+            let value = holding.quantity * closes.values().last().copied().unwrap_or(0.0);
+            weighted_beta += beta * value;
+            total_value += value;
+        }
+        if total_value == 0.0{
+            return Ok(0.0);
+        }
+        Ok(weighted_beta / total_value)
+    }
+
+    /// Computes risk parity weights for a set of symbols: each weighted inversely to the volatility of its daily returns
+    /// between start and end, so that every position contributes about the same risk. Correlations are not accounted for
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if a series cannot be loaded, or a symbol has too little history or never moves in the window
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the weight of each symbol, summing to one
+    pub async fn risk_parity_weights(&self, symbols: &[&str], start: DateTime<FixedOffset>, end: DateTime<FixedOffset>) -> Result<HashMap<String, f64>, BankError>{
+        let mut volatilities = Vec::with_capacity(symbols.len());
+        for symbol in symbols{
+            let closes = metrics::daily_closes(&self.get_time_series_daily_full(symbol).await?, self.price_field, start, end)
+                .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))?;
+            let returns = metrics::simple_returns(&closes.values().copied().collect::<Vec<f64>>());
+            if returns.len() < 2{
+                return Err(BankError::Other(format!("Not enough price history to compute the volatility of {}", symbol)));
+            }
+            volatilities.push(metrics::standard_deviation(&returns));
+        }
+        let weights = metrics::inverse_volatility_weights(&volatilities)
+            .ok_or_else(|| BankError::Other(String::from("Risk parity needs at least one symbol, each with a price that moves")))?;
+        Ok(symbols.iter().map(|symbol| symbol.to_string()).zip(weights).collect())
+    }
+
+    /// Estimates what an investment account would be left with if every holding were sold at once:
+    /// its cash, plus each holding at the market price less a haircut, less the commission on each sale.
+    /// This is more conservative than the account value, as a forced sale fills into a weak bid.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date limit to get prices at
+    /// * `haircut` - The fraction taken off each price, so 0.02 sells 2% under the market
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the haircut is not between 0 and 1, the account does not exist, or a holding cannot be priced
+    pub async fn liquidation_value(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, haircut: Decimal) -> Result<Decimal, BankError>{
+        if haircut < Decimal::ZERO || haircut > Decimal::ONE{
+            return Err(BankError::Other(format!("A haircut must be between 0 and 1, not {}", haircut)));
+        }
+        let (cash, holdings) = self.snapshot_holdings(account_id).await?;
+        let prices = self.price_holdings(&holdings, date_limit).await?;
+        let proceeds: Decimal = holdings
+            .iter()
+            .map(|holding| {
+                let value = prices[&holding.asset.symbol] * (Decimal::ONE - haircut) * money::from_f64(holding.quantity);
+                value - self.commission_schedule.commission(value, holding.quantity)
+            })
+            .sum();
+        Ok(cash + proceeds)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::{commission::CommissionSchedule, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_liquidation_value(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(100.0)));
+        broker.buy("AAPL", 5.0, id, Some(date.fixed_offset())).await.unwrap();
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+
+        let value = broker.liquidation_value(id, Some(date.fixed_offset()), dec!(0.1)).await.unwrap();
+        assert_eq!(value, dec!(500.0) + dec!(450.0) - dec!(1.0));
+        assert_eq!(broker.get_account_value(id, Some(date.fixed_offset())).await.unwrap(), dec!(1000.0));
+        assert!(broker.liquidation_value(id, None, dec!(1.5)).await.is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use futures::{stream, StreamExt};
+use rust_decimal::Decimal;
+use tracing::{debug, instrument};
+
+use crate::bank::error::BankError;
+
+use super::{fundamentals::Overview, Broker};
+
+/// What a symbol must satisfy to pass a screen. Criteria that are not set are not checked.
+/// A symbol missing the data a criterion needs, such as the P/E of an unprofitable company, does not pass it.
+#[derive(Debug, Clone, Default)]
+pub struct ScreenCriteria{
+    pub max_pe_ratio: Option<f64>,
+    /// As a fraction of the price
+    pub min_dividend_yield: Option<f64>,
+    pub min_market_capitalization: Option<f64>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+}
+
+impl ScreenCriteria{
+    /// Whether any criterion needs the company overview
+    fn needs_overview(&self) -> bool{
+        self.max_pe_ratio.is_some() || self.min_dividend_yield.is_some() || self.min_market_capitalization.is_some()
+    }
+
+    /// Whether any criterion needs the price
+    fn needs_price(&self) -> bool{
+        self.min_price.is_some() || self.max_price.is_some()
+    }
+
+    /// Whether the overview passes the fundamental criteria
+    pub fn matches_overview(&self, overview: &Overview) -> bool{
+        let at_most = |limit: Option<f64>, value: Option<f64>| limit.is_none_or(|limit| value.is_some_and(|value| value <= limit));
+        let at_least = |limit: Option<f64>, value: Option<f64>| limit.is_none_or(|limit| value.is_some_and(|value| value >= limit));
+        at_most(self.max_pe_ratio, overview.pe_ratio)
+            && at_least(self.min_dividend_yield, overview.dividend_yield)
+            && at_least(self.min_market_capitalization, overview.market_capitalization)
+    }
+
+    /// Whether the price passes the price range
+    pub fn matches_price(&self, price: Decimal) -> bool{
+        self.min_price.is_none_or(|min| price >= min) && self.max_price.is_none_or(|max| price <= max)
+    }
+}
+
+impl Broker{
+    /// Checks one symbol against the criteria
+    async fn passes_screen(&self, symbol: &str, criteria: &ScreenCriteria) -> Result<bool, BankError>{
+        if criteria.needs_overview(){
+            let overview = self.get_company_overview(symbol.to_string()).await.map_err(BankError::OtherTokio)??;
+            if !criteria.matches_overview(&overview){
+                return Ok(false);
+            }
+        }
+        if criteria.needs_price(){
+            let price = self.get_price(symbol, None).await?;
+            if !criteria.matches_price(price){
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Filters a universe of symbols down to those that pass the criteria.
+    /// Overviews and prices are fetched `get_max_concurrency` symbols at a time, through the cache.
+    ///
+    /// # Arguments
+    ///
+    /// * `universe` - The symbols to screen
+    /// * `criteria` - What a symbol must satisfy
+    ///
+    /// # Returns
+    ///
+    /// Returns the symbols that pass, in the order of the universe. Symbols whose data cannot be fetched are skipped.
+    #[instrument(skip(self, universe), fields(universe = universe.len()))]
+    pub async fn screen(&self, universe: &[String], criteria: &ScreenCriteria) -> Vec<String>{
+        stream::iter(universe)
+            .map(|symbol| async move {
+                let _permit = self.batch_permit().await;
+                match self.passes_screen(symbol, criteria).await{
+                    Ok(true) => Some(symbol.clone()),
+                    Ok(false) => None,
+                    Err(e) => {
+                        debug!(symbol = %symbol, error = %e, "skipping a symbol that could not be screened");
+                        None
+                    }
+                }
+            })
+            .buffered(self.max_concurrency)
+            .filter_map(|symbol| async move { symbol })
+            .collect()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::Bank, brokerage::prices::MockPriceSource};
+
+    use super::*;
+
+    fn overview(pe_ratio: Option<f64>, dividend_yield: Option<f64>) -> Overview{
+        Overview{
+            symbol: "XYZ".to_string(),
+            name: "XYZ".to_string(),
+            exchange: None,
+            currency: None,
+            sector: None,
+            industry: None,
+            market_capitalization: Some(1e9),
+            pe_ratio,
+            peg_ratio: None,
+            eps: None,
+            book_value: None,
+            dividend_per_share: None,
+            dividend_yield,
+            beta: None,
+            fifty_two_week_high: None,
+            fifty_two_week_low: None,
+            shares_outstanding: None,
+        }
+    }
+
+    #[test]
+    fn test_matches_overview(){
+        let criteria = ScreenCriteria{
+            max_pe_ratio: Some(20.0),
+            min_dividend_yield: Some(0.02),
+            ..Default::default()
+        };
+        assert!(criteria.matches_overview(&overview(Some(15.0), Some(0.03))));
+        assert!(!criteria.matches_overview(&overview(Some(25.0), Some(0.03))));
+        assert!(!criteria.matches_overview(&overview(None, Some(0.03))));
+        assert!(!criteria.matches_overview(&overview(Some(15.0), Some(0.01))));
+        assert!(ScreenCriteria::default().matches_overview(&overview(None, None)));
+    }
+
+    #[tokio::test]
+    async fn test_screen_by_price(){
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(190.0))
+            .with_price("F", date, dec!(12.0))
+            .with_price("MSFT", date, dec!(370.0)));
+        let criteria = ScreenCriteria{
+            min_price: Some(dec!(10.0)),
+            max_price: Some(dec!(200.0)),
+            ..Default::default()
+        };
+        let universe: Vec<String> = ["MSFT", "AAPL", "UNKNOWN", "F"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(broker.screen(&universe, &criteria).await, vec!["AAPL".to_string(), "F".to_string()]);
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::{DateTime, FixedOffset};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::bank::{error::BankError, money};
+
+use super::Broker;
+
+/// How far fills land from the quoted price, always against the trader
+///
+/// The default model has no slippage.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum SlippageModel{
+    #[default]
+    None,
+    /// A fixed number of basis points of the price, so 5 is 0.05%
+    BasisPoints(Decimal),
+    /// A number of basis points drawn evenly between zero and the maximum on every fill,
+    /// from the broker's seeded generator so that runs can be repeated
+    Random{ max_basis_points: Decimal },
+}
+
+impl SlippageModel{
+    /// The slippage per share at the given price, on average for random slippage
+    pub fn per_share(&self, price: Decimal) -> Decimal{
+        price * self.expected_basis_points() / Decimal::from(10_000)
+    }
+
+    fn expected_basis_points(&self) -> Decimal{
+        match self{
+            SlippageModel::None => Decimal::ZERO,
+            SlippageModel::BasisPoints(basis_points) => *basis_points,
+            SlippageModel::Random{ max_basis_points } => max_basis_points / Decimal::TWO,
+        }
+    }
+
+    /// The slippage per share of one fill at the given price, drawn from the generator for random slippage
+    pub fn sample_per_share(&self, price: Decimal, rng: &mut impl Rng) -> Decimal{
+        match self{
+            SlippageModel::Random{ max_basis_points } => {
+                price * max_basis_points * money::from_f64(rng.gen::<f64>()) / Decimal::from(10_000)
+            },
+            _ => self.per_share(price),
+        }
+    }
+
+    /// The price a trade fills at: above the quote for buys, and below it for sells
+    pub fn fill_price(&self, price: Decimal, is_buy: bool) -> Decimal{
+        if is_buy{
+            price + self.per_share(price)
+        }else{
+            price - self.per_share(price)
+        }
+    }
+
+    /// The part of a trade's amount, filled under this model, that was lost to slippage, on average for random slippage
+    pub fn slippage_in(&self, amount: Decimal, is_buy: bool) -> Decimal{
+        let rate = self.expected_basis_points() / Decimal::from(10_000);
+        let quoted = if is_buy{ amount / (Decimal::ONE + rate) }else{ amount / (Decimal::ONE - rate) };
+        (amount - quoted).abs()
+    }
+}
+
+impl Broker{
+    pub fn get_slippage_model(&self) -> &SlippageModel{
+        &self.slippage_model
+    }
+
+    /// Sets how far buy and sell fills land from the quoted price
+    pub fn set_slippage_model(&mut self, slippage_model: SlippageModel){
+        self.slippage_model = slippage_model;
+    }
+
+    /// Seeds the generator random models draw from, so that two runs with the same seed fill at the same prices
+    pub fn set_seed(&mut self, seed: u64){
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// The price one trade fills at, drawing the slippage for random models
+    pub(crate) fn draw_fill_price(&mut self, price: Decimal, is_buy: bool) -> Decimal{
+        let slippage = self.slippage_model.sample_per_share(price, &mut self.rng);
+        if is_buy{
+            price + slippage
+        }else{
+            price - slippage
+        }
+    }
+
+    /// Estimates everything a buy would take out of the account, without making it:
+    /// the price of the shares, the modeled slippage, and the commission
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol to buy
+    /// * `quantity` - The quantity to buy
+    /// * `date_limit` - The date limit to get the price at
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the symbol cannot be priced
+    pub async fn cost_to_acquire(&self, symbol: &str, quantity: f64, date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError>{
+        let price = self.slippage_model.fill_price(self.get_price(symbol, date_limit).await?, true);
+        let cost = price * money::from_f64(quantity);
+        Ok(cost + self.commission_schedule.commission(cost, quantity))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::{commission::CommissionSchedule, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[test]
+    fn test_fill_price(){
+        let model = SlippageModel::BasisPoints(dec!(10.0));
+        assert_eq!(model.fill_price(dec!(100.0), true), dec!(100.1));
+        assert_eq!(model.fill_price(dec!(100.0), false), dec!(99.9));
+        assert_eq!(SlippageModel::None.fill_price(dec!(100.0), true), dec!(100.0));
+    }
+
+    #[tokio::test]
+    async fn test_cost_to_acquire_matches_buy(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap().fixed_offset();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date.to_utc(), dec!(100.0)));
+        broker.set_slippage_model(SlippageModel::BasisPoints(dec!(10.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+
+        let cost = broker.cost_to_acquire("AAPL", 5.0, Some(date)).await.unwrap();
+        assert_eq!(cost, dec!(501.5));
+        let balance = broker.buy("AAPL", 5.0, id, Some(date)).await.unwrap();
+        assert_eq!(balance, dec!(1000.0) - cost);
+        let balance = broker.sell("AAPL", 5.0, id, Some(date)).await.unwrap();
+        assert_eq!(balance, dec!(1000.0) - cost + dec!(499.5) - dec!(1.0));
+    }
+
+    #[tokio::test]
+    async fn test_random_slippage_is_seeded(){
+        async fn fills(seed: u64) -> Vec<Decimal>{
+            let mut bank = Bank::empty();
+            let id = bank.open_account(None, AccountType::Investment).unwrap();
+            bank.get_investment_account_mut(id).unwrap().deposit(dec!(10000.0)).unwrap();
+            let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+            let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap();
+            broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(100.0)));
+            broker.set_slippage_model(SlippageModel::Random{ max_basis_points: dec!(50.0) });
+            broker.set_seed(seed);
+            let mut fills = Vec::new();
+            for _ in 0..5{
+                fills.push(broker.buy("AAPL", 1.0, id, Some(date.fixed_offset())).await.unwrap());
+            }
+            fills
+        }
+        let first = fills(7).await;
+        assert_eq!(first, fills(7).await);
+        assert_ne!(first, fills(8).await);
+        // each fill costs between the quote and the quote plus 0.5%
+        let mut balance = dec!(10000.0);
+        for fill in first{
+            assert!(balance - fill >= dec!(100.0) && balance - fill <= dec!(100.5));
+            balance = fill;
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use disk_cache::cache_async;
+use serde::{Deserialize, Serialize};
+
+use crate::bank::{error::BankError, transactions::{Transaction, TransactionType}};
+
+use super::{fundamentals::{parse_date, parse_number}, Broker};
+
+/// A stock split, or a reverse split when the factor is below one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SplitEvent{
+    /// The first day the stock trades at the split-adjusted price
+    pub effective_date: NaiveDate,
+    /// The number of new shares per old share
+    pub split_factor: f64,
+}
+
+#[derive(Deserialize)]
+struct RawSplits{
+    #[serde(default)]
+    data: Vec<RawSplit>,
+}
+
+#[derive(Deserialize)]
+struct RawSplit{
+    effective_date: String,
+    split_factor: String,
+}
+
+fn parse_splits(body: &str) -> Result<Vec<SplitEvent>, BankError>{
+    let raw: RawSplits = serde_json::from_str(body).map_err(|e| BankError::Other(e.to_string()))?;
+    let mut splits: Vec<SplitEvent> = raw.data
+        .into_iter()
+        .filter_map(|split| Some(SplitEvent{
+            effective_date: parse_date(Some(&split.effective_date))?,
+            split_factor: parse_number(Some(&split.split_factor)).filter(|factor| *factor > 0.0)?,
+        }))
+        .collect();
+    splits.sort_by_key(|split| split.effective_date);
+    Ok(splits)
+}
+
+/// Replays the purchases, sales and corporate actions of a symbol up to `as_of`, scaling each by the splits that took effect after it
+///
+/// # Arguments
+///
+/// * `transactions` - The transaction log of the account
+/// * `symbol` - The symbol to count
+/// * `splits` - The splits of the symbol
+/// * `as_of` - The date to adjust up to
+pub fn split_adjusted_quantity(transactions: &[Transaction], symbol: &str, splits: &[SplitEvent], as_of: DateTime<Utc>) -> f64{
+    let factor_after = |date: NaiveDate| -> f64{
+        splits
+            .iter()
+            .filter(|split| split.effective_date > date && split.effective_date <= as_of.date_naive())
+            .map(|split| split.split_factor)
+            .product()
+    };
+    transactions
+        .iter()
+        .filter(|transaction| transaction.date <= as_of)
+        .map(|transaction| match &transaction.transaction_type{
+            TransactionType::Purchase(asset, quantity) if asset.symbol == symbol => quantity * factor_after(transaction.date.date_naive()),
+            TransactionType::Sale(asset, quantity) if asset.symbol == symbol => -quantity * factor_after(transaction.date.date_naive()),
+            TransactionType::CorporateAction(changes) => changes
+                .iter()
+                .filter(|(asset, _)| asset.symbol == symbol)
+                .map(|(_, change)| change * factor_after(transaction.date.date_naive()))
+                .sum(),
+            _ => 0.0,
+        })
+        .sum()
+}
+
+impl Broker{
+    /// Gets the splits of a symbol, oldest first
+    /// Cached for a day
+    #[cache_async(cache_root = "~/.cache/trading_engine/get_split_history/{symbol}", invalidate_rate = 86400)]
+    pub async fn get_split_history(&self, symbol: String) -> Result<Vec<SplitEvent>, BankError> {
+        let body = self.query_alphavantage(&[("function", "SPLITS"), ("symbol", &symbol)]).await?;
+        parse_splits(&body)
+    }
+
+    /// Gets the quantity of a symbol an account would hold at `as_of`, had every split since each purchase been applied.
+    /// Nothing is changed: the recorded quantities stay as they were traded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or the splits cannot be fetched
+    pub async fn adjusted_quantity(&self, account_id: u32, symbol: &str, as_of: DateTime<Utc>) -> Result<f64, BankError> {
+        let transactions = {
+            let bank = self.bank.lock().await;
+            bank.get_investment_account(account_id)?.transactions.clone()
+        };
+        let splits = self.get_split_history(symbol.to_string()).await.map_err(BankError::OtherTokio)??;
+        Ok(split_adjusted_quantity(&transactions, symbol, &splits, as_of))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+
+    use crate::bank::stock::Asset;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_splits(){
+        let body = r#"{"symbol": "NVDA", "data": [{"effective_date": "2024-06-10", "split_factor": "10.0000"}, {"effective_date": "2021-07-20", "split_factor": "4.0000"}]}"#;
+        let splits = parse_splits(body).unwrap();
+        assert_eq!(splits.len(), 2);
+        assert_eq!(splits[0].effective_date, NaiveDate::from_ymd_opt(2021, 7, 20).unwrap());
+        assert_eq!(splits[1].split_factor, 10.0);
+    }
+
+    #[test]
+    fn test_split_adjusted_quantity(){
+        let date = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 15, 0, 0).unwrap();
+        let trade = |transaction_type, on| Transaction::new(transaction_type, dec!(0.0), on, None);
+        let asset = || Asset::new("NVDA".to_string());
+        let transactions = vec![
+            trade(TransactionType::Purchase(asset(), 10.0), date(2021, 1, 4)),
+            trade(TransactionType::Sale(asset(), 5.0), date(2022, 1, 4)),
+            trade(TransactionType::Purchase(asset(), 1.0), date(2024, 6, 10)),
+            trade(TransactionType::Purchase(Asset::new("AAPL".to_string()), 3.0), date(2021, 1, 4)),
+        ];
+        let splits = vec![
+            SplitEvent{ effective_date: NaiveDate::from_ymd_opt(2021, 7, 20).unwrap(), split_factor: 4.0 },
+            SplitEvent{ effective_date: NaiveDate::from_ymd_opt(2024, 6, 10).unwrap(), split_factor: 10.0 },
+        ];
+        // before the first split nothing changes
+        assert_eq!(split_adjusted_quantity(&transactions, "NVDA", &splits, date(2021, 6, 1)), 10.0);
+        // 10 * 4 - 5
+        assert_eq!(split_adjusted_quantity(&transactions, "NVDA", &splits, date(2023, 1, 1)), 35.0);
+        // (10 * 4 - 5) * 10, plus a purchase made on the day of the split, which is already adjusted
+        assert_eq!(split_adjusted_quantity(&transactions, "NVDA", &splits, date(2024, 7, 1)), 351.0);
+    }
+}
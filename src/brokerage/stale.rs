@@ -0,0 +1,141 @@
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
+use tracing::warn;
+
+use crate::bank::{accounts::InvestmentAccount, error::BankError, money, transactions::TransactionType};
+
+use super::Broker;
+
+/// The value of an investment account, with holdings that could not be priced valued at their last known price
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LenientValuation{
+    pub total_value: Decimal,
+    pub cash: Decimal,
+    pub equity: Decimal,
+    /// The holdings that could not be priced, in order of symbol
+    pub stale: Vec<String>,
+}
+
+/// The price a symbol last traded at in the account, or its average cost if it was never bought or sold there
+fn last_known_price(account: &InvestmentAccount, symbol: &str) -> Option<Decimal>{
+    let last_trade = account.transactions
+        .iter()
+        .rev()
+        .find_map(|transaction| match &transaction.transaction_type{
+            TransactionType::Purchase(asset, quantity) | TransactionType::Sale(asset, quantity) if asset.symbol == symbol && *quantity > 0.0 => {
+                Some(transaction.amount / money::from_f64(*quantity))
+            },
+            _ => None,
+        });
+    last_trade.or_else(|| account.get_investments().get(symbol).map(|holding| holding.average_cost_per_unit))
+}
+
+impl Broker{
+    /// Finds the held symbols that can no longer be priced, such as delisted ones, by asking for the price of each
+    /// symbol held in any investment account
+    /// 
+    /// # Errors
+    /// 
+    /// Returns `BankError::RateLimited` if the prices could not be asked for, which says nothing about the symbols
+    /// 
+    /// # Returns
+    /// 
+    /// Returns the symbols that could not be priced, in order
+    pub async fn detect_stale_holdings(&self, date_limit: Option<DateTime<FixedOffset>>) -> Result<Vec<String>, BankError>{
+        let mut symbols: Vec<String> = self.held_symbols().await.into_iter().collect();
+        symbols.sort();
+        let mut stale = Vec::new();
+        for symbol in symbols{
+            match self.get_price(&symbol, date_limit).await{
+                Ok(_) => {},
+                Err(BankError::RateLimited) => return Err(BankError::RateLimited),
+                Err(e) => {
+                    warn!(symbol, error = %e, "held symbol cannot be priced");
+                    stale.push(symbol);
+                },
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Values an investment account as `get_account_value` does, except that a holding that cannot be priced
+    /// is flagged rather than failing the whole valuation. It is valued at its last known price in the last price store,
+    /// or, if it has none, at the price it last traded at in the account
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the account does not exist, or the prices could not be asked for because of the rate limit
+    pub async fn get_account_value_lenient(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<LenientValuation, BankError>{
+        let (cash, mut holdings) = self.snapshot_holdings(account_id).await?;
+        holdings.sort_by(|a, b| a.asset.symbol.cmp(&b.asset.symbol));
+        let mut valuation = LenientValuation{ cash, ..Default::default() };
+        for holding in holdings{
+            let symbol = holding.asset.symbol;
+            let price = match self.get_price_or_last_known(&symbol, date_limit).await{
+                Ok(quote) => {
+                    if quote.stale{
+                        valuation.stale.push(symbol);
+                    }
+                    quote.price
+                },
+                Err(BankError::RateLimited) => return Err(BankError::RateLimited),
+                Err(e) => {
+                    warn!(account_id, symbol, error = %e, "valuing a holding at the price it last traded at");
+                    let bank = self.bank.lock().await;
+                    let price = last_known_price(bank.get_investment_account(account_id)?, &symbol).unwrap_or(holding.average_cost_per_unit);
+                    valuation.stale.push(symbol);
+                    price
+                },
+            };
+            valuation.equity += price * money::from_f64(holding.quantity);
+        }
+        valuation.total_value = valuation.cash + valuation.equity;
+        Ok(valuation)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{Duration, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType}, Bank}, brokerage::{last_prices::LastPriceStore, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stale_holdings(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(100.0), 2.0).unwrap();
+        account.purchase_investment("XYZ".to_string(), dec!(10.0), 10.0).unwrap();
+        account.purchase_investment("XYZ".to_string(), dec!(20.0), 10.0).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", Utc::now(), dec!(150.0)));
+
+        assert_eq!(broker.detect_stale_holdings(None).await.unwrap(), vec!["XYZ".to_string()]);
+        assert!(broker.get_account_value(id, None).await.is_err());
+        // XYZ is valued at 20, the price it was last bought at, rather than its average cost of 15
+        let valuation = broker.get_account_value_lenient(id, None).await.unwrap();
+        assert_eq!(valuation.cash, dec!(500.0));
+        assert_eq!(valuation.equity, dec!(300.0) + dec!(400.0));
+        assert_eq!(valuation.total_value, dec!(1200.0));
+        assert_eq!(valuation.stale, vec!["XYZ".to_string()]);
+
+        // a last known price comes before the price it last traded at
+        let _ = std::fs::remove_dir_all("tests/test_stale_holdings");
+        let last_prices = LastPriceStore::open("tests/test_stale_holdings/last_prices.json").await.unwrap();
+        last_prices.record("XYZ", Utc::now() - Duration::days(1), dec!(25.0));
+        broker.set_last_price_store(Some(last_prices));
+        let valuation = broker.get_account_value_lenient(id, None).await.unwrap();
+        assert_eq!(valuation.equity, dec!(300.0) + dec!(500.0));
+        assert_eq!(valuation.stale, vec!["XYZ".to_string()]);
+        let _ = std::fs::remove_dir_all("tests/test_stale_holdings");
+    }
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use futures::{stream, Stream};
+use rust_decimal::Decimal;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use crate::bank::error::BankError;
+
+use super::Broker;
+
+impl Broker{
+    /// Streams the latest price of a symbol, polling `get_price` every interval.
+    /// A price is only yielded when it differs from the last one, so a quiet market yields nothing between polls.
+    /// A failed poll yields its error, and the stream carries on polling
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol to watch
+    /// * `interval` - How long to wait between polls. Each poll may cost a request when the cache has expired
+    pub fn price_stream<'a>(&'a self, symbol: &'a str, interval: Duration) -> impl Stream<Item = Result<Decimal, BankError>> + 'a{
+        // the timer is only made once polled, as it needs a running runtime
+        stream::unfold((None::<Interval>, None::<Decimal>), move |(ticker, last)| async move{
+            let mut ticker = ticker.unwrap_or_else(|| {
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                ticker
+            });
+            loop{
+                ticker.tick().await;
+                match self.get_price(symbol, None).await{
+                    Ok(price) if Some(price) == last => continue,
+                    Ok(price) => return Some((Ok(price), (Some(ticker), Some(price)))),
+                    Err(e) => return Some((Err(e), (Some(ticker), last))),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use alphavantage::cache_enabled::client::Client;
+    use async_trait::async_trait;
+    use chrono::{DateTime, FixedOffset};
+    use futures::StreamExt;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::Bank, brokerage::prices::PriceSource};
+
+    use super::*;
+
+    /// Gives out the next price of a fixed sequence on each call, failing once it runs out
+    struct SequenceSource{
+        prices: Vec<Decimal>,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl PriceSource for SequenceSource{
+        async fn price(&self, symbol: &str, _date_limit: Option<DateTime<FixedOffset>>) -> Result<Decimal, BankError>{
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            self.prices.get(call)
+                .copied()
+                .ok_or_else(|| BankError::PriceUnavailable(symbol.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_price_stream_yields_changes(){
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(Bank::empty())));
+        broker.set_price_source(SequenceSource{
+            prices: vec![dec!(100.0), dec!(100.0), dec!(101.0), dec!(101.0), dec!(99.5)],
+            calls: AtomicUsize::new(0),
+        });
+
+        let updates: Vec<_> = broker.price_stream("AAPL", Duration::from_millis(1)).take(4).collect().await;
+        assert_eq!(updates.len(), 4);
+        assert_eq!(updates[0].as_ref().unwrap(), &dec!(100.0));
+        assert_eq!(updates[1].as_ref().unwrap(), &dec!(101.0));
+        assert_eq!(updates[2].as_ref().unwrap(), &dec!(99.5));
+        assert!(matches!(updates[3], Err(BankError::PriceUnavailable(_))));
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Utc};
+use rust_decimal::Decimal;
+
+use crate::bank::{error::BankError, lots::{ClosedLot, WASH_SALE_WINDOW_DAYS}, money, transactions::TransactionType};
+
+use super::Broker;
+
+/// The rates each kind of income is taxed at, as fractions, so 0.15 is 15%
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaxRates{
+    /// For gains on lots held a year or less
+    pub short_term: Decimal,
+    /// For gains on lots held more than a year
+    pub long_term: Decimal,
+    pub dividend: Decimal,
+}
+
+/// The taxable income of an investment account for a year, and the tax owed on it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TaxEstimate{
+    /// Short term gains net of short term losses, before netting against long term
+    pub short_term_gain: Decimal,
+    /// Long term gains net of long term losses, before netting against short term
+    pub long_term_gain: Decimal,
+    pub dividend_income: Decimal,
+    /// Losses that could not be claimed because of wash sales, already added back to the gains
+    pub wash_sale_disallowed: Decimal,
+    pub liability: Decimal,
+}
+
+impl TaxEstimate{
+    /// Nets a loss of one term against a gain of the other, and taxes what is left at the given rates.
+    /// A net loss overall is not taxed, and is not carried over
+    fn compute_liability(&mut self, rates: &TaxRates){
+        let (mut short_term, mut long_term) = (self.short_term_gain, self.long_term_gain);
+        if short_term < Decimal::ZERO && long_term > Decimal::ZERO{
+            long_term = (long_term + short_term).max(Decimal::ZERO);
+            short_term = Decimal::ZERO;
+        }else if long_term < Decimal::ZERO && short_term > Decimal::ZERO{
+            short_term = (short_term + long_term).max(Decimal::ZERO);
+            long_term = Decimal::ZERO;
+        }
+        self.liability = short_term.max(Decimal::ZERO) * rates.short_term
+            + long_term.max(Decimal::ZERO) * rates.long_term
+            + self.dividend_income * rates.dividend;
+    }
+}
+
+/// A holding that could be sold to realize a loss
+#[derive(Debug, Clone, PartialEq)]
+pub struct HarvestCandidate{
+    pub symbol: String,
+    pub quantity: f64,
+    pub average_cost_per_unit: Decimal,
+    pub price: Decimal,
+    /// The loss selling the whole holding would realize, as a positive amount
+    pub harvestable_loss: Decimal,
+    /// Whether the symbol was bought within the wash sale window, so that selling now could have the loss disallowed
+    pub wash_sale_risk: bool,
+}
+
+/// What selling part of a holding would bring in, after commission and the tax on its gain
+#[derive(Debug, Clone, PartialEq)]
+pub struct SellPreview{
+    pub symbol: String,
+    /// The quantity the sale would actually sell, after the account's rounding and dust rules
+    pub quantity: f64,
+    /// The expected fill price, after slippage
+    pub price: Decimal,
+    pub gross_proceeds: Decimal,
+    pub commission: Decimal,
+    /// The average cost of the shares sold
+    pub cost_basis: Decimal,
+    /// Gross proceeds minus the cost basis
+    pub realized_gain: Decimal,
+    /// Whether the gain would be taxed as long term
+    pub long_term: bool,
+    /// If the sale would be short term, how many more days the holding would have to be held for the gain to be long term.
+    /// `None` if the sale would already be long term, or the holding's acquisition date is unknown
+    pub days_until_long_term: Option<i64>,
+    /// The tax on the gain alone, at the rate for its term. A loss is not taxed, and is not netted against other gains.
+    /// Nothing in an account that is not taxable
+    pub estimated_tax: Decimal,
+    /// What is left after the commission and the estimated tax
+    pub net_proceeds: Decimal,
+}
+
+impl Broker{
+    /// Previews selling part of a holding without selling it: the proceeds at the expected fill price,
+    /// the commission, the gain against the average cost, and the tax on that gain.
+    /// The term is decided by when the holding was first bought, as it is for closed lots,
+    /// and a short term sale says how long the holding would have to be kept to become long term
+    ///
+    /// # Arguments
+    ///
+    /// * `symbol` - The symbol to sell
+    /// * `quantity` - The quantity to sell
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date to price the sale at, and to age the holding to
+    /// * `rates` - The rates to tax the gain at
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, the sale would not be allowed, or the symbol cannot be priced
+    pub async fn what_if_sell(&self, symbol: &str, quantity: f64, account_id: u32, date_limit: Option<DateTime<FixedOffset>>, rates: TaxRates) -> Result<SellPreview, BankError>{
+        let (quantity, average_cost_per_unit, first_acquired, taxable) = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            let quantity = account.sale_quantity(symbol, quantity)?;
+            let holding = &account.get_investments()[symbol];
+            (quantity, holding.average_cost_per_unit, holding.get_first_acquired(), account.get_tax_treatment().is_taxable())
+        };
+        let price = self.slippage_model.fill_price(self.get_price(symbol, date_limit).await?, false);
+        let sale_date = date_limit.map(|date| date.with_timezone(&Utc)).unwrap_or_else(Utc::now);
+
+        let gross_proceeds = price * money::from_f64(quantity);
+        let commission = self.commission_schedule.commission(gross_proceeds, quantity);
+        let mut lot = ClosedLot::new(symbol.to_string(), quantity, average_cost_per_unit * money::from_f64(quantity), gross_proceeds, sale_date);
+        if first_acquired != DateTime::UNIX_EPOCH{
+            lot = lot.with_opened_at(first_acquired);
+        }
+        let long_term = lot.is_long_term();
+        let rate = if long_term { rates.long_term } else { rates.short_term };
+        let estimated_tax = if taxable{
+            lot.realized_gain().max(Decimal::ZERO) * rate
+        }else{
+            Decimal::ZERO
+        };
+
+        Ok(SellPreview{
+            symbol: symbol.to_string(),
+            quantity,
+            price,
+            gross_proceeds,
+            commission,
+            cost_basis: lot.cost_basis,
+            realized_gain: lot.realized_gain(),
+            long_term,
+            days_until_long_term: lot.days_until_long_term(),
+            estimated_tax,
+            net_proceeds: gross_proceeds - commission - estimated_tax,
+        })
+    }
+
+    /// Estimates the tax an investment account owes for a calendar year on its realized gains and dividends.
+    /// Each closed lot is short or long term by how long its holding was held, and losses disallowed by wash sales
+    /// are added back to the gains. The income of an account that is not taxable is still totalled, but owes nothing
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `year` - The calendar year, in UTC, that lots were closed and dividends were paid in
+    /// * `rates` - The rates to tax each kind of income at
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist
+    pub async fn estimated_tax(&self, account_id: u32, year: i32, rates: TaxRates) -> Result<TaxEstimate, BankError>{
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        let mut estimate = TaxEstimate::default();
+        for lot in account.closed_lots.iter().filter(|lot| lot.closed_at.year() == year){
+            if lot.is_long_term(){
+                estimate.long_term_gain += lot.taxable_gain();
+            }else{
+                estimate.short_term_gain += lot.taxable_gain();
+            }
+            estimate.wash_sale_disallowed += lot.wash_sale_disallowed.unwrap_or(Decimal::ZERO);
+        }
+        estimate.dividend_income = account.transactions
+            .iter()
+            .filter(|transaction| transaction.date.year() == year && matches!(transaction.transaction_type, TransactionType::Dividend(..)))
+            .map(|transaction| transaction.amount)
+            .sum();
+        if account.get_tax_treatment().is_taxable(){
+            estimate.compute_liability(&rates);
+        }
+        Ok(estimate)
+    }
+
+    /// Suggests holdings to sell to realize a loss: every holding priced below its average cost,
+    /// largest loss first, each flagged if it was bought recently enough that selling could be a wash sale.
+    /// An account that is not taxable has no losses worth harvesting, so nothing is suggested for it
+    ///
+    /// # Arguments
+    ///
+    /// * `account_id` - The id of the investment account
+    /// * `date_limit` - The date to price the holdings at, and to look back for recent purchases from
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the account does not exist, or a holding cannot be priced
+    pub async fn suggest_tax_loss_harvest(&self, account_id: u32, date_limit: Option<DateTime<FixedOffset>>) -> Result<Vec<HarvestCandidate>, BankError>{
+        let now = date_limit.map(|date| date.with_timezone(&Utc)).unwrap_or_else(Utc::now);
+        let window_start = now - Duration::days(WASH_SALE_WINDOW_DAYS);
+        let recently_bought: HashSet<String> = {
+            let bank = self.bank.lock().await;
+            let account = bank.get_investment_account(account_id)?;
+            if !account.get_tax_treatment().is_taxable(){
+                return Ok(Vec::new());
+            }
+            account
+                .transactions
+                .iter()
+                .filter(|transaction| transaction.date >= window_start && transaction.date <= now)
+                .filter_map(|transaction| match &transaction.transaction_type{
+                    TransactionType::Purchase(asset, _) => Some(asset.symbol.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let (_, holdings) = self.snapshot_holdings(account_id).await?;
+        let prices = self.price_holdings(&holdings, date_limit).await?;
+
+        let mut candidates: Vec<HarvestCandidate> = holdings
+            .into_iter()
+            .filter(|holding| prices[&holding.asset.symbol] < holding.average_cost_per_unit)
+            .map(|holding| {
+                let price = prices[&holding.asset.symbol];
+                HarvestCandidate{
+                    harvestable_loss: (holding.average_cost_per_unit - price) * money::from_f64(holding.quantity),
+                    wash_sale_risk: recently_bought.contains(&holding.asset.symbol),
+                    symbol: holding.asset.symbol,
+                    quantity: holding.quantity,
+                    average_cost_per_unit: holding.average_cost_per_unit,
+                    price,
+                }
+            })
+            .collect();
+        // ties are broken by symbol, so the order does not depend on the map the holdings came from
+        candidates.sort_by(|a, b| b.harvestable_loss.cmp(&a.harvestable_loss).then_with(|| a.symbol.cmp(&b.symbol)));
+        Ok(candidates)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::TimeZone;
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::{Account, AccountType, TaxTreatment}, lots::ClosedLot, stock::Asset, transactions::Transaction, Bank}, brokerage::{commission::CommissionSchedule, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_estimated_tax(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        let date = |year, month| Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).unwrap();
+        // a long term gain of 300, a short term gain of 100, and a short term loss of 50 of which 20 was washed
+        account.closed_lots.push(ClosedLot::new("AAPL".to_string(), 1.0, dec!(100.0), dec!(400.0), date(2024, 6)).with_opened_at(date(2022, 1)));
+        account.closed_lots.push(ClosedLot::new("MSFT".to_string(), 1.0, dec!(100.0), dec!(200.0), date(2024, 6)).with_opened_at(date(2024, 1)));
+        let mut washed = ClosedLot::new("GOOG".to_string(), 1.0, dec!(100.0), dec!(50.0), date(2024, 3));
+        washed.wash_sale_disallowed = Some(dec!(20.0));
+        account.closed_lots.push(washed);
+        // a lot closed in another year
+        account.closed_lots.push(ClosedLot::new("AAPL".to_string(), 1.0, dec!(100.0), dec!(900.0), date(2023, 6)));
+        account.pay_dividend(Transaction::new(TransactionType::Dividend(Asset::new("AAPL".to_string()), 10.0), dec!(40.0), date(2024, 2), None));
+        let broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+
+        let rates = TaxRates{ short_term: dec!(0.3), long_term: dec!(0.15), dividend: dec!(0.2) };
+        let estimate = broker.estimated_tax(id, 2024, rates).await.unwrap();
+        assert_eq!(estimate.long_term_gain, dec!(300.0));
+        assert_eq!(estimate.short_term_gain, dec!(70.0));
+        assert_eq!(estimate.wash_sale_disallowed, dec!(20.0));
+        assert_eq!(estimate.dividend_income, dec!(40.0));
+        assert_eq!(estimate.liability, dec!(45.0) + dec!(21.0) + dec!(8.0));
+    }
+
+    #[tokio::test]
+    async fn test_estimated_tax_of_broker_trades(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = |year, month, day| Utc.with_ymd_and_hms(year, month, day, 15, 0, 0).unwrap();
+        broker.set_price_source(MockPriceSource::new()
+            .with_series("AAPL", vec![(date(2022, 1, 3), dec!(10.0)), (date(2023, 6, 1), dec!(15.0)), (date(2024, 6, 3), dec!(20.0))])
+            .with_series("MSFT", vec![(date(2024, 1, 2), dec!(10.0)), (date(2024, 6, 3), dec!(12.0))]));
+
+        broker.buy("AAPL", 20.0, id, Some(date(2022, 1, 3).fixed_offset())).await.unwrap();
+        broker.buy("MSFT", 10.0, id, Some(date(2024, 1, 2).fixed_offset())).await.unwrap();
+        // a long term gain of 50 in 2023, then a long term gain of 100 and a short term gain of 20 in 2024
+        broker.sell("AAPL", 10.0, id, Some(date(2023, 6, 1).fixed_offset())).await.unwrap();
+        broker.sell("AAPL", 10.0, id, Some(date(2024, 6, 3).fixed_offset())).await.unwrap();
+        broker.sell("MSFT", 10.0, id, Some(date(2024, 6, 3).fixed_offset())).await.unwrap();
+
+        let rates = TaxRates{ short_term: dec!(0.3), long_term: dec!(0.15), dividend: dec!(0.2) };
+        let estimate = broker.estimated_tax(id, 2024, rates).await.unwrap();
+        assert_eq!(estimate.long_term_gain, dec!(100.0));
+        assert_eq!(estimate.short_term_gain, dec!(20.0));
+        let estimate = broker.estimated_tax(id, 2023, rates).await.unwrap();
+        assert_eq!(estimate.long_term_gain, dec!(50.0));
+        assert_eq!(estimate.short_term_gain, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_tax_advantaged_account_owes_nothing(){
+        let mut bank = Bank::empty();
+        let id = bank.open_investment_account(None, TaxTreatment::TaxFree).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 10.0).unwrap();
+        let date = Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap();
+        account.closed_lots.push(ClosedLot::new("MSFT".to_string(), 1.0, dec!(100.0), dec!(200.0), date));
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(20.0)));
+        let rates = TaxRates{ short_term: dec!(0.3), long_term: dec!(0.15), dividend: dec!(0.2) };
+
+        let estimate = broker.estimated_tax(id, 2024, rates).await.unwrap();
+        assert_eq!(estimate.short_term_gain, dec!(100.0));
+        assert_eq!(estimate.liability, Decimal::ZERO);
+        let preview = broker.what_if_sell("AAPL", 10.0, id, Some(date.fixed_offset()), rates).await.unwrap();
+        assert_eq!(preview.realized_gain, dec!(100.0));
+        assert_eq!(preview.estimated_tax, Decimal::ZERO);
+        assert_eq!(preview.net_proceeds, dec!(200.0));
+
+        // a loss in the account is not worth harvesting
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date, dec!(5.0)));
+        assert!(broker.suggest_tax_loss_harvest(id, Some(date.fixed_offset())).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_suggest_tax_loss_harvest(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 10.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 10.0).unwrap();
+        account.purchase_investment("GOOG".to_string(), dec!(10.0), 10.0).unwrap();
+        let date = Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap();
+        // only MSFT was bought within the wash sale window
+        account.transactions[1].date = date - Duration::days(60);
+        account.transactions[2].date = date - Duration::days(5);
+        account.transactions[3].date = date - Duration::days(60);
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(8.0))
+            .with_price("MSFT", date, dec!(5.0))
+            .with_price("GOOG", date, dec!(12.0)));
+
+        let candidates = broker.suggest_tax_loss_harvest(id, Some(date.fixed_offset())).await.unwrap();
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].symbol, "MSFT");
+        assert_eq!(candidates[0].harvestable_loss, dec!(50.0));
+        assert!(candidates[0].wash_sale_risk);
+        assert_eq!(candidates[1].symbol, "AAPL");
+        assert_eq!(candidates[1].harvestable_loss, dec!(20.0));
+        assert!(!candidates[1].wash_sale_risk);
+    }
+
+    #[tokio::test]
+    async fn test_what_if_sell(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(1000.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 10.0).unwrap();
+        account.purchase_investment("MSFT".to_string(), dec!(10.0), 10.0).unwrap();
+        let date = Utc.with_ymd_and_hms(2024, 3, 1, 15, 0, 0).unwrap();
+        account.assets.get_mut("AAPL").unwrap().first_acquired = date - Duration::days(400);
+        account.assets.get_mut("MSFT").unwrap().first_acquired = date - Duration::days(30);
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        broker.set_price_source(MockPriceSource::new()
+            .with_price("AAPL", date, dec!(20.0))
+            .with_price("MSFT", date, dec!(8.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+        let rates = TaxRates{ short_term: dec!(0.3), long_term: dec!(0.15), dividend: dec!(0.2) };
+
+        let preview = broker.what_if_sell("AAPL", 4.0, id, Some(date.fixed_offset()), rates).await.unwrap();
+        assert_eq!(preview.gross_proceeds, dec!(80.0));
+        assert_eq!(preview.realized_gain, dec!(40.0));
+        assert!(preview.long_term);
+        assert_eq!(preview.days_until_long_term, None);
+        assert_eq!(preview.estimated_tax, dec!(6.0));
+        assert_eq!(preview.net_proceeds, dec!(73.0));
+
+        // a short term loss owes no tax
+        let preview = broker.what_if_sell("MSFT", 10.0, id, Some(date.fixed_offset()), rates).await.unwrap();
+        assert_eq!(preview.realized_gain, dec!(-20.0));
+        assert!(!preview.long_term);
+        assert_eq!(preview.days_until_long_term, Some(336));
+        assert_eq!(preview.estimated_tax, Decimal::ZERO);
+        assert_eq!(preview.net_proceeds, dec!(79.0));
+
+        assert!(matches!(broker.what_if_sell("GOOG", 1.0, id, None, rates).await, Err(BankError::InsufficientQuantity)));
+        // nothing was sold
+        assert_eq!(broker.bank.lock().await.get_investment_account(id).unwrap().get_investments()["AAPL"].quantity, 10.0);
+    }
+
+    #[test]
+    fn test_losses_offset_the_other_term(){
+        let rates = TaxRates{ short_term: dec!(0.3), long_term: dec!(0.15), dividend: dec!(0.2) };
+        let mut estimate = TaxEstimate{ short_term_gain: dec!(-100.0), long_term_gain: dec!(300.0), ..Default::default() };
+        estimate.compute_liability(&rates);
+        assert_eq!(estimate.liability, dec!(30.0));
+    }
+}
@@ -0,0 +1,254 @@
+use chrono::{DateTime, FixedOffset};
+use rust_decimal::Decimal;
+use tracing::info;
+
+use crate::bank::{accounts::Account, error::BankError, money, transactions::TransactionType};
+
+use super::{orders::{OrderId, OrderKind, OrderStatus}, Broker};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side{
+    Buy,
+    Sell,
+}
+
+/// How much a trade is for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sizing{
+    Shares(f64),
+    /// Shares worth this much at the quote
+    Dollars(Decimal),
+    /// Whatever it takes for the position to be worth this much, buying or selling whichever the side
+    TargetValue(Decimal),
+    /// A fraction of the holding, such as 0.5 for half of it. Only sales can be sized this way
+    Fraction(f64),
+}
+
+/// When a trade goes through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceType{
+    /// Now, at the quote
+    Market,
+    /// Now if the quote is at or better than the limit, or else once it gets there
+    Limit(Decimal),
+    /// Sells now if the quote is at or below the stop, or else once it gets there
+    Stop(Decimal),
+}
+
+/// Everything needed to make a trade, for `Broker::execute`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TradeOrder{
+    pub symbol: String,
+    pub account_id: u32,
+    pub side: Side,
+    pub sizing: Sizing,
+    pub price_type: PriceType,
+    /// The date limit to price the trade at
+    pub date_limit: Option<DateTime<FixedOffset>>,
+}
+
+impl TradeOrder{
+    /// A market order, which can be made a limit or stop order with `with_price_type`
+    pub fn new(symbol: &str, account_id: u32, side: Side, sizing: Sizing) -> Self{
+        TradeOrder{
+            symbol: symbol.to_string(),
+            account_id,
+            side,
+            sizing,
+            price_type: PriceType::Market,
+            date_limit: None,
+        }
+    }
+
+    pub fn with_price_type(mut self, price_type: PriceType) -> Self{
+        self.price_type = price_type;
+        self
+    }
+
+    pub fn with_date_limit(mut self, date_limit: DateTime<FixedOffset>) -> Self{
+        self.date_limit = Some(date_limit);
+        self
+    }
+}
+
+/// A trade that went through
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill{
+    pub symbol: String,
+    /// Which way the trade went, which for a target value may not be the side asked for
+    pub side: Side,
+    /// The quantity traded, after the account's rounding. Zero when a target value was already met
+    pub quantity: f64,
+    /// The average price filled at, or the quote when nothing was traded
+    pub price: Decimal,
+    pub commission: Decimal,
+    /// The balance of the account after the trade
+    pub balance: Decimal,
+}
+
+/// What became of an order given to `Broker::execute`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Execution{
+    /// The order traded straight away
+    Filled(Fill),
+    /// The limit or stop was not reached, so the order was put in the order book to fill once it is
+    Resting(OrderId),
+}
+
+impl Broker{
+    /// Makes a trade described by a single order, dispatching to `buy`, `sell`, `buy_notional`, `sell_fraction` or
+    /// `set_position_value`. A limit or stop order whose price has not been reached is put in the order book instead,
+    /// with the quantity it is for worked out now
+    ///
+    /// # Errors
+    ///
+    /// Returns `BankError::InvalidOrder` for a stop buy, a buy sized by a fraction, or a target value that is not a market order.
+    /// Otherwise returns an error as the trade it dispatches to does
+    pub async fn execute(&mut self, order: TradeOrder) -> Result<Execution, BankError>{
+        match (order.side, order.sizing, order.price_type){
+            (Side::Buy, _, PriceType::Stop(_)) => return Err(BankError::InvalidOrder(String::from("stop orders can only sell"))),
+            (Side::Buy, Sizing::Fraction(_), _) => return Err(BankError::InvalidOrder(String::from("only sales can be sized by a fraction of the holding"))),
+            (_, Sizing::TargetValue(_), PriceType::Limit(_) | PriceType::Stop(_)) => {
+                return Err(BankError::InvalidOrder(String::from("a target value can only be traded at market")));
+            },
+            _ => {},
+        }
+        let TradeOrder{ symbol, account_id, side, sizing, price_type, date_limit } = order;
+        let quote = self.get_price(&symbol, date_limit).await?;
+        let resting = match (side, price_type){
+            (_, PriceType::Market) => None,
+            (Side::Buy, PriceType::Limit(limit)) => (quote > limit).then_some((OrderKind::LimitBuy{ limit }, limit)),
+            (Side::Sell, PriceType::Limit(limit)) => (quote < limit).then_some((OrderKind::LimitSell{ limit }, limit)),
+            (Side::Sell, PriceType::Stop(stop)) => (quote > stop).then_some((OrderKind::StopSell{ stop }, stop)),
+            (Side::Buy, PriceType::Stop(_)) => unreachable!("stop buys are rejected above"),
+        };
+        if let Some((kind, trigger)) = resting{
+            self.bank.lock().await.get_investment_account(account_id)?;
+            let quantity = match sizing{
+                Sizing::Shares(quantity) => quantity,
+                Sizing::Dollars(amount) => money::to_f64(amount / trigger),
+                Sizing::Fraction(fraction) => self.bank.lock().await
+                    .get_investment_account(account_id)?
+                    .get_investments()
+                    .get(&symbol)
+                    .map(|holding| holding.quantity * fraction)
+                    .ok_or(BankError::HoldingNotFound)?,
+                Sizing::TargetValue(_) => unreachable!("target values are rejected above"),
+            };
+            if quantity.is_nan() || quantity <= 0.0{
+                return Err(BankError::InvalidOrder(String::from("quantity must be positive")));
+            }
+            let id = self.orders.add(account_id, &symbol, quantity, kind, OrderStatus::Active, None);
+            info!(id, symbol, quantity, "order is resting until its price is reached");
+            return Ok(Execution::Resting(id));
+        }
+
+        let first_new_transaction = self.bank.lock().await.get_investment_account(account_id)?.transactions.len();
+        match (side, sizing){
+            (_, Sizing::TargetValue(value)) => {
+                self.set_position_value(&symbol, value, account_id, date_limit).await?;
+            },
+            (Side::Buy, Sizing::Shares(quantity)) => {
+                self.buy(&symbol, quantity, account_id, date_limit).await?;
+            },
+            (Side::Buy, Sizing::Dollars(amount)) => {
+                self.buy_notional(&symbol, amount, account_id, date_limit).await?;
+            },
+            (Side::Buy, Sizing::Fraction(_)) => unreachable!("fractional buys are rejected above"),
+            (Side::Sell, Sizing::Shares(quantity)) => {
+                self.sell(&symbol, quantity, account_id, date_limit).await?;
+            },
+            (Side::Sell, Sizing::Dollars(amount)) => {
+                self.sell(&symbol, money::to_f64(amount / quote), account_id, date_limit).await?;
+            },
+            (Side::Sell, Sizing::Fraction(fraction)) => {
+                self.sell_fraction(&symbol, fraction, account_id, date_limit).await?;
+            },
+        }
+
+        // the trade may have rounded the quantity or filled away from the quote, so read back what it recorded
+        let bank = self.bank.lock().await;
+        let account = bank.get_investment_account(account_id)?;
+        let mut fill = Fill{ symbol, side, quantity: 0.0, price: quote, commission: Decimal::ZERO, balance: account.get_balance() };
+        let mut notional = Decimal::ZERO;
+        for transaction in &account.transactions[first_new_transaction..]{
+            match &transaction.transaction_type{
+                TransactionType::Purchase(_, quantity) => {
+                    fill.side = Side::Buy;
+                    fill.quantity += quantity;
+                    notional += transaction.amount;
+                },
+                TransactionType::Sale(_, quantity) => {
+                    fill.side = Side::Sell;
+                    fill.quantity += quantity;
+                    notional += transaction.amount;
+                },
+                TransactionType::Commission(_) => fill.commission += transaction.amount,
+                _ => {},
+            }
+        }
+        if fill.quantity > 0.0{
+            fill.price = notional / money::from_f64(fill.quantity);
+        }
+        Ok(Execution::Filled(fill))
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::Arc;
+
+    use alphavantage::cache_enabled::client::Client;
+    use chrono::{TimeZone, Utc};
+    use rust_decimal_macros::dec;
+    use tokio::sync::Mutex;
+
+    use crate::{bank::{accounts::AccountType, Bank}, brokerage::{commission::CommissionSchedule, prices::MockPriceSource}};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_execute(){
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(1000.0)).unwrap();
+        let mut broker = Broker::new(Client::new("unused"), Arc::new(Mutex::new(bank)));
+        let date = Utc.with_ymd_and_hms(2024, 1, 2, 15, 0, 0).unwrap().fixed_offset();
+        broker.set_price_source(MockPriceSource::new().with_price("AAPL", date.to_utc(), dec!(10.0)));
+        broker.set_commission_schedule(CommissionSchedule{ flat: dec!(1.0), ..Default::default() });
+        let order = |side, sizing| TradeOrder::new("AAPL", id, side, sizing).with_date_limit(date);
+
+        let execution = broker.execute(order(Side::Buy, Sizing::Shares(20.0))).await.unwrap();
+        assert_eq!(execution, Execution::Filled(Fill{
+            symbol: "AAPL".to_string(),
+            side: Side::Buy,
+            quantity: 20.0,
+            price: dec!(10.0),
+            commission: dec!(1.0),
+            balance: dec!(799.0),
+        }));
+        let Execution::Filled(fill) = broker.execute(order(Side::Sell, Sizing::Fraction(0.25))).await.unwrap() else{
+            panic!("a market order should fill");
+        };
+        assert_eq!(fill.quantity, 5.0);
+        // the side of a target value is whichever way gets there
+        let Execution::Filled(fill) = broker.execute(order(Side::Buy, Sizing::TargetValue(dec!(100.0)))).await.unwrap() else{
+            panic!("a market order should fill");
+        };
+        assert_eq!((fill.side, fill.quantity), (Side::Sell, 5.0));
+
+        // a limit below the quote rests in the order book, sized at the limit
+        let execution = broker.execute(order(Side::Buy, Sizing::Dollars(dec!(80.0))).with_price_type(PriceType::Limit(dec!(8.0)))).await.unwrap();
+        let Execution::Resting(resting) = execution else{
+            panic!("the limit has not been reached");
+        };
+        let resting = broker.get_order_book().get_order(resting).unwrap();
+        assert_eq!((resting.quantity, &resting.kind), (10.0, &OrderKind::LimitBuy{ limit: dec!(8.0) }));
+        // a stop above the quote sells now
+        let execution = broker.execute(order(Side::Sell, Sizing::Shares(1.0)).with_price_type(PriceType::Stop(dec!(11.0)))).await.unwrap();
+        assert!(matches!(execution, Execution::Filled(Fill{ side: Side::Sell, .. })));
+
+        assert!(matches!(broker.execute(order(Side::Buy, Sizing::Shares(1.0)).with_price_type(PriceType::Stop(dec!(9.0)))).await, Err(BankError::InvalidOrder(_))));
+        assert!(matches!(broker.execute(order(Side::Buy, Sizing::Fraction(0.5))).await, Err(BankError::InvalidOrder(_))));
+    }
+}
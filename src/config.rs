@@ -0,0 +1,25 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::brokerage::prices::PriceField;
+
+/// Settings for starting the engine
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineConfig{
+    /// Paper money to fund a default investment account with when a new bank is created.
+    /// A bank loaded from disk is never funded
+    #[serde(default)]
+    pub starting_cash: Option<Decimal>,
+    /// The seed for the broker's random models, such as random slippage. Runs with the same seed repeat exactly
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// How many requests the broker's batch operations have in flight at once
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
+    /// Which price of each daily bar the broker's backtests and risk measures use
+    #[serde(default)]
+    pub price_field: Option<PriceField>,
+    /// Which price of each intraday bar the broker values and trades at
+    #[serde(default)]
+    pub intraday_price_field: Option<PriceField>,
+}
@@ -1,2 +1,4 @@
 pub mod bank;
-pub mod brokerage;
\ No newline at end of file
+pub mod brokerage;
+pub mod config;
+pub mod state;
@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+use argon2::Argon2;
+use async_trait::async_trait;
+use rand::RngCore;
+
+use crate::bank::Bank;
+
+use super::{create_private_dir_all, StateStore};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Returned, wrapped in an `std::io::Error` of kind `InvalidData`, when the saved bank can not be decrypted.
+/// The cipher is authenticated, so this happens when the passphrase is wrong or the file was tampered with.
+#[derive(Debug, thiserror::Error)]
+#[error("Wrong passphrase, or the saved bank was modified")]
+pub struct WrongPassphrase;
+
+/// Saves the whole bank as one JSON file, encrypted with AES-256-GCM under a key derived from a passphrase with Argon2.
+///
+/// The file is the salt, then the nonce, then the ciphertext. Every save draws a fresh salt and nonce.
+pub struct EncryptedFileStore{
+    path: PathBuf,
+    passphrase: String,
+}
+
+fn to_io(e: impl std::fmt::Display) -> std::io::Error{
+    std::io::Error::other(e.to_string())
+}
+
+impl EncryptedFileStore{
+    pub fn new(path: impl Into<PathBuf>, passphrase: impl Into<String>) -> Self{
+        EncryptedFileStore{ path: path.into(), passphrase: passphrase.into() }
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<Aes256Gcm, std::io::Error>{
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(to_io)?;
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+}
+
+#[async_trait]
+impl StateStore for EncryptedFileStore{
+    async fn save(&self, bank: &Bank) -> Result<(), std::io::Error>{
+        let json = serde_json::to_vec(bank)?;
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self.cipher(&salt)?
+            .encrypt(Nonce::from_slice(&nonce), json.as_slice())
+            .map_err(to_io)?;
+
+        let mut contents = Vec::with_capacity(SALT_LEN + NONCE_LEN + ciphertext.len());
+        contents.extend_from_slice(&salt);
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&ciphertext);
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()){
+            create_private_dir_all(parent)?;
+        }
+        tokio::fs::write(&self.path, contents).await
+    }
+
+    /// Loads and decrypts the saved bank
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` wrapping [`WrongPassphrase`] if the file can not be decrypted
+    async fn load(&self) -> Result<Bank, std::io::Error>{
+        let contents = tokio::fs::read(&self.path).await?;
+        if contents.len() < SALT_LEN + NONCE_LEN{
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Encrypted bank is truncated"));
+        }
+        let (salt, rest) = contents.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let json = self.cipher(salt)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, WrongPassphrase))?;
+        Ok(serde_json::from_slice(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use crate::bank::accounts::{Account, AccountType};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_encrypted_file_store(){
+        let path = "tests/test_encrypted_file_store.bin";
+        let _ = std::fs::remove_file(path);
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(10.0)).unwrap();
+
+        let store = EncryptedFileStore::new(path, "correct horse");
+        store.save(&bank).await.unwrap();
+        assert!(!std::fs::read(path).unwrap().windows(7).any(|w| w == b"balance"));
+        assert_eq!(store.load().await.unwrap().to_string(), bank.to_string());
+
+        let error = EncryptedFileStore::new(path, "battery staple").load().await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.get_ref().unwrap().is::<WrongPassphrase>());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use crate::bank::Bank;
+
+#[cfg(feature = "encryption")]
+pub mod encrypted;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+/// Creates a directory and any missing parents, readable only by the current user on Unix.
+/// Directories that already exist keep their permissions.
+pub fn create_private_dir_all(path: &Path) -> Result<(), std::io::Error>{
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+/// Somewhere the bank can be saved to and loaded back from
+#[async_trait]
+pub trait StateStore: Send + Sync{
+    async fn save(&self, bank: &Bank) -> Result<(), std::io::Error>;
+
+    /// Loads the saved bank
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `std::io::ErrorKind::NotFound` if nothing has been saved yet
+    async fn load(&self) -> Result<Bank, std::io::Error>;
+}
+
+/// Saves the whole bank as one JSON file, rewriting it on every save
+pub struct JsonFileStore{
+    path: PathBuf,
+}
+
+impl JsonFileStore{
+    pub fn new(path: impl Into<PathBuf>) -> Self{
+        JsonFileStore{ path: path.into() }
+    }
+}
+
+#[async_trait]
+impl StateStore for JsonFileStore{
+    async fn save(&self, bank: &Bank) -> Result<(), std::io::Error>{
+        let json = serde_json::to_string(bank)?;
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()){
+            create_private_dir_all(parent)?;
+        }
+        tokio::fs::write(&self.path, json).await
+    }
+
+    async fn load(&self) -> Result<Bank, std::io::Error>{
+        let json = tokio::fs::read_to_string(&self.path).await?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use crate::bank::accounts::{Account, AccountType};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_json_file_store(){
+        let store = JsonFileStore::new("tests/test_json_file_store.json");
+        let _ = std::fs::remove_file("tests/test_json_file_store.json");
+        assert_eq!(store.load().await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_investment_account_mut(id).unwrap().deposit(dec!(10.0)).unwrap();
+        store.save(&bank).await.unwrap();
+        let loaded = store.load().await.unwrap();
+        assert_eq!(loaded.to_string(), bank.to_string());
+        std::fs::remove_file("tests/test_json_file_store.json").unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_private_dir_all(){
+        use std::os::unix::fs::PermissionsExt;
+
+        let _ = std::fs::remove_dir_all("tests/test_private_dir");
+        create_private_dir_all(Path::new("tests/test_private_dir/nested")).unwrap();
+        let mode = std::fs::metadata("tests/test_private_dir/nested").unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        std::fs::remove_dir_all("tests/test_private_dir").unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection};
+use serde_json::Value;
+
+use crate::bank::{accounts::{CheckingAccount, InvestmentAccount}, Bank};
+
+use super::StateStore;
+
+const CHECKING: &str = "checking";
+const INVESTMENT: &str = "investment";
+
+/// Saves the bank to a SQLite database, with one row per account and one row per transaction.
+///
+/// A save only writes the transactions that are new or changed since the last save, rather than rewriting the whole bank,
+/// and deletes those past the end of a log that shrank.
+pub struct SqliteStore{
+    connection: Mutex<Connection>,
+}
+
+fn to_io(e: impl std::error::Error + Send + Sync + 'static) -> std::io::Error{
+    std::io::Error::other(e)
+}
+
+impl SqliteStore{
+    /// Opens the database at the path, creating it and its tables if needed
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, std::io::Error>{
+        Self::with_connection(Connection::open(path).map_err(to_io)?)
+    }
+
+    /// Opens a database that lives only as long as the store
+    pub fn open_in_memory() -> Result<Self, std::io::Error>{
+        Self::with_connection(Connection::open_in_memory().map_err(to_io)?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, std::io::Error>{
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                kind TEXT NOT NULL,
+                id INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (kind, id)
+            );
+            CREATE TABLE IF NOT EXISTS transactions (
+                kind TEXT NOT NULL,
+                account_id INTEGER NOT NULL,
+                seq INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (kind, account_id, seq)
+            );"
+        ).map_err(to_io)?;
+        Ok(SqliteStore{ connection: Mutex::new(connection) })
+    }
+
+    /// Splits an account into its JSON without the transaction log, and the log
+    fn split_account(account: &impl serde::Serialize) -> Result<(Value, Vec<Value>), std::io::Error>{
+        let mut value = serde_json::to_value(account)?;
+        let transactions = match value.as_object_mut().and_then(|object| object.remove("transactions")){
+            Some(Value::Array(transactions)) => transactions,
+            _ => Vec::new(),
+        };
+        Ok((value, transactions))
+    }
+}
+
+#[async_trait]
+impl StateStore for SqliteStore{
+    async fn save(&self, bank: &Bank) -> Result<(), std::io::Error>{
+        let mut accounts: Vec<(&str, u32, Value, Vec<Value>)> = Vec::new();
+        for (id, account) in bank.get_checking_accounts(){
+            let (value, transactions) = Self::split_account(account)?;
+            accounts.push((CHECKING, *id, value, transactions));
+        }
+        for (id, account) in bank.get_investment_accounts(){
+            let (value, transactions) = Self::split_account(account)?;
+            accounts.push((INVESTMENT, *id, value, transactions));
+        }
+
+        let mut connection = self.connection.lock().unwrap();
+        let transaction = connection.transaction().map_err(to_io)?;
+        // accounts that were closed since the last save
+        let stored: Vec<(String, u32)> = {
+            let mut statement = transaction.prepare("SELECT kind, id FROM accounts").map_err(to_io)?;
+            let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).map_err(to_io)?;
+            rows.collect::<Result<_, _>>().map_err(to_io)?
+        };
+        for (kind, id) in stored{
+            if !accounts.iter().any(|(k, i, _, _)| *k == kind && *i == id){
+                transaction.execute("DELETE FROM accounts WHERE kind = ?1 AND id = ?2", params![kind, id]).map_err(to_io)?;
+                transaction.execute("DELETE FROM transactions WHERE kind = ?1 AND account_id = ?2", params![kind, id]).map_err(to_io)?;
+            }
+        }
+        for (kind, id, value, transactions) in accounts{
+            transaction.execute(
+                "INSERT INTO accounts (kind, id, data) VALUES (?1, ?2, ?3)
+                ON CONFLICT (kind, id) DO UPDATE SET data = excluded.data",
+                params![kind, id, value.to_string()],
+            ).map_err(to_io)?;
+            let saved: HashMap<usize, String> = {
+                let mut statement = transaction.prepare("SELECT seq, data FROM transactions WHERE kind = ?1 AND account_id = ?2").map_err(to_io)?;
+                let rows = statement.query_map(params![kind, id], |row| Ok((row.get(0)?, row.get(1)?))).map_err(to_io)?;
+                rows.collect::<Result<_, _>>().map_err(to_io)?
+            };
+            transaction.execute(
+                "DELETE FROM transactions WHERE kind = ?1 AND account_id = ?2 AND seq >= ?3",
+                params![kind, id, transactions.len()],
+            ).map_err(to_io)?;
+            for (seq, entry) in transactions.iter().enumerate(){
+                let data = entry.to_string();
+                if saved.get(&seq) == Some(&data){
+                    continue;
+                }
+                transaction.execute(
+                    "INSERT INTO transactions (kind, account_id, seq, data) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (kind, account_id, seq) DO UPDATE SET data = excluded.data",
+                    params![kind, id, seq, data],
+                ).map_err(to_io)?;
+            }
+        }
+        // marks the database as saved to, so that an empty bank loads as saved
+        transaction.pragma_update(None, "user_version", 1).map_err(to_io)?;
+        transaction.commit().map_err(to_io)
+    }
+
+    async fn load(&self) -> Result<Bank, std::io::Error>{
+        let connection = self.connection.lock().unwrap();
+        let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(to_io)?;
+        if version == 0{
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "nothing has been saved to the database"));
+        }
+
+        let mut transactions: HashMap<(String, u32), Vec<Value>> = HashMap::new();
+        let mut statement = connection.prepare("SELECT kind, account_id, data FROM transactions ORDER BY seq").map_err(to_io)?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?, row.get::<_, String>(2)?))).map_err(to_io)?;
+        for row in rows{
+            let (kind, id, data) = row.map_err(to_io)?;
+            transactions.entry((kind, id)).or_default().push(serde_json::from_str(&data)?);
+        }
+
+        let mut statement = connection.prepare("SELECT kind, id, data FROM accounts").map_err(to_io)?;
+        let rows = statement.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?, row.get::<_, String>(2)?))).map_err(to_io)?;
+        let mut checking_accounts = HashMap::new();
+        let mut investment_accounts = HashMap::new();
+        for row in rows{
+            let (kind, id, data) = row.map_err(to_io)?;
+            let mut value: Value = serde_json::from_str(&data)?;
+            let log = transactions.remove(&(kind.clone(), id)).unwrap_or_default();
+            if let Some(object) = value.as_object_mut(){
+                object.insert(String::from("transactions"), Value::Array(log));
+            }
+            if kind == CHECKING{
+                checking_accounts.insert(id, serde_json::from_value::<CheckingAccount>(value)?);
+            }else{
+                investment_accounts.insert(id, serde_json::from_value::<InvestmentAccount>(value)?);
+            }
+        }
+        let mut bank = Bank::new(checking_accounts);
+        *bank.get_investment_accounts_mut() = investment_accounts;
+        Ok(bank)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use rust_decimal_macros::dec;
+
+    use crate::bank::accounts::{Account, AccountType};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sqlite_store_saves_incrementally(){
+        let store = SqliteStore::open_in_memory().unwrap();
+        assert_eq!(store.load().await.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+        let mut bank = Bank::empty();
+        let checking = bank.open_account(Some("Nickname".to_string()), AccountType::Checking).unwrap();
+        let investment = bank.open_account(None, AccountType::Investment).unwrap();
+        bank.get_checking_account_mut(checking).unwrap().deposit(dec!(10.0)).unwrap();
+        let account = bank.get_investment_account_mut(investment).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.purchase_investment("AAPL".to_string(), dec!(10.0), 2.0).unwrap();
+        store.save(&bank).await.unwrap();
+
+        bank.get_investment_account_mut(investment).unwrap().sell_investment("AAPL".to_string(), dec!(12.0), 1.0).unwrap();
+        store.save(&bank).await.unwrap();
+        let rows: usize = store.connection.lock().unwrap()
+            .query_row("SELECT COUNT(*) FROM transactions", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 4);
+
+        let loaded = store.load().await.unwrap();
+        assert_eq!(loaded.get_checking_account(checking).unwrap().to_string(), bank.get_checking_account(checking).unwrap().to_string());
+        assert_eq!(loaded.get_investment_account(investment).unwrap().to_string(), bank.get_investment_account(investment).unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_saves_edited_transactions(){
+        let store = SqliteStore::open_in_memory().unwrap();
+        let mut bank = Bank::empty();
+        let id = bank.open_account(None, AccountType::Investment).unwrap();
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.deposit(dec!(100.0)).unwrap();
+        account.deposit(dec!(50.0)).unwrap();
+        store.save(&bank).await.unwrap();
+
+        // an edit to a saved transaction
+        bank.get_investment_account_mut(id).unwrap().transactions[0].add_tag("edited");
+        store.save(&bank).await.unwrap();
+        let loaded = store.load().await.unwrap();
+        assert_eq!(loaded.get_investment_account(id).unwrap().transactions[0].tags, vec!["edited".to_string()]);
+
+        // a log replaced by a different one of the same length
+        let account = bank.get_investment_account_mut(id).unwrap();
+        account.transactions.clear();
+        account.deposit(dec!(1.0)).unwrap();
+        account.deposit(dec!(2.0)).unwrap();
+        store.save(&bank).await.unwrap();
+        let loaded = store.load().await.unwrap();
+        let amounts: Vec<_> = loaded.get_investment_account(id).unwrap().transactions.iter().map(|transaction| transaction.amount).collect();
+        assert_eq!(amounts, vec![dec!(1.0), dec!(2.0)]);
+
+        // and by a shorter one
+        bank.get_investment_account_mut(id).unwrap().transactions.truncate(1);
+        store.save(&bank).await.unwrap();
+        assert_eq!(store.load().await.unwrap().get_investment_account(id).unwrap().transactions.len(), 1);
+    }
+}